@@ -0,0 +1,47 @@
+//! 测试用的一块内存，空闲区域的节点就放在里面。
+
+use crate::node::{ListNode, FREE, NODE_MAGIC};
+use core::{mem, ptr};
+use std::{boxed::Box, vec};
+
+/// 按 `ListNode` 对齐的 `size` 字节内存，所有节点指针都从同一个基址派生。
+pub(crate) struct Arena {
+    base: *mut usize,
+    words: usize,
+}
+
+impl Arena {
+    pub(crate) fn new(size: usize) -> Self {
+        let words = size / mem::size_of::<usize>();
+        let base = Box::into_raw(vec![0usize; words].into_boxed_slice()) as *mut usize;
+        Arena { base, words }
+    }
+
+    /// 在偏移 `offset` 处写入一个大小为 `size` 的空闲区域的节点，它还不在任何索引中。
+    pub(crate) fn node(&self, offset: usize, size: usize) -> *mut ListNode {
+        assert!(offset.is_multiple_of(mem::align_of::<ListNode>()));
+        assert!(offset + size <= self.words * mem::size_of::<usize>());
+        let node = unsafe { self.base.cast::<u8>().add(offset).cast::<ListNode>() };
+        unsafe {
+            node.write(ListNode {
+                tag: size | FREE,
+                next: ptr::null_mut(),
+                prev: ptr::null_mut(),
+                magic: NODE_MAGIC ^ size,
+            })
+        };
+        node
+    }
+
+    /// `node` 在这块内存中的偏移。
+    pub(crate) fn offset(&self, node: *mut ListNode) -> usize {
+        node as usize - self.base as usize
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        let words = ptr::slice_from_raw_parts_mut(self.base, self.words);
+        drop(unsafe { Box::from_raw(words) });
+    }
+}
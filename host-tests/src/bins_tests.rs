@@ -0,0 +1,158 @@
+use crate::arena::Arena;
+use crate::bins::{self, bin_of, Nodes, BIN_COUNT};
+use crate::ListNode;
+use core::ptr;
+use std::{vec, vec::Vec};
+
+type Bins = [*mut ListNode; BIN_COUNT];
+
+/// 按遍历顺序返回所有节点的偏移，并检查每个节点的 `prev` 都指向前一个节点。
+fn offsets(arena: &Arena, bins: &Bins) -> Vec<usize> {
+    for &head in bins.iter() {
+        let mut prev = ptr::null_mut();
+        let mut node = head;
+        while !node.is_null() {
+            assert_eq!(unsafe { (*node).prev }, prev);
+            prev = node;
+            node = unsafe { (*node).next };
+        }
+    }
+    Nodes {
+        node: ptr::null_mut(),
+        bins,
+    }
+    .map(|node| arena.offset(node))
+    .collect()
+}
+
+#[test]
+fn bin_of_splits_at_the_limits() {
+    assert_eq!(bin_of(32), 0);
+    assert_eq!(bin_of(63), 0);
+    assert_eq!(bin_of(64), 1);
+    assert_eq!(bin_of(255), 1);
+    assert_eq!(bin_of(256), 2);
+    assert_eq!(bin_of(1023), 2);
+    assert_eq!(bin_of(1024), 3);
+    assert_eq!(bin_of(usize::MAX), BIN_COUNT - 1);
+}
+
+#[test]
+fn insert_pushes_to_the_head_of_its_bin() {
+    let arena = Arena::new(4096);
+    let mut bins = [ptr::null_mut(); BIN_COUNT];
+    unsafe {
+        bins::insert(&mut bins, arena.node(0, 32), ptr::null_mut());
+        bins::insert(&mut bins, arena.node(64, 512), ptr::null_mut());
+        bins::insert(&mut bins, arena.node(1024, 32), ptr::null_mut());
+        bins::insert(&mut bins, arena.node(2048, 128), ptr::null_mut());
+    }
+    assert_eq!(offsets(&arena, &bins), vec![1024, 0, 2048, 64]);
+}
+
+#[test]
+fn insert_after_a_node_of_the_same_bin() {
+    let arena = Arena::new(4096);
+    let mut bins = [ptr::null_mut(); BIN_COUNT];
+    let (a, b, c) = (arena.node(0, 32), arena.node(64, 32), arena.node(128, 48));
+    unsafe {
+        bins::insert(&mut bins, a, ptr::null_mut());
+        bins::insert(&mut bins, b, ptr::null_mut());
+        bins::insert(&mut bins, c, b);
+    }
+    assert_eq!(offsets(&arena, &bins), vec![64, 128, 0]);
+    // 中间插入之后，后一个节点的 prev 也要指向新节点
+    assert_eq!(unsafe { (*a).prev }, c);
+}
+
+#[test]
+fn insert_after_a_node_of_another_bin_goes_to_the_head() {
+    let arena = Arena::new(4096);
+    let mut bins = [ptr::null_mut(); BIN_COUNT];
+    let (a, b, c) = (arena.node(0, 32), arena.node(64, 512), arena.node(1024, 40));
+    unsafe {
+        bins::insert(&mut bins, a, ptr::null_mut());
+        bins::insert(&mut bins, b, ptr::null_mut());
+        bins::insert(&mut bins, c, b);
+    }
+    assert_eq!(offsets(&arena, &bins), vec![1024, 0, 64]);
+}
+
+#[test]
+fn remove_returns_the_successor() {
+    let arena = Arena::new(4096);
+    let mut bins = [ptr::null_mut(); BIN_COUNT];
+    let nodes: Vec<_> = (0..5).map(|i| arena.node(i * 64, 32)).collect();
+    for &node in nodes.iter() {
+        unsafe { bins::insert(&mut bins, node, ptr::null_mut()) };
+    }
+    assert_eq!(offsets(&arena, &bins), vec![256, 192, 128, 64, 0]);
+    // 中间、表头、表尾
+    assert_eq!(unsafe { bins::remove(&mut bins, nodes[2]) }, nodes[1]);
+    assert_eq!(offsets(&arena, &bins), vec![256, 192, 64, 0]);
+    assert_eq!(unsafe { bins::remove(&mut bins, nodes[4]) }, nodes[3]);
+    assert_eq!(offsets(&arena, &bins), vec![192, 64, 0]);
+    assert_eq!(
+        unsafe { bins::remove(&mut bins, nodes[0]) },
+        ptr::null_mut()
+    );
+    assert_eq!(offsets(&arena, &bins), vec![192, 64]);
+    unsafe {
+        bins::remove(&mut bins, nodes[1]);
+        bins::remove(&mut bins, nodes[3]);
+    }
+    assert!(bins.iter().all(|head| head.is_null()));
+}
+
+/// 遍历得到的指针可以直接用来摘除和改写节点，其他节点的指针仍然有效：
+/// 原来通过 `&'static mut` 引用查找区域时，这样做会让别名的引用失效。
+#[test]
+fn nodes_found_while_walking_can_be_rewritten() {
+    let arena = Arena::new(8192);
+    let mut bins = [ptr::null_mut(); BIN_COUNT];
+    for i in 0..8 {
+        unsafe { bins::insert(&mut bins, arena.node(i * 512, 48 + i * 8), ptr::null_mut()) };
+    }
+    let found: Vec<_> = Nodes {
+        node: ptr::null_mut(),
+        bins: &bins,
+    }
+    .collect();
+    for (i, &node) in found.iter().enumerate() {
+        if i % 2 == 0 {
+            continue;
+        }
+        // 摘下来，改写成更大的区域，再插入到别的分箱
+        unsafe {
+            bins::remove(&mut bins, node);
+            let size = (*node).size() + 400;
+            (*node).tag = size | crate::node::FREE;
+            (*node).magic = crate::node::NODE_MAGIC ^ size;
+            bins::insert(&mut bins, node, found[0]);
+        }
+    }
+    for &node in found.iter() {
+        unsafe { (*node).verify() };
+    }
+    let sizes: Vec<usize> = Nodes {
+        node: ptr::null_mut(),
+        bins: &bins,
+    }
+    .map(|node| unsafe { (*node).size() })
+    .collect();
+    assert_eq!(sizes, vec![56, 104, 88, 72, 464, 480, 496, 448]);
+}
+
+#[cfg(feature = "heap-verify")]
+#[test]
+#[should_panic(expected = "heap corrupted")]
+fn walking_over_a_corrupted_node_panics() {
+    let arena = Arena::new(4096);
+    let mut bins = [ptr::null_mut(); BIN_COUNT];
+    let node = arena.node(0, 32);
+    unsafe {
+        bins::insert(&mut bins, node, ptr::null_mut());
+        (*node).magic = 0;
+    }
+    offsets(&arena, &bins);
+}
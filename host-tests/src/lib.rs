@@ -0,0 +1,32 @@
+//! 在宿主机上编译 `LinkedListAllocator` 的空闲区域索引，用 Miri 检查其中的原始指针操作。
+//!
+//! 这些模块直接取自内核的源码树。内核的 `.cargo/config.toml` 会为内核的目标构建 `core`，
+//! 所以要在仓库之外的目录运行，例如在仓库的上一级目录中：
+//!
+//! ```text
+//! cargo +nightly miri test --manifest-path <仓库>/host-tests/Cargo.toml
+//! cargo +nightly miri test --manifest-path <仓库>/host-tests/Cargo.toml --features heap-verify
+//! ```
+
+#![no_std]
+// 内核用到的一些函数这里的测试用不到
+#![allow(dead_code)]
+
+#[cfg(test)]
+extern crate std;
+
+#[path = "../../src/allocator/linked_list/bins.rs"]
+mod bins;
+#[path = "../../src/allocator/linked_list/node.rs"]
+mod node;
+#[path = "../../src/allocator/linked_list/tree.rs"]
+mod tree;
+
+use node::ListNode;
+
+#[cfg(test)]
+mod arena;
+#[cfg(test)]
+mod bins_tests;
+#[cfg(test)]
+mod tree_tests;
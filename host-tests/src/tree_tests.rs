@@ -0,0 +1,100 @@
+use crate::arena::Arena;
+use crate::tree;
+use crate::ListNode;
+use core::ptr;
+use std::vec::Vec;
+
+/// 按 (大小, 地址) 顺序返回树中所有节点的 (大小, 偏移)。
+fn walk(arena: &Arena, root: *mut ListNode) -> Vec<(usize, usize)> {
+    let mut out = Vec::new();
+    let mut node = unsafe { tree::first_at_least(root, (0, 0)) };
+    while !node.is_null() {
+        out.push((unsafe { (*node).size() }, arena.offset(node)));
+        node = unsafe { tree::successor(root, &*node) };
+    }
+    out
+}
+
+/// 64 个节点，大小在几个值之间循环，相同大小的节点按地址排序。
+fn build(arena: &Arena, root: &mut *mut ListNode) -> Vec<*mut ListNode> {
+    let nodes: Vec<_> = (0..64)
+        .map(|i| arena.node(i * 256, 32 + (i * 7 % 5) * 48))
+        .collect();
+    for &node in nodes.iter() {
+        unsafe { tree::insert(root, node) };
+    }
+    nodes
+}
+
+fn sorted(arena: &Arena, nodes: &[*mut ListNode]) -> Vec<(usize, usize)> {
+    let mut expected: Vec<_> = nodes
+        .iter()
+        .map(|&node| (unsafe { (*node).size() }, arena.offset(node)))
+        .collect();
+    expected.sort();
+    expected
+}
+
+#[test]
+fn walk_is_ordered_by_size_then_address() {
+    let arena = Arena::new(64 * 256);
+    let mut root = ptr::null_mut();
+    let nodes = build(&arena, &mut root);
+    assert_eq!(walk(&arena, root), sorted(&arena, &nodes));
+}
+
+#[test]
+fn first_at_least_finds_the_smallest_fitting_node() {
+    let arena = Arena::new(64 * 256);
+    let mut root = ptr::null_mut();
+    build(&arena, &mut root);
+    let first = |lower| {
+        let node = unsafe { tree::first_at_least(root, lower) };
+        (!node.is_null()).then(|| (unsafe { (*node).size() }, arena.offset(node)))
+    };
+    assert_eq!(first((0, 0)), Some((32, 0)));
+    assert_eq!(first((33, 0)), Some((80, 256 * 3)));
+    // 相同大小的下一个节点
+    let node = unsafe { tree::first_at_least(root, (33, 0)) };
+    assert_eq!(
+        arena.offset(unsafe { tree::successor(root, &*node) }),
+        256 * 8
+    );
+    assert_eq!(first((224, 0)), Some((224, 256 * 2)));
+    assert_eq!(first((225, 0)), None);
+}
+
+#[test]
+fn remove_any_node_keeps_the_order() {
+    let arena = Arena::new(64 * 256);
+    let mut root = ptr::null_mut();
+    let mut nodes = build(&arena, &mut root);
+    let mut i = 0;
+    while !nodes.is_empty() {
+        i = (i + 17) % nodes.len();
+        let node = nodes.swap_remove(i);
+        unsafe { tree::remove(&mut root, node) };
+        assert_eq!(walk(&arena, root), sorted(&arena, &nodes));
+    }
+    assert!(root.is_null());
+}
+
+#[test]
+fn removed_nodes_can_be_resized_and_reinserted() {
+    let arena = Arena::new(64 * 256);
+    let mut root = ptr::null_mut();
+    let nodes = build(&arena, &mut root);
+    for &node in nodes.iter().step_by(3) {
+        unsafe {
+            tree::remove(&mut root, node);
+            let size = (*node).size() + 16;
+            (*node).tag = size | crate::node::FREE;
+            (*node).magic = crate::node::NODE_MAGIC ^ size;
+            tree::insert(&mut root, node);
+        }
+    }
+    assert_eq!(walk(&arena, root), sorted(&arena, &nodes));
+    for &node in nodes.iter() {
+        unsafe { (*node).verify() };
+    }
+}
@@ -2,13 +2,20 @@
 
 use alloc::alloc::{GlobalAlloc, Layout};
 use bump::BumpAllocator;
-use fixed_size_block::FixedSizeBlockAllocator;
-use core::ptr::null_mut;
-use linked_list::LinkedListAllocator;
-use linked_list_allocator::LockedHeap;
+use fixed_size_block::{BlockStats, FixedSizeBlockAllocator};
+use core::{
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    ptr::{self, null_mut, NonNull},
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+};
+use linked_list::{HeapStats, LinkedListAllocator};
 use x86_64::{
+    instructions::interrupts,
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::{MapToError, UnmapError},
+        FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB,
     },
     VirtAddr,
 };
@@ -24,21 +31,710 @@ pub mod linked_list;
 /// 一个围绕 spin::Mutex 的包装器，以允许特性实现。
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
+    /// 持有锁时是否关闭中断。
+    irq_safe: bool,
+    /// 持有锁的 CPU 的编号，没有被持有时为 [`NO_OWNER`]，由守卫设置和清除。
+    owner: AtomicUsize,
 }
 impl<A> Locked<A> {
     pub const fn new(inner: A) -> Self {
         Locked {
             inner: spin::Mutex::new(inner),
+            irq_safe: false,
+            owner: AtomicUsize::new(NO_OWNER),
+        }
+    }
+
+    /// 创建一个持有锁时关闭中断的包装器，用于中断处理程序也会使用的分配器。
+    ///
+    /// 否则中断处理程序分配内存时，如果被打断的代码正持有这把锁，CPU 会一直在这把锁上自旋。
+    pub const fn new_irq_safe(inner: A) -> Self {
+        Locked {
+            inner: spin::Mutex::new(inner),
+            irq_safe: true,
+            owner: AtomicUsize::new(NO_OWNER),
+        }
+    }
+
+    /// 上锁。自旋 [`LOCK_SPIN_LIMIT`] 次仍然拿不到锁，而锁已经被这个 CPU 持有时 panic，
+    /// 而不是永远自旋下去：这通常是中断处理程序在被打断的代码持有堆的锁时分配了内存。锁被别的
+    /// CPU 持有时一直等下去，CPU 的编号由 [`set_current_cpu`] 设置的函数给出。
+    pub fn lock(&self) -> LockedGuard<'_, A> {
+        let mut spins = 0;
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            spins += 1;
+            if spins >= LOCK_SPIN_LIMIT && self.owner.load(Ordering::Acquire) == current_cpu() {
+                panic!(
+                    "reentrant heap allocation from interrupt context: \
+                     the allocator lock is already held by this CPU"
+                );
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// 尝试上锁，锁已经被持有时返回 `None`。
+    pub fn try_lock(&self) -> Option<LockedGuard<'_, A>> {
+        // 先关中断再上锁，这样持有锁时不会有中断处理程序来抢这把锁
+        let reenable = self.irq_safe && interrupts::are_enabled();
+        if reenable {
+            interrupts::disable();
+        }
+        match self.inner.try_lock() {
+            Some(guard) => {
+                self.owner.store(current_cpu(), Ordering::Release);
+                Some(LockedGuard {
+                    guard: ManuallyDrop::new(guard),
+                    owner: &self.owner,
+                    reenable,
+                })
+            }
+            None => {
+                if reenable {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+}
+
+/// [`Locked::lock`] 拿不到锁时最多自旋的次数，之后检查是不是重入。
+pub const LOCK_SPIN_LIMIT: usize = 1 << 20;
+
+/// [`Locked`] 没有被持有时记录的持有者。
+const NO_OWNER: usize = usize::MAX;
+
+/// 返回当前 CPU 编号的函数的地址，0 表示没有设置，这时认为只有一个 CPU，编号为 0。
+static CURRENT_CPU: AtomicUsize = AtomicUsize::new(0);
+
+/// 设置返回当前 CPU 编号的函数，[`Locked`] 用它记录锁的持有者。
+///
+/// 多个 CPU 共用一把锁时要在启动其他 CPU 之前设置，否则别的 CPU 长时间持有锁会被当成重入，
+/// 引发 panic。
+pub fn set_current_cpu(current_cpu: fn() -> usize) {
+    CURRENT_CPU.store(current_cpu as usize, Ordering::Release);
+}
+
+fn current_cpu() -> usize {
+    let current_cpu = CURRENT_CPU.load(Ordering::Acquire);
+    if current_cpu == 0 {
+        return 0;
+    }
+    // CURRENT_CPU 中只会存入 fn() -> usize 的地址
+    let current_cpu: fn() -> usize = unsafe { core::mem::transmute(current_cpu) };
+    current_cpu()
+}
+
+/// [`Locked::lock`] 返回的守卫；中断安全的包装器在它被丢弃时恢复上锁之前的中断状态。
+pub struct LockedGuard<'a, A> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, A>>,
+    owner: &'a AtomicUsize,
+    /// 上锁之前中断是开着的，放开锁之后重新打开。
+    reenable: bool,
+}
+
+impl<A> Deref for LockedGuard<'_, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.guard
+    }
+}
+
+impl<A> DerefMut for LockedGuard<'_, A> {
+    fn deref_mut(&mut self) -> &mut A {
+        &mut self.guard
+    }
+}
+
+impl<A> Drop for LockedGuard<'_, A> {
+    fn drop(&mut self) {
+        // 先放开锁再开中断
+        self.owner.store(NO_OWNER, Ordering::Release);
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.reenable {
+            interrupts::enable();
+        }
+    }
+}
+
+/// 主堆使用的分配器，在 [`init_heap_with`] 时选择。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AllocatorKind {
+    /// [`BumpAllocator`]，全部释放以后才重新使用内存。
+    Bump,
+    /// [`LinkedListAllocator`]。
+    LinkedList,
+    /// [`FixedSizeBlockAllocator`]，`init_heap` 使用这一个。
+    #[default]
+    FixedSizeBlock,
+}
+
+/// 三种分配器之一，运行时选择，换一个分配器不必重新编译内核。
+///
+/// `Locked<KernelAllocator>` 在锁内调用所选分配器自己的实现，分配失败时和
+/// `Locked<FixedSizeBlockAllocator>` 一样先请回收函数释放内存再试一次。`heap-latency` 的耗时
+/// 统计只在直接使用 `Locked<LinkedListAllocator>` 时记录。
+// 只放在一个静态变量里，不同变体的大小差别无关紧要
+#[allow(clippy::large_enum_variant)]
+pub enum KernelAllocator {
+    Bump(BumpAllocator),
+    LinkedList(LinkedListAllocator),
+    FixedSizeBlock(FixedSizeBlockAllocator),
+}
+
+/// [`KernelAllocator::stats`] 返回的统计快照，内容取决于所选的分配器。
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelStats {
+    Bump {
+        /// 见 [`BumpAllocator::used`]。
+        used: usize,
+        /// 见 [`BumpAllocator::remaining`]。
+        remaining: usize,
+        /// 尚未释放的分配的个数。
+        allocations: usize,
+    },
+    LinkedList(HeapStats),
+    FixedSizeBlock(BlockStats),
+}
+
+impl KernelAllocator {
+    /// 创建一个 `kind` 类型的空的分配器。
+    pub const fn new(kind: AllocatorKind) -> Self {
+        match kind {
+            AllocatorKind::Bump => KernelAllocator::Bump(BumpAllocator::new()),
+            AllocatorKind::LinkedList => KernelAllocator::LinkedList(LinkedListAllocator::new()),
+            AllocatorKind::FixedSizeBlock => {
+                KernelAllocator::FixedSizeBlock(FixedSizeBlockAllocator::new())
+            }
+        }
+    }
+
+    /// 返回所选的分配器的类型。
+    pub fn kind(&self) -> AllocatorKind {
+        match self {
+            KernelAllocator::Bump(_) => AllocatorKind::Bump,
+            KernelAllocator::LinkedList(_) => AllocatorKind::LinkedList,
+            KernelAllocator::FixedSizeBlock(_) => AllocatorKind::FixedSizeBlock,
+        }
+    }
+
+    /// 使用给定的堆边界初始化所选的分配器。
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须确保给定的内存范围未被使用。此外，这个方法只能被调用一次。
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        match self {
+            KernelAllocator::Bump(allocator) => allocator.init(heap_start, heap_size),
+            KernelAllocator::LinkedList(allocator) => allocator.init(heap_start, heap_size),
+            KernelAllocator::FixedSizeBlock(allocator) => allocator.init(heap_start, heap_size),
+        }
+    }
+
+    /// 把 `start..start + size` 加入堆，返回是否加入。突增分配器只使用 `init` 给出的内存，
+    /// 总是返回 `false`。
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证给定的内存是有效的并且未被使用。
+    pub unsafe fn extend(&mut self, start: usize, size: usize) -> bool {
+        match self {
+            KernelAllocator::Bump(_) => false,
+            KernelAllocator::LinkedList(allocator) => allocator.extend(start, size).is_ok(),
+            KernelAllocator::FixedSizeBlock(allocator) => {
+                allocator.extend(start, size);
+                true
+            }
+        }
+    }
+
+    /// 见 [`FixedSizeBlockAllocator::trim`]；另外两个分配器没有缓存的块，返回 0。
+    pub fn trim(&mut self, target_bytes: usize) -> usize {
+        match self {
+            KernelAllocator::FixedSizeBlock(allocator) => allocator.trim(target_bytes),
+            _ => 0,
+        }
+    }
+
+    /// 返回所选的分配器的统计。只复制计数，不分配内存。
+    pub fn stats(&self) -> KernelStats {
+        match self {
+            KernelAllocator::Bump(allocator) => KernelStats::Bump {
+                used: allocator.used(),
+                remaining: allocator.remaining(),
+                allocations: allocator.allocation_count(),
+            },
+            KernelAllocator::LinkedList(allocator) => KernelStats::LinkedList(allocator.stats()),
+            KernelAllocator::FixedSizeBlock(allocator) => {
+                KernelStats::FixedSizeBlock(allocator.stats())
+            }
+        }
+    }
+
+    /// 把所选的分配器的状态打印到 `writer`：链表分配器的空闲列表，块分配器各个类浪费的字节和
+    /// 大小分布，突增分配器的用量。不分配内存。
+    pub fn dump(&self, writer: &mut impl fmt::Write) -> fmt::Result {
+        match self {
+            KernelAllocator::Bump(allocator) => writeln!(
+                writer,
+                "bump heap: {} bytes used, {} bytes remaining, {} live allocations",
+                allocator.used(),
+                allocator.remaining(),
+                allocator.allocation_count()
+            ),
+            KernelAllocator::LinkedList(allocator) => allocator.dump(writer),
+            KernelAllocator::FixedSizeBlock(allocator) => {
+                writeln!(writer, "fixed-size block heap:")?;
+                allocator.dump_waste(writer)?;
+                allocator.dump_histograms(writer)
+            }
+        }
+    }
+
+    /// `GlobalAlloc::alloc` 在锁内的部分，`layout` 的大小不为零。
+    unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        match self {
+            KernelAllocator::Bump(allocator) => {
+                allocator.alloc(layout).map_or(null_mut(), NonNull::as_ptr)
+            }
+            KernelAllocator::LinkedList(allocator) => allocator
+                .alloc_tagged(layout)
+                .map_or(null_mut(), |start| start as *mut u8),
+            KernelAllocator::FixedSizeBlock(allocator) => allocator.allocate(layout).0,
+        }
+    }
+
+    /// `GlobalAlloc::alloc_zeroed` 在锁内的部分，`layout` 的大小不为零。另外返回开头需要清零的
+    /// 字节数：两个分配器都知道哪些内存从未写过，只有突增分配器需要全部清零。
+    unsafe fn allocate_zeroed(&mut self, layout: Layout) -> (*mut u8, usize) {
+        match self {
+            KernelAllocator::Bump(allocator) => {
+                let ptr = allocator.alloc(layout).map_or(null_mut(), NonNull::as_ptr);
+                (ptr, layout.size())
+            }
+            KernelAllocator::LinkedList(allocator) => match allocator.alloc_zeroed_tagged(layout) {
+                Some((start, dirty_len)) => (start as *mut u8, dirty_len),
+                None => (null_mut(), 0),
+            },
+            KernelAllocator::FixedSizeBlock(allocator) => {
+                let (ptr, pristine) = allocator.allocate(layout);
+                (ptr, fixed_size_block::dirty_len(layout.size(), pristine))
+            }
+        }
+    }
+
+    /// `GlobalAlloc::dealloc` 在锁内的部分，`layout` 的大小不为零。
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        match self {
+            KernelAllocator::Bump(allocator) => {
+                if let Some(ptr) = NonNull::new(ptr) {
+                    allocator.dealloc(ptr, layout);
+                }
+            }
+            KernelAllocator::LinkedList(allocator) => allocator.dealloc_tagged(ptr, layout),
+            KernelAllocator::FixedSizeBlock(allocator) => allocator.deallocate(ptr, layout),
+        }
+    }
+
+    /// 尝试不移动地把 `ptr` 这个分配调整为 `new_layout`，需要移动时返回 `Ok(false)`；
+    /// 链表分配器认出无效的指针时返回 `Err`。
+    unsafe fn resize_in_place(
+        &mut self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_layout: Layout,
+    ) -> Result<bool, ()> {
+        match self {
+            KernelAllocator::Bump(_) => Ok(false),
+            KernelAllocator::LinkedList(allocator) => {
+                allocator.realloc_in_place(ptr, layout, new_layout.size())
+            }
+            KernelAllocator::FixedSizeBlock(allocator) => {
+                Ok(allocator.resize_in_place(&layout, &new_layout))
+            }
+        }
+    }
+}
+
+impl Locked<KernelAllocator> {
+    /// 返回所选的分配器的类型。
+    pub fn kind(&self) -> AllocatorKind {
+        self.lock().kind()
+    }
+
+    /// 见 [`KernelAllocator::extend`]。
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证给定的内存是有效的并且未被使用。
+    pub unsafe fn extend(&self, start: usize, size: usize) -> bool {
+        self.lock().extend(start, size)
+    }
+
+    /// 见 [`KernelAllocator::trim`]。可以作为回收函数注册给 [`register_shrinker`]。
+    pub fn trim(&self, target_bytes: usize) -> usize {
+        self.lock().trim(target_bytes)
+    }
+
+    /// 见 [`KernelAllocator::stats`]。
+    pub fn stats(&self) -> KernelStats {
+        self.lock().stats()
+    }
+
+    /// 检查所有分配都已经释放，否则报告尚未释放的分配并 panic。
+    pub fn assert_balanced(&self) {
+        // 先复制计数再 panic，不在持有锁的时候格式化
+        let (classes, live) = match &*self.lock() {
+            KernelAllocator::Bump(allocator) => (None, allocator.allocation_count()),
+            KernelAllocator::LinkedList(allocator) => (None, allocator.live_allocs()),
+            KernelAllocator::FixedSizeBlock(allocator) => (Some(allocator.live_counts()), 0),
+        };
+        match classes {
+            Some(classes) if !classes.is_balanced() => panic!("unbalanced heap: {}", classes),
+            None if live != 0 => panic!("unbalanced heap: {} live allocations", live),
+            _ => {}
+        }
+    }
+
+    /// 通过串口打印所选的分配器的状态，见 [`KernelAllocator::dump`]。
+    ///
+    /// 如果锁正被持有（例如在分配器内部 panic），只打印一条提示而不是死锁。
+    pub fn dump_to_serial(&self) {
+        use core::fmt::Write;
+
+        let mut serial = crate::serial::SERIAL1.lock();
+        let _ = match self.inner.try_lock() {
+            Some(allocator) => allocator.dump(&mut *serial),
+            None => writeln!(serial, "heap: allocator is locked"),
+        };
+    }
+
+    /// 在锁内调用 `allocate`，分配失败时先请所有注册的回收函数释放内存，再试一次。
+    unsafe fn allocate_or_shrink<T>(
+        &self,
+        layout: Layout,
+        allocate: impl Fn(&mut KernelAllocator) -> (*mut u8, T),
+    ) -> (*mut u8, T) {
+        let allocated = allocate(&mut self.lock());
+        // 回收函数可能会锁上这个分配器，调用之前必须先释放锁
+        if !allocated.0.is_null() || run_shrinkers(layout.size() + layout.align()) == 0 {
+            return allocated;
+        }
+        allocate(&mut self.lock())
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<KernelAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return dangling(&layout);
+        }
+        self.allocate_or_shrink(layout, |allocator| (allocator.allocate(layout), ()))
+            .0
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return dangling(&layout);
+        }
+        let (ptr, dirty_len) =
+            self.allocate_or_shrink(layout, |allocator| allocator.allocate_zeroed(layout));
+        // 在锁外清零
+        if !ptr.is_null() {
+            ptr.write_bytes(0, dirty_len);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        self.lock().deallocate(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if layout.size() == 0 {
+            return self.alloc(new_layout);
         }
+        match self.lock().resize_in_place(ptr, layout, new_layout) {
+            Ok(true) => return ptr,
+            Ok(false) => {}
+            Err(()) => return null_mut(),
+        }
+
+        // 无法原地调整 -> 分配新的内存，复制数据后释放旧的分配
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+            if let KernelAllocator::LinkedList(allocator) = &mut *self.lock() {
+                allocator.count_moved_realloc();
+            }
+        }
+        new_ptr
+    }
+}
+
+static ALLOCATOR: Locked<KernelAllocator> =
+    Locked::new_irq_safe(KernelAllocator::new(AllocatorKind::FixedSizeBlock));
+
+/// 引导堆的大小。
+pub const BOOTSTRAP_HEAP_SIZE: usize = 64 * 1024;
+
+/// 引导堆的内存在内核镜像中，不需要分页就可以使用。
+#[repr(align(4096))]
+struct BootstrapMemory([u8; BOOTSTRAP_HEAP_SIZE]);
+static mut BOOTSTRAP_MEMORY: BootstrapMemory = BootstrapMemory([0; BOOTSTRAP_HEAP_SIZE]);
+
+/// `init_heap` 之前使用的引导堆，第一次分配时初始化。
+static BOOTSTRAP: Locked<BumpAllocator> = Locked::new_irq_safe(BumpAllocator::new());
+/// 引导堆是否已经初始化，只在持有 `BOOTSTRAP` 的锁时修改。
+static BOOTSTRAP_READY: AtomicBool = AtomicBool::new(false);
+
+/// 全局分配器所处的阶段，见 [`heap_stage`]。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum HeapStage {
+    /// `init_heap` 之前，从引导堆分配。
+    Bootstrap,
+    /// 从主堆分配；引导堆上还有分配没有释放，或者主堆不能加入别的内存。引导堆上的块的释放
+    /// 仍然交给引导堆，引导堆不再使用。
+    Retired,
+    /// 从主堆分配；切换时引导堆是空的，整块加入了主堆。
+    Donated,
+}
+
+/// 当前的 [`HeapStage`]，只在持有 `BOOTSTRAP` 的锁时修改。
+static STAGE: AtomicU8 = AtomicU8::new(HeapStage::Bootstrap as u8);
+
+/// 返回全局分配器所处的阶段。
+pub fn heap_stage() -> HeapStage {
+    match STAGE.load(Ordering::Acquire) {
+        0 => HeapStage::Bootstrap,
+        1 => HeapStage::Retired,
+        _ => HeapStage::Donated,
+    }
+}
+
+fn bootstrap_start() -> usize {
+    unsafe { ptr::addr_of_mut!(BOOTSTRAP_MEMORY.0) as usize }
+}
+
+/// `ptr` 是否是引导堆上的分配：指向引导堆的内存，并且引导堆没有加入主堆。
+pub fn is_bootstrap_allocation(ptr: *const u8) -> bool {
+    let start = bootstrap_start();
+    (start..start + BOOTSTRAP_HEAP_SIZE).contains(&(ptr as usize))
+        && heap_stage() != HeapStage::Donated
+}
+
+/// 在引导阶段从引导堆分配，已经切换到主堆时返回 `None`。
+fn bootstrap_alloc(layout: Layout) -> Option<*mut u8> {
+    if heap_stage() != HeapStage::Bootstrap {
+        return None;
+    }
+    let mut bootstrap = BOOTSTRAP.lock();
+    // 切换时持有这把锁，锁上之后再看一次，这样切换之后不会再从引导堆分配
+    if heap_stage() != HeapStage::Bootstrap {
+        return None;
     }
+    if !BOOTSTRAP_READY.load(Ordering::Relaxed) {
+        // 引导堆的内存只归 BOOTSTRAP 使用
+        unsafe { bootstrap.init(bootstrap_start(), BOOTSTRAP_HEAP_SIZE) };
+        BOOTSTRAP_READY.store(true, Ordering::Relaxed);
+    }
+    Some(bootstrap.alloc(layout).map_or(null_mut(), NonNull::as_ptr))
+}
 
-    pub fn lock(&self) -> spin::MutexGuard<A> {
-        self.inner.lock()
+/// 让之后的分配都交给主堆。引导堆是空的时候把它加入主堆，否则它上面的分配永远留在那里。
+fn switch_to_main_heap() {
+    let bootstrap = BOOTSTRAP.lock();
+    STAGE.store(HeapStage::Retired as u8, Ordering::Release);
+    // `external-fallback` 的后备堆只能在结尾扩展，引导堆不在那里
+    if bootstrap.allocation_count() == 0
+        && cfg!(not(feature = "external-fallback"))
+        // 引导堆没有分配，之后也不会再从它分配
+        && unsafe { ALLOCATOR.extend(bootstrap_start(), BOOTSTRAP_HEAP_SIZE) }
+    {
+        STAGE.store(HeapStage::Donated as u8, Ordering::Release);
     }
 }
+
+/// 内核的全局分配器：`init_heap` 之前从引导堆分配，之后交给 `ALLOCATOR`。
+///
+/// 引导堆上的块在切换之后仍然可以释放和调整大小，调整大小时移到主堆上。
+struct KernelHeap;
+
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(
-    FixedSizeBlockAllocator::new());
+static KERNEL_HEAP: KernelHeap = KernelHeap;
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        bootstrap_alloc(layout).unwrap_or_else(|| ALLOCATOR.alloc(layout))
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        match bootstrap_alloc(layout) {
+            // 引导堆全部释放后会从头重新分配，内存不一定是零
+            Some(ptr) => {
+                if !ptr.is_null() {
+                    ptr::write_bytes(ptr, 0, layout.size());
+                }
+                ptr
+            }
+            None => ALLOCATOR.alloc_zeroed(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if is_bootstrap_allocation(ptr) {
+            BOOTSTRAP.dealloc(ptr, layout);
+        } else {
+            ALLOCATOR.dealloc(ptr, layout);
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if heap_stage() != HeapStage::Bootstrap && !is_bootstrap_allocation(ptr) {
+            return ALLOCATOR.realloc(ptr, layout, new_size);
+        }
+        // 引导堆上的块，或者还在引导阶段 -> 重新分配再复制，切换之后新的块在主堆上
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+/// 最多可以注册的回收函数个数。
+pub const MAX_SHRINKERS: usize = 4;
+
+/// 回收函数：尽量释放至少给定字节数的缓存内存，返回实际释放的字节数。
+pub type Shrinker = fn(usize) -> usize;
+
+static SHRINKERS: spin::Mutex<[Option<Shrinker>; MAX_SHRINKERS]> =
+    spin::Mutex::new([None; MAX_SHRINKERS]);
+
+/// 注册一个在分配失败时调用的回收函数，已经注册满时返回 `false`。
+///
+/// 回收函数在分配器的锁之外调用，但它自己不能分配内存。
+pub fn register_shrinker(shrinker: Shrinker) -> bool {
+    let mut shrinkers = SHRINKERS.lock();
+    match shrinkers.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some(shrinker);
+            true
+        }
+        None => false,
+    }
+}
+
+/// 依次调用注册的回收函数，直到释放了至少 `target` 字节，返回一共释放的字节数。
+pub fn run_shrinkers(target: usize) -> usize {
+    // 复制一份再调用，回收函数不会在持有 SHRINKERS 的锁时运行
+    let shrinkers = *SHRINKERS.lock();
+    let mut released = 0;
+    for shrinker in shrinkers.iter().flatten() {
+        if released >= target {
+            break;
+        }
+        released += shrinker(target - released);
+    }
+    released
+}
+
+/// 让全局堆把最多约 `target_bytes` 字节缓存的块还给后备分配器，返回实际释放的字节数。
+///
+/// 供帧分配器在空闲帧低于水位时调用；不能在持有全局堆的锁时调用。
+pub fn trim_heap(target_bytes: usize) -> usize {
+    ALLOCATOR.trim(target_bytes)
+}
+
+/// 全局堆当前的结尾，`extend_heap` 从这里继续映射页面。
+static HEAP_END: spin::Mutex<usize> = spin::Mutex::new(HEAP_START + HEAP_SIZE);
+
+/// [`extend_heap`] 失败的原因。
+#[derive(Debug)]
+pub enum ExtendHeapError {
+    /// 映射页面失败。
+    Map(MapToError<Size4KiB>),
+    /// 分配器不接受新映射的 `start..start + size`，例如链表分配器已经记录了太多段堆范围。
+    ///
+    /// 这些页面仍然映射着，可以用 [`unmap_heap_pages`] 取消映射并交还帧；堆的结尾不变。
+    Rejected { start: usize, size: usize },
+}
+
+impl From<MapToError<Size4KiB>> for ExtendHeapError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        ExtendHeapError::Map(err)
+    }
+}
+
+/// 在全局堆的结尾再映射至少 `size` 字节（向上取整到页）并加入堆，例如在解析内存映射之后。
+///
+/// 必须在 `init_heap` 之后调用。使用突增分配器，或者启用 `external-fallback` 和 `heap-debug` 时
+/// 堆不能扩展，会 panic。
+pub fn extend_heap(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    size: usize,
+) -> Result<(), ExtendHeapError> {
+    assert!(
+        heap_allocator_kind() != AllocatorKind::Bump,
+        "the bump allocator cannot be extended"
+    );
+    let mut heap_end = HEAP_END.lock();
+    let start = *heap_end;
+    let size = align_up(size, Size4KiB::SIZE as usize);
+    if size == 0 {
+        return Ok(());
+    }
+    let first_page = Page::<Size4KiB>::containing_address(VirtAddr::new(start as u64));
+    let last_page = Page::containing_address(VirtAddr::new((start + size - 1) as u64));
+    for page in Page::range_inclusive(first_page, last_page) {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+    // 这些页面刚刚映射，还没有交给任何代码
+    if !unsafe { ALLOCATOR.extend(start, size) } {
+        return Err(ExtendHeapError::Rejected { start, size });
+    }
+    *heap_end = start + size;
+    Ok(())
+}
+
+/// 检查全局堆上的所有分配都已经释放，否则报告尚未释放的分配并 panic。
+pub fn assert_balanced() {
+    ALLOCATOR.assert_balanced();
+}
+
+/// 返回主堆使用的分配器的类型，`init_heap` 之前是 [`AllocatorKind::FixedSizeBlock`]。
+pub fn heap_allocator_kind() -> AllocatorKind {
+    ALLOCATOR.kind()
+}
+
+/// 返回主堆的统计，见 [`KernelAllocator::stats`]。
+pub fn heap_stats() -> KernelStats {
+    ALLOCATOR.stats()
+}
+
+/// 通过串口打印主堆的状态，见 [`KernelAllocator::dump`]。
+pub fn dump_heap() {
+    ALLOCATOR.dump_to_serial();
+}
 
 unsafe impl GlobalAlloc for Dummy {
     unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
@@ -49,9 +745,20 @@ unsafe impl GlobalAlloc for Dummy {
         panic!("dealloc should be never called")
     }
 }
+/// 映射堆页面并用 [`FixedSizeBlockAllocator`] 初始化主堆，见 [`init_heap_with`]。
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    init_heap_with(mapper, frame_allocator, AllocatorKind::FixedSizeBlock)
+}
+
+/// 映射 `HEAP_START..HEAP_START + HEAP_SIZE` 的页面，用 `kind` 类型的分配器初始化主堆，
+/// 然后让全局分配器从引导堆切换到主堆。只能调用一次。
+pub fn init_heap_with(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    kind: AllocatorKind,
 ) -> Result<(), MapToError<Size4KiB>> {
     let page_range = {
         let heap_start = VirtAddr::new(HEAP_START as u64);
@@ -69,14 +776,77 @@ pub fn init_heap(
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
     }
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        let mut allocator = ALLOCATOR.lock();
+        // 还在引导阶段，ALLOCATOR 还没有分配过
+        *allocator = KernelAllocator::new(kind);
+        allocator.init(HEAP_START, HEAP_SIZE);
     }
+    switch_to_main_heap();
+    register_shrinker(|target| ALLOCATOR.trim(target));
+
+    Ok(())
+}
 
+/// 取消映射 `start..start + size` 这些堆页面，并把对应的帧交还给帧分配器。
+///
+/// 用来处理 [`LinkedListAllocator::release_tail`] 报告的范围，范围必须是页对齐的。
+///
+/// # Safety
+///
+/// 调用者必须保证这些页面已经映射，并且不再有任何代码使用它们。
+pub unsafe fn unmap_heap_pages(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+    start: usize,
+    size: usize,
+) -> Result<(), UnmapError> {
+    let first_page = Page::<Size4KiB>::containing_address(VirtAddr::new(start as u64));
+    let last_page = Page::containing_address(VirtAddr::new((start + size - 1) as u64));
+    for page in Page::range_inclusive(first_page, last_page) {
+        let (frame, flush) = mapper.unmap(page)?;
+        flush.flush();
+        frame_deallocator.deallocate_frame(frame);
+    }
     Ok(())
 }
 /// 向上对齐给定地址 `addr` 到对齐 `align`。
 ///
+/// 要求 `align` 是2的幂（debug 构建中会检查），并且结果不超出 `usize`；结果可能溢出时使用
+/// [`checked_align_up`]。
+pub const fn align_up(addr: usize, align: usize) -> usize {
+    debug_assert!(align.is_power_of_two());
+    // 先算 `align - 1`，这样结果不溢出时中间值也不会溢出
+    (addr + (align - 1)) & !(align - 1)
+}
+
+/// 向上对齐给定地址 `addr` 到对齐 `align`，溢出时返回 `None`。
+///
+/// 要求 `align` 是2的幂（debug 构建中会检查）。
+pub const fn checked_align_up(addr: usize, align: usize) -> Option<usize> {
+    debug_assert!(align.is_power_of_two());
+    match addr.checked_add(align - 1) {
+        Some(addr) => Some(addr & !(align - 1)),
+        None => None,
+    }
+}
+
+/// 向下对齐给定地址 `addr` 到对齐 `align`。
+///
 /// 要求 `align` 是2的幂。
-fn align_up(addr: usize, align: usize) -> usize {
-    (addr + align - 1) & !(align - 1)
+pub const fn align_down(addr: usize, align: usize) -> usize {
+    addr & !(align - 1)
+}
+
+/// 给定地址 `addr` 是否按 `align` 对齐。
+///
+/// 要求 `align` 是2的幂。
+pub const fn is_aligned(addr: usize, align: usize) -> bool {
+    addr & (align - 1) == 0
+}
+
+/// 零大小分配返回的指针：非空且满足对齐，但不占用任何堆内存。
+///
+/// 各个分配器释放零大小的布局时直接忽略，不会去检查这个指针。
+fn dangling(layout: &Layout) -> *mut u8 {
+    layout.align() as *mut u8
 }
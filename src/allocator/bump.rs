@@ -1,25 +1,143 @@
+use alloc::{alloc::handle_alloc_error, boxed::Box};
 use core::{
-    alloc::{GlobalAlloc, Layout},
-    ptr,
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    mem::MaybeUninit,
+    ptr::{self, NonNull},
+    sync::atomic::{compiler_fence, Ordering},
 };
 
-use super::{align_up, Locked};
+use super::{align_down, checked_align_up, dangling, Locked};
+use crate::serial_println;
 
-pub struct BumpAllocator {
+/// 最多可以追加的块数。
+pub const MAX_EXTRA_CHUNKS: usize = 4;
+
+/// 堆用完时获取新块的钩子，见 [`BumpAllocator::set_grow_fn`]。
+///
+/// 参数是新块至少要有的字节数，返回块的起始地址和大小。
+pub type GrowFn = fn(min_size: usize) -> Option<(usize, usize)>;
+
+/// 释放追加的块的钩子，参数是 [`GrowFn`] 返回的起始地址和大小。
+pub type ReleaseFn = fn(start: usize, size: usize);
+
+/// 分配失败、即将返回空指针时调用的钩子，见 [`BumpAllocator::set_oom_hook`]。
+pub type OomHook<const DOWN: bool = false> = fn(&BumpAllocator<DOWN>, Layout);
+
+/// 通过串口打印失败的布局和堆的用量，可以用 [`BumpAllocator::set_oom_hook`] 设置。
+pub fn log_oom<const DOWN: bool>(bump: &BumpAllocator<DOWN>, layout: Layout) {
+    serial_println!(
+        "bump: out of memory allocating {:?}: {} bytes used, {} bytes remaining, peak {} bytes",
+        layout,
+        bump.used(),
+        bump.remaining(),
+        bump.peak_used()
+    );
+}
+
+/// 从堆的哪一端分配，见 [`BumpAllocator::alloc_at`]。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    /// 从堆的开头向上分配。
+    Bottom,
+    /// 从堆尾向下分配。
+    Top,
+}
+
+/// [`BumpAllocator::checkpoint`] 记下的一端的分配状态，[`BumpAllocator::rewind`] 可以回到这里。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BumpCheckpoint {
+    end: End,
+    next: usize,
+    allocations: usize,
+    /// 记下时追加过的块数，换了块之后不能再回到这里。
+    chunks: usize,
+}
+
+/// 突增分配器。`DOWN` 为 `true` 时从堆尾向下分配，这样同一块内存的开头可以留给向上增长的数据。
+///
+/// [`alloc_at`](Self::alloc_at) 还可以从另一端分配：两端各有一个游标，相遇时分配失败。
+pub struct BumpAllocator<const DOWN: bool = false> {
     heap_start: usize,
     heap_end: usize,
     next: usize,
     allocations: usize,
+    /// 另一端的游标和它的尚未释放的分配数，这些分配总是在当前的块中。
+    far: usize,
+    far_allocations: usize,
+    /// `used` 曾经达到的最大值。
+    peak_used: usize,
+    /// 被忽略的释放次数。
+    spurious_deallocs: usize,
+    /// 自上一次重置以来对齐留下的空隙的总字节数和最大的一个。
+    wasted_bytes: usize,
+    max_gap: usize,
+    /// `init` 给出的堆，全部释放后回到这里。
+    initial: (usize, usize),
+    /// 追加的块的起始地址和大小，最后一个是当前的块。
+    extra_chunks: [(usize, usize); MAX_EXTRA_CHUNKS],
+    extra_count: usize,
+    grow: Option<GrowFn>,
+    release: Option<ReleaseFn>,
+    /// 重置时是否把用过的内存清零。
+    zero_on_reset: bool,
+    /// 自重置或者换块以来当前的块中两端用过的最大字节数，释放最近的分配和 `rewind` 之后那里
+    /// 仍然有旧数据。
+    touched: usize,
+    far_touched: usize,
+    oom_hook: Option<OomHook<DOWN>>,
+    /// `with_memory` 给出的内存，随分配器一起丢弃。
+    _memory: Option<Box<[MaybeUninit<u8>]>>,
 }
 
 impl BumpAllocator {
     /// 创建一个新的空突增分配器。
     pub const fn new() -> Self {
+        Self::empty()
+    }
+
+    /// 创建一个在 `memory` 上分配的突增分配器，`memory` 随分配器一起丢弃。
+    ///
+    /// 可以不经过 `Locked` 直接使用 [`alloc`](Self::alloc) 和 [`dealloc`](Self::dealloc)，
+    /// 例如驱动程序从全局堆申请一小块内存，在上面构建临时的命令结构。
+    pub fn with_memory(memory: Box<[MaybeUninit<u8>]>) -> Self {
+        let mut bump = Self::empty();
+        // 这块内存归分配器所有，会一直活到分配器被丢弃
+        unsafe { bump.init(memory.as_ptr() as usize, memory.len()) };
+        bump._memory = Some(memory);
+        bump
+    }
+}
+
+impl BumpAllocator<true> {
+    /// 创建一个新的从堆尾向下分配的空突增分配器。
+    pub const fn new_downward() -> Self {
+        Self::empty()
+    }
+}
+
+impl<const DOWN: bool> BumpAllocator<DOWN> {
+    const fn empty() -> Self {
         BumpAllocator {
             heap_start: 0,
             heap_end: 0,
             next: 0,
             allocations: 0,
+            far: 0,
+            far_allocations: 0,
+            peak_used: 0,
+            spurious_deallocs: 0,
+            wasted_bytes: 0,
+            max_gap: 0,
+            initial: (0, 0),
+            extra_chunks: [(0, 0); MAX_EXTRA_CHUNKS],
+            extra_count: 0,
+            grow: None,
+            release: None,
+            zero_on_reset: false,
+            touched: 0,
+            far_touched: 0,
+            oom_hook: None,
+            _memory: None,
         }
     }
 
@@ -29,34 +147,532 @@ impl BumpAllocator {
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.heap_start = heap_start;
         self.heap_end = heap_start + heap_size;
-        self.next = heap_start;
+        self.next = self.base();
+        self.far = self.far_base();
+        self.initial = (self.heap_start, self.heap_end);
+    }
+
+    /// 设置堆用完时获取新块的钩子。之前的块不再分配，但仍然记着，全部释放后用
+    /// [`set_release_fn`](Self::set_release_fn) 设置的钩子释放追加的块，再回到 `init` 给出的堆。
+    ///
+    /// 钩子在持有分配器的锁时调用，不能从这个分配器分配内存。最多追加 [`MAX_EXTRA_CHUNKS`] 个块。
+    pub fn set_grow_fn(&mut self, grow: GrowFn) {
+        self.grow = Some(grow);
+    }
+
+    /// 设置全部释放后释放追加的块的钩子。没有设置时追加的块只是被丢弃。
+    pub fn set_release_fn(&mut self, release: ReleaseFn) {
+        self.release = Some(release);
+    }
+
+    /// 设置重置时是否把用过的内存清零，这样上一轮的数据不会被下一轮的分配读到。默认不清零。
+    ///
+    /// 显式的 [`reset`](Self::reset) 和全部释放时的重置都会清零，范围包括已经释放的最近的分配和
+    /// `rewind` 丢弃的分配用过的内存。
+    pub fn set_zero_on_reset(&mut self, zero_on_reset: bool) {
+        self.zero_on_reset = zero_on_reset;
+    }
+
+    /// 设置分配失败时调用的钩子，默认不调用。[`log_oom`] 通过串口打印堆的用量。
+    ///
+    /// 钩子在持有分配器的锁时调用，可以读取这个分配器的状态，但不能分配内存。
+    pub fn set_oom_hook(&mut self, hook: OomHook<DOWN>) {
+        self.oom_hook = Some(hook);
+    }
+
+    /// 返回追加的、还没有释放的块的起始地址和大小，最后一个是当前的块。
+    pub fn extra_chunks(&self) -> &[(usize, usize)] {
+        &self.extra_chunks[..self.extra_count]
+    }
+
+    /// 用 `grow` 钩子换一个至少能放下 `layout` 的新块，没有钩子、块数已满、另一端还有分配或者
+    /// 钩子失败时返回 `false`。
+    fn grow(&mut self, layout: Layout) -> bool {
+        let grow = match self.grow {
+            Some(grow) if self.extra_count < MAX_EXTRA_CHUNKS && self.far_allocations == 0 => grow,
+            _ => return false,
+        };
+        // 新块的开头不一定满足对齐
+        let min_size = match layout.size().checked_add(layout.align() - 1) {
+            Some(min_size) => min_size,
+            None => return false,
+        };
+        let (start, size) = match grow(min_size) {
+            Some(chunk) => chunk,
+            None => return false,
+        };
+        self.extra_chunks[self.extra_count] = (start, size);
+        self.extra_count += 1;
+        self.heap_start = start;
+        self.heap_end = start + size;
+        self.next = self.base();
+        self.far = self.far_base();
+        self.touched = 0;
+        self.far_touched = 0;
+        true
+    }
+
+    /// `end` 是不是另一端，也就是与 `DOWN` 选出的一端相反的那一端。
+    fn is_far(end: End) -> bool {
+        (end == End::Top) != DOWN
+    }
+
+    /// 这一端的游标是否向上移动。
+    fn grows_up(far: bool) -> bool {
+        far == DOWN
+    }
+
+    /// 在当前的块中为 `layout` 找位置，返回分配的开头和游标新的位置；两个游标会交叉时返回 `None`。
+    fn fit(&self, layout: Layout, far: bool) -> Option<(usize, usize)> {
+        let (cursor, limit) = if far {
+            (self.far, self.next)
+        } else {
+            (self.next, self.far)
+        };
+        if Self::grows_up(far) {
+            let start = checked_align_up(cursor, layout.align())?;
+            let end = start.checked_add(layout.size())?;
+            (end <= limit).then_some((start, end))
+        } else {
+            // 向下分配时游标新的位置就是分配的开头
+            let start = align_down(cursor.checked_sub(layout.size())?, layout.align());
+            (start >= limit).then_some((start, start))
+        }
+    }
+
+    /// 返回当前的块中从这一端的开头到它的游标的字节数。
+    fn end_used(&self, far: bool) -> usize {
+        let cursor = if far { self.far } else { self.next };
+        if Self::grows_up(far) {
+            cursor - self.heap_start
+        } else {
+            self.heap_end - cursor
+        }
+    }
+
+    /// `addr` 是否在 `init` 给出的堆或者追加的某个块中。
+    fn owns(&self, addr: usize) -> bool {
+        let (start, end) = self.initial;
+        (start..end).contains(&addr)
+            || self
+                .extra_chunks()
+                .iter()
+                .any(|&(start, size)| (start..start + size).contains(&addr))
+    }
+
+    /// 没有分配时 `next` 的位置：向上分配时是堆的开头，向下分配时是堆尾。
+    fn base(&self) -> usize {
+        if DOWN {
+            self.heap_end
+        } else {
+            self.heap_start
+        }
+    }
+
+    /// 没有分配时另一端的游标的位置。
+    fn far_base(&self) -> usize {
+        if DOWN {
+            self.heap_start
+        } else {
+            self.heap_end
+        }
+    }
+
+    /// 返回当前的块中两个游标之间还没有分配出去的字节数，`init` 之前为 0。
+    pub fn remaining(&self) -> usize {
+        if DOWN {
+            self.next - self.far
+        } else {
+            self.far - self.next
+        }
+    }
+
+    /// 返回当前的块中两端从开头到游标的字节数之和，包括对齐留下的空隙，`init` 之前为 0。
+    pub fn used(&self) -> usize {
+        self.end_used(false) + self.end_used(true)
+    }
+
+    /// 返回两端尚未释放的分配的个数。
+    pub fn allocation_count(&self) -> usize {
+        self.allocations + self.far_allocations
+    }
+
+    /// 返回 `used` 曾经达到的最大值。
+    pub fn peak_used(&self) -> usize {
+        self.peak_used
+    }
+
+    /// 返回自上一次重置以来为了对齐跳过的字节数，这部分包含在 `used` 中。
+    ///
+    /// 释放最近的分配和 `rewind` 都不减少这个计数。
+    pub fn wasted_bytes(&self) -> usize {
+        self.wasted_bytes
+    }
+
+    /// 返回自上一次重置以来为了对齐跳过的最大的一段空隙。
+    pub fn max_alignment_gap(&self) -> usize {
+        self.max_gap
+    }
+
+    /// 返回被忽略的释放次数：没有尚未释放的分配，或者指针不在堆和追加的块内。
+    ///
+    /// 突增分配器不记录单个分配，无法发现其他的重复释放。
+    pub fn spurious_deallocs(&self) -> usize {
+        self.spurious_deallocs
+    }
+
+    /// 记下 `DOWN` 选出的一端当前的分配状态。
+    pub fn checkpoint(&self) -> BumpCheckpoint {
+        self.checkpoint_at(if DOWN { End::Top } else { End::Bottom })
+    }
+
+    /// 记下 `end` 这一端当前的分配状态，两端的检查点互不影响。
+    pub fn checkpoint_at(&self, end: End) -> BumpCheckpoint {
+        let far = Self::is_far(end);
+        BumpCheckpoint {
+            end,
+            next: if far { self.far } else { self.next },
+            allocations: if far {
+                self.far_allocations
+            } else {
+                self.allocations
+            },
+            chunks: self.extra_count,
+        }
+    }
+
+    /// 让检查点所在的一端回到 `checkpoint` 记下的状态，一次丢弃这一端在它之后的所有分配。
+    ///
+    /// 这个方法是不安全的，因为调用者必须确保检查点之后这一端的分配都不再被使用，并且这一端在
+    /// 检查点之前的分配在这期间都没有释放。检查点之后追加过块时不能回到这里。
+    pub unsafe fn rewind(&mut self, checkpoint: BumpCheckpoint) {
+        debug_assert_eq!(
+            checkpoint.chunks, self.extra_count,
+            "a chunk was added after the checkpoint"
+        );
+        let far = Self::is_far(checkpoint.end);
+        let (cursor, base) = if far {
+            (self.far, self.far_base())
+        } else {
+            (self.next, self.base())
+        };
+        let (low, high) = if Self::grows_up(far) {
+            (base, cursor)
+        } else {
+            (cursor, base)
+        };
+        debug_assert!(
+            low <= checkpoint.next && checkpoint.next <= high,
+            "checkpoint {:#x} is not between the heap base and the current next",
+            checkpoint.next
+        );
+        if far {
+            self.far = checkpoint.next;
+            self.far_allocations = checkpoint.allocations;
+        } else {
+            self.next = checkpoint.next;
+            self.allocations = checkpoint.allocations;
+        }
+    }
+
+    /// 丢弃所有的分配：释放追加的块，回到 `init` 给出的堆的开头。全部释放时也会这样重置。
+    ///
+    /// 这个方法是不安全的，因为调用者必须确保之前分配的内存都不再被使用。
+    pub unsafe fn reset(&mut self) {
+        if self.zero_on_reset {
+            self.wipe();
+        }
+        for &(start, size) in &self.extra_chunks[..self.extra_count] {
+            if let Some(release) = self.release {
+                release(start, size);
+            }
+        }
+        self.extra_count = 0;
+        self.allocations = 0;
+        self.far_allocations = 0;
+        self.wasted_bytes = 0;
+        self.max_gap = 0;
+        (self.heap_start, self.heap_end) = self.initial;
+        self.next = self.base();
+        self.far = self.far_base();
+        self.touched = 0;
+        self.far_touched = 0;
+    }
+
+    /// 把用过的内存清零：当前的块只清零用过的部分，之前的块不知道用到了哪里，整块清零。
+    unsafe fn wipe(&self) {
+        // 向上增长的一端从堆的开头清零，向下增长的一端清零到堆尾
+        let (up, down) = if DOWN {
+            (self.far_touched, self.touched)
+        } else {
+            (self.touched, self.far_touched)
+        };
+        ptr::write_bytes(self.heap_start as *mut u8, 0, up);
+        ptr::write_bytes((self.heap_end - down) as *mut u8, 0, down);
+        if self.extra_count > 0 {
+            let (start, end) = self.initial;
+            ptr::write_bytes(start as *mut u8, 0, end - start);
+            for &(start, size) in &self.extra_chunks[..self.extra_count - 1] {
+                ptr::write_bytes(start as *mut u8, 0, size);
+            }
+        }
+        // 之后不会再读这些内存，不能让编译器把清零当作无用的写入去掉
+        compiler_fence(Ordering::SeqCst);
     }
 }
-unsafe impl GlobalAlloc for Locked<BumpAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut bump = self.lock(); // 获取一个可变引用
 
-        let alloc_start = align_up(bump.next, layout.align());
-        let alloc_end = match alloc_start.checked_add(layout.size()) {
-            Some(end) => end,
-            None => return ptr::null_mut(),
+impl<const DOWN: bool> Locked<BumpAllocator<DOWN>> {
+    /// 返回堆中还没有分配出去的字节数，见 [`BumpAllocator::remaining`]。
+    pub fn remaining(&self) -> usize {
+        self.lock().remaining()
+    }
+
+    /// 返回从堆的开头到 `next` 的字节数，见 [`BumpAllocator::used`]。
+    pub fn used(&self) -> usize {
+        self.lock().used()
+    }
+
+    /// 返回尚未释放的分配的个数。
+    pub fn allocation_count(&self) -> usize {
+        self.lock().allocation_count()
+    }
+
+    /// 返回 `used` 曾经达到的最大值。
+    pub fn peak_used(&self) -> usize {
+        self.lock().peak_used()
+    }
+
+    /// 返回为了对齐跳过的字节数，见 [`BumpAllocator::wasted_bytes`]。
+    pub fn wasted_bytes(&self) -> usize {
+        self.lock().wasted_bytes()
+    }
+
+    /// 返回为了对齐跳过的最大的一段空隙，见 [`BumpAllocator::max_alignment_gap`]。
+    pub fn max_alignment_gap(&self) -> usize {
+        self.lock().max_alignment_gap()
+    }
+
+    /// 返回被忽略的释放次数，见 [`BumpAllocator::spurious_deallocs`]。
+    pub fn spurious_deallocs(&self) -> usize {
+        self.lock().spurious_deallocs()
+    }
+
+    /// 设置堆用完时获取新块的钩子，见 [`BumpAllocator::set_grow_fn`]。
+    pub fn set_grow_fn(&self, grow: GrowFn) {
+        self.lock().set_grow_fn(grow);
+    }
+
+    /// 设置全部释放后释放追加的块的钩子，见 [`BumpAllocator::set_release_fn`]。
+    pub fn set_release_fn(&self, release: ReleaseFn) {
+        self.lock().set_release_fn(release);
+    }
+
+    /// 设置分配失败时调用的钩子，见 [`BumpAllocator::set_oom_hook`]。
+    pub fn set_oom_hook(&self, hook: OomHook<DOWN>) {
+        self.lock().set_oom_hook(hook);
+    }
+
+    /// 设置重置时是否把用过的内存清零，见 [`BumpAllocator::set_zero_on_reset`]。
+    pub fn set_zero_on_reset(&self, zero_on_reset: bool) {
+        self.lock().set_zero_on_reset(zero_on_reset);
+    }
+
+    /// 返回追加的、还没有释放的块的个数。
+    pub fn extra_chunk_count(&self) -> usize {
+        self.lock().extra_chunks().len()
+    }
+
+    /// 记下当前的分配状态，见 [`BumpAllocator::checkpoint`]。
+    pub fn checkpoint(&self) -> BumpCheckpoint {
+        self.lock().checkpoint()
+    }
+
+    /// 记下 `end` 这一端当前的分配状态，见 [`BumpAllocator::checkpoint_at`]。
+    pub fn checkpoint_at(&self, end: End) -> BumpCheckpoint {
+        self.lock().checkpoint_at(end)
+    }
+
+    /// 回到 `checkpoint` 记下的状态，见 [`BumpAllocator::rewind`]。
+    ///
+    /// 这个方法是不安全的，要求与 [`BumpAllocator::rewind`] 相同。
+    pub unsafe fn rewind(&self, checkpoint: BumpCheckpoint) {
+        self.lock().rewind(checkpoint);
+    }
+
+    /// 丢弃所有的分配，见 [`BumpAllocator::reset`]。
+    ///
+    /// 这个方法是不安全的，要求与 [`BumpAllocator::reset`] 相同。
+    pub unsafe fn reset(&self) {
+        self.lock().reset();
+    }
+}
+
+impl<const DOWN: bool> BumpAllocator<DOWN> {
+    /// 从 `DOWN` 选出的一端为 `layout` 分配内存，放不下时返回 `None`。零大小的分配得到按对齐悬空
+    /// 的指针。
+    pub fn alloc(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        self.alloc_at(layout, if DOWN { End::Top } else { End::Bottom })
+    }
+
+    /// 从 `end` 这一端为 `layout` 分配内存，会越过另一端的游标时返回 `None`。
+    ///
+    /// 只有 `DOWN` 选出的一端会用 [`set_grow_fn`](Self::set_grow_fn) 的钩子换新块，并且另一端
+    /// 有分配时不换块。
+    pub fn alloc_at(&mut self, layout: Layout, end: End) -> Option<NonNull<u8>> {
+        if layout.size() == 0 {
+            return NonNull::new(dangling(&layout));
+        }
+        let far = Self::is_far(end);
+        let fit = match self.fit(layout, far) {
+            Some(fit) => Some(fit),
+            // 当前的块不够用了 -> 换一个新块再试一次
+            None if !far && self.grow(layout) => self.fit(layout, far),
+            None => None,
+        };
+        let Some((alloc_start, cursor)) = fit else {
+            // 内存不足
+            if let Some(hook) = self.oom_hook {
+                hook(self, layout);
+            }
+            return None;
         };
 
-        if alloc_end > bump.heap_end {
-            ptr::null_mut() // 内存不足
+        // 对齐前的游标与分配之间的空隙
+        let old_cursor = if far { self.far } else { self.next };
+        let gap = if Self::grows_up(far) {
+            alloc_start - old_cursor
         } else {
-            bump.next = alloc_end;
-            bump.allocations += 1;
-            alloc_start as *mut u8
+            old_cursor - (alloc_start + layout.size())
+        };
+        self.wasted_bytes += gap;
+        self.max_gap = self.max_gap.max(gap);
+        if far {
+            self.far = cursor;
+            self.far_allocations += 1;
+            self.far_touched = self.far_touched.max(self.end_used(true));
+        } else {
+            self.next = cursor;
+            self.allocations += 1;
+            self.touched = self.touched.max(self.end_used(false));
         }
+        self.peak_used = self.peak_used.max(self.used());
+        NonNull::new(alloc_start as *mut u8)
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        let mut bump = self.lock(); // 获取一个可变引用
+    /// 释放 `alloc` 为 `layout` 分配的 `ptr`，多余的释放被忽略并计数。
+    ///
+    /// 这个方法是不安全的，因为释放之后这块内存可能被重新分配或者在重置时清零，调用者必须保证
+    /// `ptr` 不再被使用。
+    pub unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        // 多余的释放不能让计数下溢，否则全部释放后不会再重置
+        let addr = ptr.as_ptr() as usize;
+        if self.allocation_count() == 0 || !self.owns(addr) {
+            self.spurious_deallocs += 1;
+            return;
+        }
+        // 另一端的分配都在当前的块中，位于它的开头和游标之间
+        let far_range = if DOWN {
+            self.heap_start..self.far
+        } else {
+            self.far..self.heap_end
+        };
+        let far = self.far_allocations > 0 && far_range.contains(&addr);
+        if far {
+            self.far_allocations -= 1;
+        } else if self.allocations > 0 {
+            self.allocations -= 1;
+        } else {
+            self.spurious_deallocs += 1;
+            return;
+        }
+        if self.allocation_count() == 0 {
+            self.reset();
+            return;
+        }
+        let cursor = if far { &mut self.far } else { &mut self.next };
+        if Self::grows_up(far) && addr + layout.size() == *cursor {
+            // 释放的是这一端最近的分配 -> 收回它占用的空间，对齐留下的空隙仍然留在前面
+            *cursor = addr;
+        } else if !Self::grows_up(far) && addr == *cursor {
+            // 释放的是这一端最近的分配 -> 收回它占用的空间，对齐留下的空隙仍然留在后面
+            *cursor = addr + layout.size();
+        }
+    }
+}
 
-        bump.allocations -= 1;
-        if bump.allocations == 0 {
-            bump.next = bump.heap_start;
+unsafe impl<const DOWN: bool> GlobalAlloc for Locked<BumpAllocator<DOWN>> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock()
+            .alloc(layout)
+            .map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut bump = self.lock();
+        match NonNull::new(ptr) {
+            Some(ptr) => bump.dealloc(ptr, layout),
+            // 空指针不在堆内，和其他多余的释放一样计数
+            None if layout.size() != 0 => bump.spurious_deallocs += 1,
+            None => {}
+        }
+    }
+}
+
+/// 从全局堆分配的一块内存上的突增分配区，被丢弃时整块还给全局堆。
+///
+/// 实现了 [`Allocator`]，`Vec::new_in(&arena)` 和 `Box::new_in(value, &arena)` 可以在上面建立
+/// 临时的集合；借用规则保证它们先于分配区被丢弃，所以它们的 `Drop` 照常运行。
+pub struct Bump {
+    inner: Locked<BumpAllocator>,
+}
+
+impl Bump {
+    /// 从全局堆分配 `capacity` 字节作为分配区，全局堆不够时调用 `handle_alloc_error`。
+    pub fn with_capacity(capacity: usize) -> Self {
+        Bump {
+            inner: Locked::new(BumpAllocator::with_memory(Box::new_uninit_slice(capacity))),
+        }
+    }
+
+    /// 把 `value` 移进分配区，返回它的可变引用。分配区放不下时调用 `handle_alloc_error`。
+    ///
+    /// 分配区被丢弃时只是把整块内存还给全局堆，`value` 的 `Drop` 永远不会运行。
+    // 每次调用返回的都是新分配的内存，不会与别的引用重叠
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_without_drop<T>(&self, value: T) -> &mut T {
+        let layout = Layout::new::<T>();
+        let ptr = unsafe { self.inner.alloc(layout) } as *mut T;
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        unsafe {
+            ptr.write(value);
+            &mut *ptr
         }
     }
+
+    /// 返回分配区中还没有分配出去的字节数。
+    pub fn remaining(&self) -> usize {
+        self.inner.remaining()
+    }
+
+    /// 返回分配区已经用掉的字节数。
+    pub fn used(&self) -> usize {
+        self.inner.used()
+    }
+}
+
+unsafe impl Allocator for Bump {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        // 零大小的分配得到按对齐悬空的指针，长度为零
+        let ptr = NonNull::new(unsafe { self.inner.alloc(layout) }).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.inner.dealloc(ptr.as_ptr(), layout);
+    }
 }
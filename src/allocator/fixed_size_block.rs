@@ -1,13 +1,30 @@
 use core::{
-    alloc::{GlobalAlloc, Layout}, mem, ptr::{self, NonNull}
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout}, fmt, mem, ptr::{self, NonNull}
 };
 
-use super::Locked;
+use super::{align_up, dangling, is_aligned, Locked};
 
-/// 使用的块大小。
+mod batch;
+mod fallback;
+mod per_class;
+mod per_cpu;
+#[cfg(feature = "heap-quarantine")]
+mod quarantine;
+#[cfg(feature = "heap-slab")]
+mod slab;
+
+use fallback::Fallback;
+pub use per_class::{ClassLockedBlockAllocator, PER_CLASS_BATCH};
+pub use per_cpu::{PerCpuBlockAllocator, PER_CPU_BATCH};
+#[cfg(feature = "heap-quarantine")]
+pub use quarantine::DEFAULT_QUARANTINE_DEPTH;
+
+/// 默认使用的块大小，其他的大小表可以传给 [`FixedSizeBlockAllocator::with_block_sizes`]。
 ///
 /// 大小必须是2的幂，因为它们也用作块对齐（对齐必须始终是2的幂）。
-const BLOCK_SIZES: &[usize] = &[
+/// 4096 和 8192 字节的类覆盖页大小的缓冲区；它们按页对齐地从后备分配器切出，
+/// 对齐留下的前部空隙会留在后备分配器的空闲列表里，不会浪费。
+pub const BLOCK_SIZES: [usize; 11] = [
     1 << 3,
     1 << 4,
     1 << 5,
@@ -17,86 +34,1823 @@ const BLOCK_SIZES: &[usize] = &[
     1 << 9,
     1 << 10,
     1 << 11,
+    1 << 12,
+    1 << 13,
 ];
+
+/// 每个非零大小的分配至少满足的对齐：启用 `min-align-16` feature 时是 16，否则是 8。
+///
+/// 类的块按块大小对齐，所以启用时不足 16 字节的请求也使用 16 字节的类；大块向后备分配器请求时
+/// 对齐也至少取这么大。
+pub const MIN_ALIGN: usize = if cfg!(feature = "min-align-16") {
+    16
+} else {
+    8
+};
+
+/// 默认的大小类的个数。
+pub const CLASS_COUNT: usize = BLOCK_SIZES.len();
+
+/// 每个大小类的空闲列表默认最多缓存的块数，超出的块归还给后备分配器。
+pub const DEFAULT_CACHE_CAP: usize = 64;
+
+/// [`FixedSizeBlockAllocator::tick`] 按最近的分配次数调整缓存上限时的下限和上限。
+pub const MIN_ADAPTIVE_CAP: usize = 8;
+pub const MAX_ADAPTIVE_CAP: usize = 512;
+
+/// 后备分配器能单独记录的最小空闲范围。更小的块释放时会盖住同一批里相邻的块，
+/// 所以这些类的块总是留在空闲列表中，不受缓存上限的限制。
+pub const MIN_RETURNABLE_BLOCK: usize = fallback::Heap::MIN_FREE;
+
+/// 空闲列表为空时默认一次切出一页的块，但不超过这么多个，也至少一个。
+const DEFAULT_BATCH_BYTES: usize = 4096;
+const MAX_DEFAULT_BATCH: usize = 16;
+
+/// 启用 `heap-poison` feature 时，空闲列表中的块在 `ListNode` 之后的字节都填充为这个值。
+#[cfg(feature = "heap-poison")]
+pub const POISON: u8 = 0xDD;
+
+/// 空闲列表中的块是否在开头的记录之后全部是零：启用 `zero-on-free` 时释放的块都被清零，
+/// 但启用 `heap-poison` 时填充的是 `POISON`。
+const FREE_BLOCKS_ZEROED: bool = cfg!(feature = "zero-on-free") && !cfg!(feature = "heap-poison");
+
+/// 把 `ptr` 开始的 `len` 字节清零。写入是 volatile 的，即使这段内存随后就被释放、再也没有读取，
+/// 编译器也不能省略它们。
+#[cfg(feature = "zero-on-free")]
+unsafe fn scrub_bytes(ptr: *mut u8, len: usize) {
+    let word = mem::size_of::<usize>();
+    let head = ptr.align_offset(word).min(len);
+    for i in 0..head {
+        ptr.add(i).write_volatile(0);
+    }
+    let words = (len - head) / word;
+    let aligned = ptr.add(head) as *mut usize;
+    for i in 0..words {
+        aligned.add(i).write_volatile(0);
+    }
+    for i in head + words * word..len {
+        ptr.add(i).write_volatile(0);
+    }
+}
+
+/// 页级大块分配使用的页大小。
+pub const PAGE_SIZE: usize = 4096;
+
+/// 调用者提供的页分配函数，见 [`FixedSizeBlockAllocator::set_page_allocator`]。
+///
+/// 两个函数都在分配器的锁内调用，不能再从这个分配器分配内存。
+#[derive(Debug, Clone, Copy)]
+pub struct PageAllocator {
+    /// 分配 `count` 个连续的页，返回按 `PAGE_SIZE` 对齐的第一页的地址，失败时返回 `None`。
+    pub alloc_pages: fn(count: usize) -> Option<usize>,
+    /// 释放 `alloc_pages` 返回的从 `addr` 开始的 `count` 个页。
+    pub free_pages: fn(addr: usize, count: usize),
+}
+
+/// 一个大小类的分配统计。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClassStats {
+    /// 这个类的块大小。
+    pub block_size: usize,
+    /// 直接从空闲列表取到块的分配次数。
+    pub hits: usize,
+    /// 空闲列表为空、从后备分配器切出新块的分配次数，也就是缓存未命中；每次切出一批块。
+    pub carves: usize,
+    /// 归还给后备分配器的块数：空闲列表已满时释放的块，以及 `drain` 清空的块。
+    pub returned: usize,
+    /// 空闲列表中当前缓存的块数。
+    pub cached: usize,
+    /// 尚未释放的块数。
+    pub live: usize,
+}
+
+impl ClassStats {
+    /// 空闲列表中缓存的字节数。
+    pub fn cached_bytes(&self) -> usize {
+        self.cached * self.block_size
+    }
+
+    /// 尚未释放的块占用的字节数。
+    pub fn live_bytes(&self) -> usize {
+        self.live * self.block_size
+    }
+
+    /// 命中占这个类所有分配的千分比，还没有分配过时返回 `None`。
+    pub fn hit_permille(&self) -> Option<usize> {
+        permille(self.hits, self.hits + self.carves)
+    }
+}
+
+/// `part` 占 `total` 的千分比，`total` 为零时返回 `None`。
+fn permille(part: usize, total: usize) -> Option<usize> {
+    (total > 0).then(|| part * 1000 / total)
+}
+
+/// [`FixedSizeBlockAllocator::stats`] 返回的统计快照。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStats<const N: usize = CLASS_COUNT> {
+    /// 按块大小从小到大排列的各个类的统计。
+    pub classes: [ClassStats; N],
+    /// 比所有类都大、直接交给后备分配器或页分配函数的分配次数。
+    pub large_allocs: usize,
+    /// 后备分配器管理的字节数。
+    pub fallback_size: usize,
+    /// 后备分配器中已分配的字节数：切给各个类的块无论缓存着还是正在使用都算在内。
+    pub fallback_used: usize,
+    /// 后备分配器中空闲的字节数。
+    pub fallback_free: usize,
+}
+
+impl<const N: usize> BlockStats<N> {
+    /// 所有类的空闲列表中缓存的字节数。
+    pub fn cached_bytes(&self) -> usize {
+        self.classes.iter().map(ClassStats::cached_bytes).sum()
+    }
+
+    /// 所有类中尚未释放的块占用的字节数，不包括直接交给后备分配器的大块。
+    pub fn live_bytes(&self) -> usize {
+        self.classes.iter().map(ClassStats::live_bytes).sum()
+    }
+
+    /// 所有类中直接从缓存取到块的分配次数。
+    pub fn hits(&self) -> usize {
+        self.classes.iter().map(|class| class.hits).sum()
+    }
+
+    /// 所有类中缓存为空、从后备分配器切出新块的分配次数。
+    pub fn misses(&self) -> usize {
+        self.classes.iter().map(|class| class.carves).sum()
+    }
+
+    /// 不属于任何类、绕过缓存的分配次数，即 `large_allocs`。
+    pub fn bypasses(&self) -> usize {
+        self.large_allocs
+    }
+
+    /// 命中占所有分配（包括绕过缓存的大块）的千分比，还没有分配过时返回 `None`。
+    pub fn hit_permille(&self) -> Option<usize> {
+        permille(self.hits(), self.hits() + self.misses() + self.bypasses())
+    }
+}
+
+/// [`FixedSizeBlockAllocator::waste`] 返回的一个类的内部碎片：块大小超出请求大小的字节数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClassWaste {
+    /// 这个类的块大小。
+    pub block_size: usize,
+    /// 从这个类分配的次数。
+    pub allocations: usize,
+    /// 所有分配浪费的字节数之和。
+    pub wasted: usize,
+    /// 尚未释放的块数。
+    pub live: usize,
+    /// 尚未释放的分配浪费的字节数。
+    pub live_wasted: usize,
+}
+
+impl ClassWaste {
+    /// 所有分配浪费的字节占分配出去的块的千分比，还没有分配过时返回 `None`。
+    pub fn wasted_permille(&self) -> Option<usize> {
+        permille(self.wasted, self.allocations * self.block_size)
+    }
+
+    /// 尚未释放的分配浪费的字节占这些块的千分比，没有尚未释放的块时返回 `None`。
+    pub fn live_wasted_permille(&self) -> Option<usize> {
+        permille(self.live_wasted, self.live * self.block_size)
+    }
+}
+
+/// 把千分比打印成一位小数的百分数，没有时打印 `-`。
+struct Percent(Option<usize>);
+
+impl fmt::Display for Percent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(permille) => write!(f, "{}.{}%", permille / 10, permille % 10),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+/// [`FixedSizeBlockAllocator::live_counts`] 返回的尚未释放的分配个数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveCounts<const N: usize = CLASS_COUNT> {
+    /// 各个类的块大小，从小到大排列。
+    pub block_sizes: [usize; N],
+    /// 按块大小从小到大排列的各个类中尚未释放的块数。
+    pub classes: [usize; N],
+    /// 直接交给后备分配器或页分配函数、尚未释放的大块个数。
+    pub large: usize,
+}
+
+impl<const N: usize> LiveCounts<N> {
+    /// 是否所有分配都已经释放。
+    pub fn is_balanced(&self) -> bool {
+        self.large == 0 && self.classes.iter().all(|&live| live == 0)
+    }
+}
+
+/// 列出还有尚未释放的分配的类。
+impl<const N: usize> fmt::Display for LiveCounts<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        let classes = self.block_sizes.iter().zip(self.classes.iter());
+        for (block_size, &live) in classes.filter(|&(_, &live)| live > 0) {
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} live in the {}-byte class", live, block_size)?;
+            first = false;
+        }
+        if self.large > 0 {
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} live in the fallback heap", self.large)?;
+        }
+        Ok(())
+    }
+}
+
+/// [`SizeHistogram`] 的桶数：第 `k` 个桶统计 `2^k` 到 `2^(k+1) - 1` 字节的请求。
+pub const SIZE_BUCKETS: usize = usize::BITS as usize;
+
+/// 按请求大小的以 2 为底的对数分桶的分配次数，用于挑选块大小。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeHistogram {
+    /// 每个桶的请求次数。
+    pub counts: [usize; SIZE_BUCKETS],
+}
+
+impl SizeHistogram {
+    const fn new() -> Self {
+        SizeHistogram {
+            counts: [0; SIZE_BUCKETS],
+        }
+    }
+
+    /// 返回 `size` 字节的请求所在的桶，0 字节算在第一个桶里。
+    pub fn bucket_of(size: usize) -> usize {
+        (usize::BITS - 1 - size.max(1).leading_zeros()) as usize
+    }
+
+    /// 记录的请求总数。
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    #[inline]
+    fn record(&mut self, size: usize) {
+        self.counts[Self::bucket_of(size)] += 1;
+    }
+
+    /// 把非空的桶逐行打印到 `writer`，每行是桶的大小范围和请求次数。不分配内存。
+    pub fn dump(&self, writer: &mut impl fmt::Write) -> fmt::Result {
+        if self.total() == 0 {
+            return writeln!(writer, "  no allocations");
+        }
+        for (bucket, &count) in self.counts.iter().enumerate() {
+            if count > 0 {
+                let low = 1usize << bucket;
+                let high = low | (low - 1);
+                writeln!(writer, "  {:>8}..={:<8} bytes: {}", low, high, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `self_test` 最多记住这么多个空闲块的地址，用来发现出现在两个位置的块。
+const SELF_TEST_SCAN: usize = 256;
+
+/// `self_test` 写入每个测试块再读回的字节。
+const SELF_TEST_PATTERN: u8 = 0xA5;
+
+/// [`FixedSizeBlockAllocator::self_test`] 发现的第一个问题。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfTestError {
+    /// 出问题的类的块大小。
+    pub block_size: usize,
+    /// 出问题的指针，问题不在某个指针上时为 0。
+    pub addr: usize,
+    pub reason: SelfTestReason,
+}
+
+/// 自检失败的具体原因。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestReason {
+    /// 空闲列表中的指针不在堆内，或者块越过了堆的结尾。
+    OutsideHeap,
+    /// 空闲列表中的指针没有按块大小对齐。
+    Misaligned,
+    /// 同一个块在空闲列表中出现了两次。
+    Duplicate,
+    /// 空闲列表的长度与统计中缓存的块数不符，多半是出现了环或者列表被截断。
+    CountMismatch,
+    /// 这个类分配不到测试用的块。
+    AllocationFailed,
+    /// 测试块读回的内容与写入的不同。
+    PatternMismatch,
+}
+
+impl fmt::Display for SelfTestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "self-test of the {}-byte class failed at {:#x}: {:?}",
+            self.block_size, self.addr, self.reason
+        )
+    }
+}
+
 struct ListNode {
     next: Option<&'static mut ListNode>,
 }
-pub struct FixedSizeBlockAllocator {
-    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
-    fallback_allocator: linked_list_allocator::Heap,
+
+/// 启用 `heap-verify` feature 时，不小于两个 `usize` 的空闲块在第二个字中存放这个值与
+/// 块地址取反后的异或。
+#[cfg(feature = "heap-verify")]
+const BLOCK_MAGIC: usize = 0xB10C_F4EE;
+
+/// 空闲块开头被空闲列表占用的字节数：`ListNode`，以及启用 `heap-verify` 时的 magic。
+const fn free_header_len(block_size: usize) -> usize {
+    let word = mem::size_of::<usize>();
+    if cfg!(feature = "heap-verify") && block_size >= 2 * word {
+        mem::size_of::<ListNode>() + word
+    } else {
+        mem::size_of::<ListNode>()
+    }
+}
+
+/// `allocate` 为 `size` 字节的请求分配的内存中需要清零的字节数，`pristine` 是它的第二个返回值。
+pub(super) fn dirty_len(size: usize, pristine: bool) -> usize {
+    // 全零的块只可能在开头留有空闲列表的记录；更小的请求也可能分到带 magic 的块
+    if pristine {
+        free_header_len(usize::MAX).min(size)
+    } else {
+        size
+    }
+}
+
+/// 启用 `heap-verify` 时在 `ptr` 处 `block_size` 字节的空闲块中写入 magic，否则什么也不做。
+#[inline]
+unsafe fn write_magic(ptr: *mut u8, block_size: usize) {
+    #[cfg(feature = "heap-verify")]
+    if block_size >= 2 * mem::size_of::<usize>() {
+        (ptr as *mut usize)
+            .add(1)
+            .write(BLOCK_MAGIC ^ !(ptr as usize));
+    }
+    #[cfg(not(feature = "heap-verify"))]
+    let _ = (ptr, block_size);
+}
+
+/// 启用 `heap-verify` 时检查刚从空闲列表取出的块中的 magic，不符说明块在缓存期间被写坏，
+/// 以块大小和地址 panic，而不是继续跟随其中的 `next`。
+#[inline]
+unsafe fn verify_magic(ptr: *mut u8, block_size: usize) {
+    #[cfg(feature = "heap-verify")]
+    if block_size >= 2 * mem::size_of::<usize>() {
+        let magic = (ptr as *const usize).add(1).read();
+        if magic != BLOCK_MAGIC ^ !(ptr as usize) {
+            panic!(
+                "free list corrupted: {}-byte block {:p} has magic {:#x}",
+                block_size, ptr, magic
+            );
+        }
+    }
+    #[cfg(not(feature = "heap-verify"))]
+    let _ = (ptr, block_size);
+}
+
+/// 启用 `heap-debug` 时从堆的末尾切出的表：后备堆中每 `granule` 字节对应一个字节，
+/// 记录从这里开始的尚未释放的块所属的类的下标加一，其他位置是零。
+#[cfg(feature = "heap-debug")]
+struct ClassTags {
+    tags: &'static mut [u8],
+    /// 表中第一项对应的地址除以 `granule`。
+    base: usize,
+    /// 最小的块大小；每个类的块都按它对齐，所以不同的块落在不同的项上。
+    granule: usize,
+}
+
+#[cfg(feature = "heap-debug")]
+impl ClassTags {
+    const fn new() -> Self {
+        ClassTags {
+            tags: &mut [],
+            base: 0,
+            granule: 1,
+        }
+    }
+
+    /// 在堆的末尾切出覆盖堆的其余部分的表并清零，返回留给后备分配器的字节数。
+    ///
+    /// 调用者必须保证给定的堆边界是有效的，并且堆未被使用。
+    unsafe fn reserve(&mut self, heap_start: usize, heap_size: usize, granule: usize) -> usize {
+        let len = (heap_size / (granule + 1) + 2).min(heap_size);
+        let remaining = heap_size - len;
+        let tags = core::slice::from_raw_parts_mut((heap_start + remaining) as *mut u8, len);
+        tags.fill(0);
+        self.tags = tags;
+        self.base = heap_start / granule;
+        self.granule = granule;
+        remaining
+    }
+
+    /// `ptr` 对应的项，不在后备堆中时返回 `None`。
+    fn slot(&mut self, ptr: *mut u8) -> Option<&mut u8> {
+        let index = (ptr as usize / self.granule).checked_sub(self.base)?;
+        self.tags.get_mut(index)
+    }
+}
+
+/// 不属于任何类的 `layout` 向后备分配器请求时使用的布局：对齐至少是 [`MIN_ALIGN`]。
+fn large_layout(layout: Layout) -> Layout {
+    // 只有接近 `isize::MAX` 的大小才会对齐失败，这样的请求反正分配不到
+    layout.align_to(MIN_ALIGN).unwrap_or(layout)
+}
+
+/// 一个分配器使用的大小类：块大小，以及由所需大小直接查出类的表。
+#[derive(Clone, Copy)]
+struct SizeTable<const N: usize> {
+    /// 从小到大排列的块大小。
+    sizes: [usize; N],
+    /// 第 `k` 项是块大小不小于 `1 << k` 的最小的类的下标，没有这样的类时是 `N`。
+    by_shift: [u8; usize::BITS as usize],
+}
+
+impl<const N: usize> SizeTable<N> {
+    /// 检查 `sizes` 并建立查找表。
+    ///
+    /// `sizes` 必须非空、严格递增、每一项都是 2 的幂，并且最小的块放得下 `ListNode`，
+    /// 否则 panic；在 `static` 或 `const` 的初始化中，这会成为编译错误。
+    const fn new(sizes: [usize; N]) -> Self {
+        assert!(N > 0, "block size table is empty");
+        assert!(
+            sizes[0] >= mem::size_of::<ListNode>(),
+            "smallest block size cannot hold a free list node"
+        );
+        let mut i = 0;
+        while i < N {
+            assert!(
+                sizes[i].is_power_of_two(),
+                "block sizes must be powers of two"
+            );
+            assert!(
+                i == 0 || sizes[i - 1] < sizes[i],
+                "block sizes must be sorted"
+            );
+            i += 1;
+        }
+        // 严格递增的 2 的幂不超过 `usize::BITS` 个，下标放得进 `u8`
+        let mut by_shift = [N as u8; usize::BITS as usize];
+        let (mut shift, mut class) = (0, 0);
+        while shift < by_shift.len() {
+            while class < N && sizes[class] < 1 << shift {
+                class += 1;
+            }
+            by_shift[shift] = class as u8;
+            shift += 1;
+        }
+        SizeTable { sizes, by_shift }
+    }
+
+    /// Choose an appropriate block size for the given layout.
+    ///
+    /// Returns an index into the `sizes` array.
+    ///
+    /// 块大小都是 2 的幂，所以只需要把所需大小向上取到 2 的幂，用它以 2 为底的对数查表，
+    /// 不需要逐个比较。
+    ///
+    /// 对齐超过最大块的布局即使很小也不属于任何类，交给后备分配器，对齐无法满足时分配失败。
+    /// 按 `GlobalAlloc` 的约定释放时的布局与分配时相同，所以 `alloc` 和 `dealloc`
+    /// 总是为同一个指针选出同一条路径。
+    fn index(&self, layout: &Layout) -> Option<usize> {
+        // 块大小不小于 8，不启用 `min-align-16` 时 `MIN_ALIGN` 不改变选出的类
+        let required_block_size = layout.size().max(layout.align()).max(MIN_ALIGN);
+        // 零大小的布局也落在这里：所需大小就是对齐
+        let index = if required_block_size > self.sizes[N - 1] {
+            None
+        } else {
+            let shift = required_block_size.next_power_of_two().trailing_zeros();
+            Some(self.by_shift[shift as usize] as usize)
+        };
+        debug_assert_eq!(
+            index,
+            self.sizes.iter().position(|&s| s >= required_block_size)
+        );
+        index
+    }
+
+    /// 返回块大小正好是 `block_size` 的类的下标。
+    fn position(&self, block_size: usize) -> Option<usize> {
+        self.sizes.iter().position(|&s| s == block_size)
+    }
+
+    /// 第 `index` 个类的块使用的布局：对齐等于块大小。
+    fn block_layout(&self, index: usize) -> Layout {
+        let block_size = self.sizes[index];
+        // only works if all block sizes are a power of 2
+        Layout::from_size_align(block_size, block_size).unwrap()
+    }
+}
+
+/// 按 `N` 个大小类分配的分配器，默认使用 [`BLOCK_SIZES`]。
+pub struct FixedSizeBlockAllocator<const N: usize = CLASS_COUNT> {
+    table: SizeTable<N>,
+    list_heads: [Option<&'static mut ListNode>; N],
+    /// 每个空闲列表允许的最大长度。
+    cache_caps: [usize; N],
+    /// 用 `set_cache_cap` 固定了上限、`tick` 不再调整的类。
+    fixed_caps: [bool; N],
+    /// 每个类最近的分配次数，每次 `tick` 减半。
+    recent_allocs: [usize; N],
+    /// 每个类在空闲列表为空时一次从后备分配器切出的块数。
+    batch_sizes: [usize; N],
+    /// 每个类的统计，`cached` 同时就是空闲列表的长度。
+    class_stats: [ClassStats; N],
+    /// 每个类所有分配和尚未释放的分配中块大小超出请求大小的字节数。
+    wasted: [usize; N],
+    live_wasted: [usize; N],
+    large_allocs: usize,
+    /// 直接交给后备分配器或页分配函数、尚未释放的大块个数。
+    large_live: usize,
+    /// 不属于任何类、不小于 `page_threshold` 字节的请求使用的页分配函数。
+    pages: Option<PageAllocator>,
+    page_threshold: usize,
+    /// 通过 `pages` 分配、尚未释放的页数。
+    pages_in_use: usize,
+    /// 所有分配请求的大小。
+    request_sizes: SizeHistogram,
+    /// 比所有类都大的请求的大小。
+    fallback_sizes: SizeHistogram,
+    /// 从这个地址到堆尾的后备堆自 `init_zeroed` 以来从未分配出去过，仍然是全零的，
+    /// 只有后备分配器可能恰好在这个地址留下的空闲记录例外。
+    pristine_start: usize,
+    /// 每个类最近切出的一批全零的块中还没有分配出去的部分，除了开头空闲列表占用的字节都是零。
+    ///
+    /// 这些块在空闲列表中按地址顺序排列，所以只需要记录下一个块和这一批的结尾。
+    fresh_blocks: [(usize, usize); N],
+    /// 启用 `heap-slab` 时较小的类使用的 slab。
+    #[cfg(feature = "heap-slab")]
+    slabs: slab::Slabs<N>,
+    /// 启用 `heap-quarantine` 时释放的块在重新使用之前排队的隔离区。
+    #[cfg(feature = "heap-quarantine")]
+    quarantine: quarantine::Quarantine<N>,
+    /// 启用 `heap-debug` 时每个尚未释放的块所属的类，用于发现布局不符的释放。
+    #[cfg(feature = "heap-debug")]
+    tags: ClassTags,
+    /// 被忽略的空指针释放次数。
+    null_frees: usize,
+    fallback_allocator: fallback::Heap,
 }
 impl FixedSizeBlockAllocator {
-    /// 创建一个空的 FixedSizeBlockAllocator。
+    /// 创建一个使用 [`BLOCK_SIZES`] 的空的 FixedSizeBlockAllocator。
     pub const fn new() -> Self {
+        Self::with_block_sizes(BLOCK_SIZES)
+    }
+}
+impl<const N: usize> FixedSizeBlockAllocator<N> {
+    /// 创建一个使用给定块大小的空的 FixedSizeBlockAllocator。
+    ///
+    /// `block_sizes` 必须非空、严格递增、每一项都是 2 的幂并且不小于 8，否则 panic；
+    /// 在 `static` 或 `const` 的初始化中调用时，这会成为编译错误。
+    pub const fn with_block_sizes(block_sizes: [usize; N]) -> Self {
         const EMPTY: Option<&'static mut ListNode> = None;
         FixedSizeBlockAllocator {
-            list_heads: [EMPTY; BLOCK_SIZES.len()],
-            fallback_allocator: linked_list_allocator::Heap::empty(),
+            table: SizeTable::new(block_sizes),
+            list_heads: [EMPTY; N],
+            cache_caps: [DEFAULT_CACHE_CAP; N],
+            fixed_caps: [false; N],
+            recent_allocs: [0; N],
+            batch_sizes: Self::default_batch_sizes(&block_sizes),
+            class_stats: Self::empty_stats(&block_sizes),
+            wasted: [0; N],
+            live_wasted: [0; N],
+            large_allocs: 0,
+            large_live: 0,
+            pages: None,
+            page_threshold: 0,
+            pages_in_use: 0,
+            request_sizes: SizeHistogram::new(),
+            fallback_sizes: SizeHistogram::new(),
+            pristine_start: usize::MAX,
+            fresh_blocks: [(0, 0); N],
+            #[cfg(feature = "heap-slab")]
+            slabs: slab::Slabs::new(),
+            #[cfg(feature = "heap-quarantine")]
+            quarantine: quarantine::Quarantine::new(),
+            #[cfg(feature = "heap-debug")]
+            tags: ClassTags::new(),
+            null_frees: 0,
+            fallback_allocator: fallback::empty(),
         }
     }
 
+    /// 返回从小到大排列的各个类的块大小。
+    pub fn block_sizes(&self) -> &[usize; N] {
+        &self.table.sizes
+    }
+
+    /// 返回每个非零大小的分配至少满足的对齐，也就是 [`MIN_ALIGN`]。
+    ///
+    /// 每批块和每个 slab 都按块大小对齐地从后备分配器切出，所以类的块总是按块大小对齐。
+    pub fn min_alignment(&self) -> usize {
+        MIN_ALIGN
+    }
+
     /// 使用给定的堆边界初始化分配器。
     ///
     /// 这个函数是不安全的，因为调用者必须保证给定的堆边界是有效的，并且堆未被使用。此方法只能调用一次。
+    ///
+    /// 启用 `heap-debug` 时堆的末尾用来记录每个块所属的类，大约占堆的 1 / (最小块大小 + 1)。
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.fallback_allocator.init(heap_start, heap_size);
+        self.init_with(heap_start, heap_size, false);
     }
-    /// Allocates using the fallback allocator.
-    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
-            Err(_) => ptr::null_mut(),
+
+    /// 与 [`init`](Self::init) 相同，但调用者还保证整个堆已经被清零（例如刚映射的页面），
+    /// 这样 `alloc_zeroed` 就可以跳过从未分配过的内存的清零。
+    pub unsafe fn init_zeroed(&mut self, heap_start: usize, heap_size: usize) {
+        self.init_with(heap_start, heap_size, true);
+        self.pristine_start = heap_start;
+    }
+
+    unsafe fn init_with(&mut self, heap_start: usize, heap_size: usize, zeroed: bool) {
+        #[cfg(feature = "heap-debug")]
+        let heap_size = self
+            .tags
+            .reserve(heap_start, heap_size, self.table.sizes[0]);
+        if zeroed {
+            Fallback::init_zeroed(&mut self.fallback_allocator, heap_start, heap_size);
+        } else {
+            Fallback::init(&mut self.fallback_allocator, heap_start, heap_size);
         }
     }
-    /// Choose an appropriate block size for the given layout.
+
+    /// 在 `init` 之后把 `start..start + size` 加入后备堆。这段内存不保证为零。
     ///
-    /// Returns an index into the `BLOCK_SIZES` array.
-    fn list_index(layout: &Layout) -> Option<usize> {
-        let required_block_size = layout.size().max(layout.align());
-        BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+    /// 这个函数是不安全的，因为调用者必须保证给定的内存是有效的并且未被使用。后备分配器不接受
+    /// 这段内存时 panic：默认的 [`LinkedListAllocator`](super::linked_list::LinkedListAllocator)
+    /// 要求它按 `usize` 对齐、不与堆重叠；启用 `external-fallback` 时它必须紧接在后备堆的结尾，
+    /// 而启用 `heap-debug` 时那里是记录块所属的类的表，所以不能扩展。扩展出的内存中的块
+    /// 不在这张表里，释放时不检查布局。
+    pub unsafe fn extend(&mut self, start: usize, size: usize) {
+        Fallback::extend(&mut self.fallback_allocator, start, size);
+        if start + size > self.pristine_start {
+            self.pristine_start = start + size;
+        }
     }
-}
-unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.lock();
-        match FixedSizeBlockAllocator::list_index(&layout) {
+
+    /// 设置大小为 `block_size` 的类最多缓存多少个空闲块。
+    ///
+    /// 只影响之后的释放：已经缓存的块不会立即归还。小于 [`MIN_RETURNABLE_BLOCK`] 的块总是留在缓存中。
+    /// 启用 `heap-slab` 时使用 slab 的类不受缓存上限的限制，slab 全部空闲时就会归还。
+    /// 设置之后 [`tick`](Self::tick) 不再调整这个类的上限。
+    /// `block_size` 不是任何一个类的大小时 panic。
+    pub fn set_cache_cap(&mut self, block_size: usize, cap: usize) {
+        let index = self
+            .table
+            .position(block_size)
+            .expect("no size class with this block size");
+        self.cache_caps[index] = cap;
+        self.fixed_caps[index] = true;
+    }
+
+    /// 返回大小为 `block_size` 的类最多缓存的空闲块数，没有这个类时返回 `None`。
+    pub fn cache_cap(&self, block_size: usize) -> Option<usize> {
+        self.table
+            .position(block_size)
+            .map(|index| self.cache_caps[index])
+    }
+
+    /// 设置大小为 `block_size` 的类在空闲列表为空时一次切出多少个块，0 按 1 处理。
+    ///
+    /// 一批块只占用后备分配器的一次分配，第一个块用于这次分配，其余的放进空闲列表。
+    /// 启用 `heap-slab` 时使用 slab 的类总是一次切出一个 slab。
+    /// `block_size` 不是任何一个类的大小时 panic。
+    pub fn set_batch_size(&mut self, block_size: usize, batch: usize) {
+        let index = self
+            .table
+            .position(block_size)
+            .expect("no size class with this block size");
+        self.batch_sizes[index] = batch.max(1);
+    }
+
+    /// 返回大小为 `block_size` 的类一次切出的块数，没有这个类时返回 `None`。
+    pub fn batch_size(&self, block_size: usize) -> Option<usize> {
+        self.table
+            .position(block_size)
+            .map(|index| self.batch_sizes[index])
+    }
+
+    /// 返回大小为 `block_size` 的类当前缓存的空闲块数，没有这个类时返回 `None`。
+    pub fn cached_blocks(&self, block_size: usize) -> Option<usize> {
+        self.table
+            .position(block_size)
+            .map(|index| self.class_stats[index].cached)
+    }
+
+    /// 设置启用 `heap-quarantine` 时每个类的隔离区最多容纳多少个释放的块，0 关闭隔离。
+    ///
+    /// 超出新深度的块立即按释放的顺序放回空闲列表。
+    #[cfg(feature = "heap-quarantine")]
+    pub fn set_quarantine_depth(&mut self, depth: usize) {
+        self.quarantine.set_depth(depth);
+        for index in 0..N {
+            while let Some(ptr) = self.quarantine.pop_excess(index, self.table.sizes[index]) {
+                // 隔离区中的块已经释放，没有别人使用
+                unsafe { self.release_quarantined(index, ptr) };
+            }
+        }
+    }
+
+    /// 返回每个类的隔离区最多容纳的块数。
+    #[cfg(feature = "heap-quarantine")]
+    pub fn quarantine_depth(&self) -> usize {
+        self.quarantine.depth()
+    }
+
+    /// 返回所有隔离区中的块占用的字节数。
+    #[cfg(feature = "heap-quarantine")]
+    pub fn quarantined_bytes(&self) -> usize {
+        (0..N)
+            .map(|index| self.quarantine.len(index) * self.table.sizes[index])
+            .sum()
+    }
+
+    /// 让不属于任何类、不小于 `threshold` 字节的请求按整页交给 `pages`，后备堆只用来切块和满足
+    /// 其余的大块。对齐超过 `PAGE_SIZE` 的请求仍然交给后备分配器。
+    ///
+    /// 释放时按同样的规则由布局判断块的来源，所以只能在还没有尚未释放的大块时设置，否则 panic。
+    pub fn set_page_allocator(&mut self, pages: PageAllocator, threshold: usize) {
+        assert_eq!(
+            self.large_live, 0,
+            "page allocator set while large allocations are live"
+        );
+        self.pages = Some(pages);
+        self.page_threshold = threshold;
+    }
+
+    /// 返回被忽略的空指针释放次数。
+    pub fn null_frees(&self) -> usize {
+        self.null_frees
+    }
+
+    /// 返回通过页分配函数分配、尚未释放的页数。
+    pub fn pages_in_use(&self) -> usize {
+        self.pages_in_use
+    }
+
+    /// 不属于任何类的 `layout` 交给页分配函数时返回它和所需的页数。
+    fn page_path(&self, layout: &Layout) -> Option<(PageAllocator, usize)> {
+        let pages = self.pages?;
+        if layout.size() < self.page_threshold || layout.align() > PAGE_SIZE {
+            return None;
+        }
+        Some((pages, align_up(layout.size(), PAGE_SIZE) / PAGE_SIZE))
+    }
+
+    /// 返回各个类和大块分配的统计。只复制计数，不分配内存。
+    pub fn stats(&self) -> BlockStats<N> {
+        BlockStats {
+            classes: self.class_stats,
+            large_allocs: self.large_allocs,
+            fallback_size: self.fallback_size(),
+            fallback_used: self.fallback_used(),
+            fallback_free: self.fallback_free(),
+        }
+    }
+
+    /// 返回每个类的块大小超出请求大小的字节数，用于判断是否值得增加中间的类。
+    ///
+    /// 释放时按布局中的大小扣除，所以对 `GlobalAlloc` 的使用是精确的；通过 `Allocator`
+    /// 以大于请求的大小释放时，`live_wasted` 会偏大。
+    pub fn waste(&self) -> [ClassWaste; N] {
+        let mut waste = [ClassWaste::default(); N];
+        for (index, class) in waste.iter_mut().enumerate() {
+            let stats = &self.class_stats[index];
+            *class = ClassWaste {
+                block_size: stats.block_size,
+                allocations: stats.hits + stats.carves,
+                wasted: self.wasted[index],
+                live: stats.live,
+                live_wasted: self.live_wasted[index],
+            };
+        }
+        waste
+    }
+
+    /// 返回所有尚未释放的分配中块大小超出请求大小的字节数。
+    pub fn internal_fragmentation_bytes(&self) -> usize {
+        self.live_wasted.iter().sum()
+    }
+
+    /// 把分配过的每个类浪费的字节和比例打印到 `writer`。不分配内存。
+    pub fn dump_waste(&self, writer: &mut impl fmt::Write) -> fmt::Result {
+        for class in self.waste().iter().filter(|class| class.allocations > 0) {
+            writeln!(
+                writer,
+                "  {:>5}-byte class: {} allocations wasted {} bytes ({}), \
+                 {} live blocks waste {} bytes ({})",
+                class.block_size,
+                class.allocations,
+                class.wasted,
+                Percent(class.wasted_permille()),
+                class.live,
+                class.live_wasted,
+                Percent(class.live_wasted_permille())
+            )?;
+        }
+        Ok(())
+    }
+
+    /// 返回每个类以及后备分配器中尚未释放的分配个数，用于检查泄漏。
+    pub fn live_counts(&self) -> LiveCounts<N> {
+        let mut classes = [0; N];
+        for (live, stats) in classes.iter_mut().zip(self.class_stats.iter()) {
+            *live = stats.live;
+        }
+        LiveCounts {
+            block_sizes: self.table.sizes,
+            classes,
+            large: self.large_live,
+        }
+    }
+
+    /// 返回所有分配请求的大小分布，包括失败的请求。
+    pub fn size_histogram(&self) -> SizeHistogram {
+        self.request_sizes
+    }
+
+    /// 返回比所有类都大、交给后备分配器或页分配函数的请求的大小分布，包括失败的请求。
+    ///
+    /// 这里集中的桶说明值得为它们增加一个类。
+    pub fn fallback_histogram(&self) -> SizeHistogram {
+        self.fallback_sizes
+    }
+
+    /// 两个大小不为零的布局是否落在同一个类里，是的话按新的大小重新计算这个块浪费的字节数。
+    pub(super) fn resize_in_place(&mut self, old_layout: &Layout, new_layout: &Layout) -> bool {
+        if old_layout.size() == 0 || new_layout.size() == 0 {
+            return false;
+        }
+        let class = self.table.index(old_layout);
+        match class {
+            Some(index) if class == self.table.index(new_layout) => {
+                let live_wasted = &mut self.live_wasted[index];
+                *live_wasted = (*live_wasted + old_layout.size()).saturating_sub(new_layout.size());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// 把两个大小分布打印到 `writer`。不分配内存。
+    pub fn dump_histograms(&self, writer: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(writer, "all allocations:")?;
+        self.request_sizes.dump(writer)?;
+        writeln!(writer, "fallback allocations:")?;
+        self.fallback_sizes.dump(writer)
+    }
+
+    /// 每个类默认一次切出一页的块，至少一个，最多 `MAX_DEFAULT_BATCH` 个。
+    const fn default_batch_sizes(block_sizes: &[usize; N]) -> [usize; N] {
+        let mut batches = [1; N];
+        let mut i = 0;
+        while i < N {
+            let batch = DEFAULT_BATCH_BYTES / block_sizes[i];
+            if batch > MAX_DEFAULT_BATCH {
+                batches[i] = MAX_DEFAULT_BATCH;
+            } else if batch > 1 {
+                batches[i] = batch;
+            }
+            i += 1;
+        }
+        batches
+    }
+
+    /// 从后备分配器为第 `index` 个类切出一批块，返回第一个块，其余的放进空闲列表。
+    ///
+    /// 整批放不下时退回到只切一个块。同一批的块也可以逐个还给后备分配器，
+    /// 因为后备分配器释放时只按给定的范围记录空闲内存。
+    /// 第二个返回值说明这一批是否切自从未分配过的内存；是的话整批块都记作全零。
+    fn carve_batch(&mut self, index: usize) -> (*mut u8, bool) {
+        let block_size = self.table.sizes[index];
+        let batch = self.batch_sizes[index];
+        let chunk = block_size
+            .checked_mul(batch)
+            .and_then(|size| Layout::from_size_align(size, block_size).ok());
+        if let (true, Some(chunk)) = (batch > 1, chunk) {
+            let (start, pristine) = self.fallback_alloc_fresh(chunk);
+            if !start.is_null() {
+                // 倒着压入，让空闲列表按地址从低到高排列
+                for i in (1..batch).rev() {
+                    // 这一批块刚刚切出，没有别人使用
+                    unsafe { self.push_block(index, start.add(i * block_size)) };
+                }
+                // 启用 `heap-poison` 时放进列表的块已经被填充，不再是全零的
+                if pristine && !cfg!(feature = "heap-poison") {
+                    let start = start as usize;
+                    self.fresh_blocks[index] = (start + block_size, start + chunk.size());
+                }
+                return (start, pristine);
+            }
+        }
+        self.fallback_alloc_fresh(self.table.block_layout(index))
+    }
+
+    /// 为 `layout` 分配内存，第二个返回值说明除了开头空闲列表占用的字节以外是否都是零。
+    pub(super) fn allocate(&mut self, layout: Layout) -> (*mut u8, bool) {
+        self.request_sizes.record(layout.size());
+        let class = self.table.index(&layout);
+        let (ptr, pristine) = match class {
+            #[cfg(feature = "heap-slab")]
+            Some(index) if self.uses_slabs(index) => (self.slab_alloc(index), false),
             Some(index) => {
-                match allocator.list_heads[index].take() {
+                match self.list_heads[index].take() {
                     Some(node) => {
-                        allocator.list_heads[index] = node.next.take();
-                        node as *mut ListNode as *mut u8
+                        let ptr = node as *mut ListNode as *mut u8;
+                        // 先检查 magic，再跟随块中的 `next`
+                        unsafe { verify_magic(ptr, self.table.sizes[index]) };
+                        self.list_heads[index] = unsafe { (*(ptr as *mut ListNode)).next.take() };
+                        let stats = &mut self.class_stats[index];
+                        stats.hits += 1;
+                        stats.cached -= 1;
+                        stats.live += 1;
+                        unsafe { self.verify_poison(index, ptr) };
+                        // 回收的块总在表头，只有轮到一批中的下一个块时它才是全零的，
+                        // 除非释放时已经清零
+                        let (next, end) = self.fresh_blocks[index];
+                        let fresh = ptr as usize == next && next < end;
+                        if fresh {
+                            self.fresh_blocks[index].0 += self.table.sizes[index];
+                        }
+                        (ptr, fresh || FREE_BLOCKS_ZEROED)
                     }
                     None => {
-                        // no block exists in list => allocate new block
-                        let block_size = BLOCK_SIZES[index];
-                        // only works if all block sizes are a power of 2
-                        let block_align = block_size;
-                        let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                        allocator.fallback_alloc(layout)
+                        // no block exists in list => allocate a batch of new blocks
+                        let (ptr, pristine) = self.carve_batch(index);
+                        if !ptr.is_null() {
+                            let stats = &mut self.class_stats[index];
+                            stats.carves += 1;
+                            stats.live += 1;
+                        }
+                        (ptr, pristine)
                     }
                 }
             }
-            None => allocator.fallback_alloc(layout),
+            None => {
+                self.fallback_sizes.record(layout.size());
+                let (ptr, pristine) = match self.page_path(&layout) {
+                    Some((pages, count)) => match (pages.alloc_pages)(count) {
+                        Some(addr) => {
+                            self.pages_in_use += count;
+                            (addr as *mut u8, false)
+                        }
+                        None => (ptr::null_mut(), false),
+                    },
+                    None => self.fallback_alloc_fresh(large_layout(layout)),
+                };
+                if !ptr.is_null() {
+                    self.large_allocs += 1;
+                    self.large_live += 1;
+                }
+                (ptr, pristine)
+            }
+        };
+        // 每个类的块都按块大小对齐，块大小又不小于请求的对齐；大块的对齐由后备分配器或者页保证
+        if let (Some(index), false) = (class, ptr.is_null()) {
+            debug_assert_eq!(ptr as usize % self.table.sizes[index], 0);
+            self.tag_block(index, ptr);
+            self.recent_allocs[index] += 1;
+            let waste = self.table.sizes[index] - layout.size();
+            self.wasted[index] += waste;
+            self.live_wasted[index] += waste;
         }
+        debug_assert!(is_aligned(ptr as usize, layout.align()));
+        (ptr, pristine)
     }
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut allocator = self.lock();
-        match FixedSizeBlockAllocator::list_index(&layout) {
+
+    /// 释放 `allocate` 为 `layout` 分配的 `ptr`，`layout` 的大小不为零。
+    ///
+    /// 调用者必须保证 `ptr` 是用同样的布局分配、尚未释放的。空指针被忽略；交给后备分配器的大块
+    /// 不在堆内时 panic。
+    pub(super) unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        // 和 libc 的 free 一样忽略空指针，只记下次数
+        if ptr.is_null() {
+            self.null_frees += 1;
+            return;
+        }
+        let class = self.table.index(&layout);
+        if let Some(index) = class {
+            self.check_double_free(index, ptr);
+        }
+        self.check_layout(class, ptr, &layout);
+        match class {
             Some(index) => {
-                let new_node = ListNode {
-                    next: allocator.list_heads[index].take(),
-                };
-                // verify that block has size and alignment required for storing node
-                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
-                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
-                let new_node_ptr = ptr as *mut ListNode;
-                new_node_ptr.write(new_node);
-                allocator.list_heads[index] = Some(&mut *new_node_ptr);
+                self.class_stats[index].live -= 1;
+                let waste = self.table.sizes[index] - layout.size();
+                self.live_wasted[index] = self.live_wasted[index].saturating_sub(waste);
+                #[cfg(feature = "heap-quarantine")]
+                {
+                    let block_size = self.table.sizes[index];
+                    self.scrub(index, ptr);
+                    self.quarantine.push(index, block_size, ptr);
+                    // 隔离区没满时块留在队列中，否则放回最早释放的块
+                    if let Some(oldest) = self.quarantine.pop_excess(index, block_size) {
+                        self.release_quarantined(index, oldest);
+                    }
+                }
+                #[cfg(not(feature = "heap-quarantine"))]
+                self.recycle(index, ptr);
             }
             None => {
-                let ptr = NonNull::new(ptr).unwrap();
-                allocator.fallback_allocator.deallocate(ptr, layout);
+                let pages = self.page_path(&layout);
+                if pages.is_none() {
+                    self.check_heap_range(ptr, &layout);
+                }
+                #[cfg(feature = "zero-on-free")]
+                scrub_bytes(ptr, layout.size());
+                match pages {
+                    Some((pages, count)) => {
+                        (pages.free_pages)(ptr as usize, count);
+                        self.pages_in_use -= count;
+                    }
+                    None => {
+                        // 上面已经排除了空指针
+                        let ptr = NonNull::new_unchecked(ptr);
+                        let layout = large_layout(layout);
+                        Fallback::deallocate(&mut self.fallback_allocator, ptr, layout);
+                    }
+                }
+                self.large_live -= 1;
+            }
+        }
+    }
+
+    /// 检查要交给后备分配器释放的 `ptr` 连同 `layout` 都在后备堆的同一段内存中，否则以指针和布局
+    /// panic，而不是让后备分配器把它串进自己的空闲链表。`extend` 加入的内存和原来的堆之间的
+    /// 空隙不算在内。
+    fn check_heap_range(&self, ptr: *mut u8, layout: &Layout) {
+        if !self.in_heap(ptr as usize, layout.size()) {
+            panic!("dealloc of {:p} with {:?} outside the heap", ptr, layout);
+        }
+    }
+
+    /// `addr..addr + size` 是否整个落在后备堆的某一段内存中。
+    fn in_heap(&self, addr: usize, size: usize) -> bool {
+        Fallback::heap_range_of(&self.fallback_allocator, addr)
+            .is_some_and(|(_, end)| size <= end - addr)
+    }
+
+    /// 把第 `index` 个类的一个已经计入释放的块放回 slab 或空闲列表，空闲列表已满时还给后备分配器。
+    unsafe fn recycle(&mut self, index: usize, ptr: *mut u8) {
+        #[cfg(feature = "heap-slab")]
+        if self.uses_slabs(index) {
+            return self.slab_free(index, ptr);
+        }
+        if self.class_stats[index].cached >= self.cache_caps[index]
+            && self.table.sizes[index] >= MIN_RETURNABLE_BLOCK
+        {
+            // 空闲列表已满 -> 把块还给后备分配器，而不是一直缓存下去
+            #[cfg(feature = "zero-on-free")]
+            scrub_bytes(ptr, self.table.sizes[index]);
+            let ptr = NonNull::new(ptr).unwrap();
+            let layout = self.table.block_layout(index);
+            Fallback::deallocate(&mut self.fallback_allocator, ptr, layout);
+            self.class_stats[index].returned += 1;
+        } else {
+            self.push_block(index, ptr);
+        }
+    }
+
+    /// 检查从隔离区取出的块在隔离期间没有被写过，再把它放回空闲列表。
+    #[cfg(feature = "heap-quarantine")]
+    unsafe fn release_quarantined(&mut self, index: usize, ptr: *mut u8) {
+        self.verify_poison(index, ptr);
+        self.recycle(index, ptr);
+    }
+
+    /// 所有计数为零、只填好了块大小的统计。
+    const fn empty_stats(block_sizes: &[usize; N]) -> [ClassStats; N] {
+        let mut stats = [ClassStats {
+            block_size: 0,
+            hits: 0,
+            carves: 0,
+            returned: 0,
+            cached: 0,
+            live: 0,
+        }; N];
+        let mut i = 0;
+        while i < N {
+            stats[i].block_size = block_sizes[i];
+            i += 1;
+        }
+        stats
+    }
+
+    /// 为每个类从后备分配器预先切出 `counts` 个块放进空闲列表，返回每个类实际切出的块数。
+    ///
+    /// 应当在 `init` 之后立即调用，让早期的分配不必经过后备分配器。后备分配器空间不足时
+    /// 停止，已经切出的块仍然保留。预先切出的块不计入 `carves`，也不受缓存上限的限制。
+    /// 启用 `heap-slab` 时使用 slab 的类每次切出一整个 slab，实际的块数可能多于请求的。
+    pub fn prefill(&mut self, counts: &[usize; N]) -> [usize; N] {
+        let mut filled = [0; N];
+        for (index, &count) in counts.iter().enumerate() {
+            #[cfg(feature = "heap-slab")]
+            if self.uses_slabs(index) {
+                while filled[index] < count {
+                    match self.new_slab(index) {
+                        0 => return filled,
+                        blocks => filled[index] += blocks,
+                    }
+                }
+                continue;
+            }
+            while filled[index] < count {
+                let ptr = self.fallback_alloc(self.table.block_layout(index));
+                if ptr.is_null() {
+                    return filled;
+                }
+                // 新切出的块没有别人使用
+                unsafe { self.push_block(index, ptr) };
+                filled[index] += 1;
+            }
+        }
+        filled
+    }
+
+    /// 把 `ptr` 处的块压入第 `index` 个类的空闲列表。
+    ///
+    /// 调用者必须保证 `ptr` 是这个类的一个未被使用的块。
+    unsafe fn push_block(&mut self, index: usize, ptr: *mut u8) {
+        let new_node = ListNode {
+            next: self.list_heads[index].take(),
+        };
+        // verify that block has size and alignment required for storing node
+        assert!(mem::size_of::<ListNode>() <= self.table.sizes[index]);
+        assert!(mem::align_of::<ListNode>() <= self.table.sizes[index]);
+        let new_node_ptr = ptr as *mut ListNode;
+        new_node_ptr.write(new_node);
+        write_magic(ptr, self.table.sizes[index]);
+        self.list_heads[index] = Some(&mut *new_node_ptr);
+        self.class_stats[index].cached += 1;
+        self.scrub(index, ptr);
+    }
+
+    /// 从第 `index` 个类的 slab 中分配一个块，没有空闲块时先从后备分配器切出一个新的 slab。
+    #[cfg(feature = "heap-slab")]
+    fn slab_alloc(&mut self, index: usize) -> *mut u8 {
+        if let Some(ptr) = self.slabs.alloc(index, self.table.sizes[index]) {
+            unsafe { self.verify_poison(index, ptr) };
+            let stats = &mut self.class_stats[index];
+            stats.hits += 1;
+            stats.cached -= 1;
+            stats.live += 1;
+            return ptr;
+        }
+        if self.new_slab(index) == 0 {
+            return ptr::null_mut();
+        }
+        let stats = &mut self.class_stats[index];
+        stats.carves += 1;
+        stats.cached -= 1;
+        stats.live += 1;
+        // 新的 slab 刚刚挂进链表
+        self.slabs.alloc(index, self.table.sizes[index]).unwrap()
+    }
+
+    /// 从后备分配器为第 `index` 个类切出一个新的 slab，返回其中的块数，空间不足时返回 0。
+    #[cfg(feature = "heap-slab")]
+    fn new_slab(&mut self, index: usize) -> usize {
+        let slab = self.fallback_alloc(Self::slab_layout());
+        if slab.is_null() {
+            return 0;
+        }
+        // 先填充整个 slab，之后写入的头部和空闲链表会覆盖各自的位置
+        #[cfg(feature = "heap-poison")]
+        unsafe {
+            slab.write_bytes(POISON, slab::SLAB_SIZE)
+        };
+        // 这个 slab 刚刚分配，没有别人使用
+        let blocks = unsafe { self.slabs.add(index, self.table.sizes[index], slab) };
+        self.class_stats[index].cached += blocks;
+        blocks
+    }
+
+    /// 把第 `index` 个类的块 `ptr` 放回它的 slab，slab 全部空闲时还给后备分配器。
+    #[cfg(feature = "heap-slab")]
+    unsafe fn slab_free(&mut self, index: usize, ptr: *mut u8) {
+        self.scrub(index, ptr);
+        self.class_stats[index].cached += 1;
+        if let Some(slab) = self.slabs.free(index, self.table.sizes[index], ptr) {
+            self.release_slab(index, slab);
+        }
+    }
+
+    /// 把已经从链表中摘下的、全部空闲的 slab 还给后备分配器。
+    #[cfg(feature = "heap-slab")]
+    unsafe fn release_slab(&mut self, index: usize, slab: *mut u8) {
+        let slab = NonNull::new(slab).unwrap();
+        Fallback::deallocate(&mut self.fallback_allocator, slab, Self::slab_layout());
+        let blocks = slab::capacity(self.table.sizes[index]);
+        let stats = &mut self.class_stats[index];
+        stats.cached -= blocks;
+        stats.returned += blocks;
+    }
+
+    /// 第 `index` 个类是否使用 slab。
+    #[cfg(feature = "heap-slab")]
+    fn uses_slabs(&self, index: usize) -> bool {
+        slab::uses_slabs(self.table.sizes[index])
+    }
+
+    /// slab 使用的布局：对齐等于大小，这样由块地址向下对齐就能找到 slab 的头部。
+    #[cfg(feature = "heap-slab")]
+    fn slab_layout() -> Layout {
+        Layout::from_size_align(slab::SLAB_SIZE, slab::SLAB_SIZE).unwrap()
+    }
+
+    /// 启用 `heap-poison` 时把 `ptr` 处的空闲块在空闲列表占用的字节之后填充为 `POISON`，
+    /// 否则启用 `zero-on-free` 时把这些字节清零，都没有启用时什么也不做。
+    #[inline]
+    unsafe fn scrub(&self, index: usize, ptr: *mut u8) {
+        let block_size = self.table.sizes[index];
+        let offset = free_header_len(block_size);
+        #[cfg(feature = "heap-poison")]
+        ptr.add(offset).write_bytes(POISON, block_size - offset);
+        #[cfg(all(feature = "zero-on-free", not(feature = "heap-poison")))]
+        scrub_bytes(ptr.add(offset), block_size - offset);
+        #[cfg(not(any(feature = "heap-poison", feature = "zero-on-free")))]
+        let _ = (ptr, offset);
+    }
+
+    /// 启用 `heap-poison` 时检查刚从空闲列表取出的块在空闲列表占用的字节之后仍然是 `POISON`，
+    /// 否则说明释放后的块被写过，以块大小和第一个被改动的字节的偏移 panic。
+    #[inline]
+    unsafe fn verify_poison(&self, index: usize, ptr: *mut u8) {
+        #[cfg(feature = "heap-poison")]
+        {
+            let block_size = self.table.sizes[index];
+            let offset = free_header_len(block_size);
+            let bytes = core::slice::from_raw_parts(ptr.add(offset), block_size - offset);
+            if let Some(position) = bytes.iter().position(|&byte| byte != POISON) {
+                panic!(
+                    "use after free: {}-byte block {:p} modified at offset {} after being freed",
+                    block_size,
+                    ptr,
+                    offset + position
+                );
+            }
+        }
+        #[cfg(not(feature = "heap-poison"))]
+        let _ = (index, ptr);
+    }
+
+    /// 启用 `heap-debug` feature 时检查 `ptr` 不在第 `index` 个类的空闲列表中，
+    /// 否则说明它被释放了两次，以指针和块大小 panic，而不是把它第二次放进列表。
+    #[inline]
+    fn check_double_free(&self, index: usize, ptr: *mut u8) {
+        #[cfg(feature = "heap-debug")]
+        if self.is_cached(index, ptr) {
+            panic!(
+                "double free of {:p} in the {}-byte size class",
+                ptr, self.table.sizes[index]
+            );
+        }
+        #[cfg(not(feature = "heap-debug"))]
+        let _ = (index, ptr);
+    }
+
+    /// 启用 `heap-debug` 时记下 `ptr` 是从第 `index` 个类分配的。
+    #[inline]
+    fn tag_block(&mut self, index: usize, ptr: *mut u8) {
+        #[cfg(feature = "heap-debug")]
+        if let Some(tag) = self.tags.slot(ptr) {
+            *tag = index as u8 + 1;
+        }
+        #[cfg(not(feature = "heap-debug"))]
+        let _ = (index, ptr);
+    }
+
+    /// 启用 `heap-debug` 时检查释放 `ptr` 的 `layout` 选出的类 `class` 就是分配时的类并清除记录，
+    /// 否则以两边的布局 panic，而不是把块放进错误的空闲列表。
+    #[inline]
+    fn check_layout(&mut self, class: Option<usize>, ptr: *mut u8, layout: &Layout) {
+        #[cfg(feature = "heap-debug")]
+        {
+            let Some(tag) = self.tags.slot(ptr) else {
+                return;
+            };
+            let allocated = (*tag as usize).checked_sub(1);
+            *tag = 0;
+            if allocated == class {
+                return;
+            }
+            match allocated {
+                Some(index) => panic!(
+                    "mismatched dealloc of {:p}: freed with {:?} but allocated as {:?} \
+                     in the {}-byte size class",
+                    ptr,
+                    layout,
+                    self.table.block_layout(index),
+                    self.table.sizes[index]
+                ),
+                None => panic!(
+                    "mismatched dealloc of {:p}: freed with {:?} but not allocated from a class",
+                    ptr, layout
+                ),
+            }
+        }
+        #[cfg(not(feature = "heap-debug"))]
+        let _ = (class, ptr, layout);
+    }
+
+    /// `ptr` 是否已经是第 `index` 个类的空闲块。
+    #[cfg(feature = "heap-debug")]
+    fn is_cached(&self, index: usize, ptr: *mut u8) -> bool {
+        #[cfg(feature = "heap-quarantine")]
+        if self.quarantine.contains(index, ptr) {
+            return true;
+        }
+        #[cfg(feature = "heap-slab")]
+        if self.uses_slabs(index) {
+            // 块属于分配器管理的某个 slab，只需要查找这个 slab 的空闲链表
+            return unsafe { self.slabs.is_free(ptr) };
+        }
+        let mut node = self.list_heads[index].as_deref();
+        while let Some(current) = node {
+            if current as *const ListNode as *mut u8 == ptr {
+                return true;
+            }
+            node = current.next.as_deref();
+        }
+        false
+    }
+
+    /// 把所有空闲列表中的块还给后备分配器，返回释放的字节数。
+    ///
+    /// 切自同一批的块也可以逐个归还；只有小于 [`MIN_RETURNABLE_BLOCK`] 的块无法单独记录，
+    /// 它们留在空闲列表中。启用 `heap-slab` 时还会归还所有全部空闲的 slab，
+    /// 启用 `heap-quarantine` 时先清空隔离区。
+    pub fn drain(&mut self) -> usize {
+        #[cfg(feature = "heap-quarantine")]
+        for index in 0..N {
+            while let Some(ptr) = self.quarantine.pop(index, self.table.sizes[index]) {
+                // 隔离区中的块已经释放，没有别人使用
+                unsafe { self.release_quarantined(index, ptr) };
+            }
+        }
+        self.trim(usize::MAX)
+    }
+
+    /// 与 [`drain`](Self::drain) 相同，但从最大的类开始归还，释放了至少 `target_bytes` 字节就停止，
+    /// 返回实际释放的字节数。
+    ///
+    /// 除了在分配失败时作为回收函数，也可以在帧分配器的空闲帧低于水位时主动调用。
+    /// 页分配函数分配的大块释放时就已经还回去，不在这里归还。
+    pub fn trim(&mut self, target_bytes: usize) -> usize {
+        let mut released = 0;
+        for (index, block_size) in self.table.sizes.into_iter().enumerate().rev() {
+            #[cfg(feature = "heap-slab")]
+            while released < target_bytes {
+                let Some(slab) = self.slabs.take_empty(index, block_size) else {
+                    break;
+                };
+                // 摘下的 slab 中没有尚未释放的块
+                unsafe { self.release_slab(index, slab) };
+                released += slab::SLAB_SIZE;
+            }
+            if block_size < MIN_RETURNABLE_BLOCK {
+                continue;
+            }
+            while released < target_bytes && self.return_cached_block(index) {
+                released += block_size;
             }
         }
+        released
+    }
+
+    /// 按最近的分配次数调整各个类的缓存上限，把超出新上限的缓存块还给后备分配器，
+    /// 返回释放的字节数。
+    ///
+    /// 应当由定时器周期性地调用。每次调用时一个类的上限取为它最近的分配次数，限制在
+    /// [`MIN_ADAPTIVE_CAP`] 和 [`MAX_ADAPTIVE_CAP`] 之间，随后计数减半，所以不再分配的类
+    /// 在几次调用之后只缓存下限个数的块。第一次调用之前所有类的上限都是 [`DEFAULT_CACHE_CAP`]；
+    /// 用 [`set_cache_cap`](Self::set_cache_cap) 固定了上限的类不受影响。
+    pub fn tick(&mut self) -> usize {
+        let mut released = 0;
+        for index in 0..N {
+            let recent = self.recent_allocs[index];
+            self.recent_allocs[index] = recent / 2;
+            if self.fixed_caps[index] {
+                continue;
+            }
+            let cap = recent.clamp(MIN_ADAPTIVE_CAP, MAX_ADAPTIVE_CAP);
+            self.cache_caps[index] = cap;
+            if self.table.sizes[index] < MIN_RETURNABLE_BLOCK {
+                continue;
+            }
+            while self.class_stats[index].cached > cap && self.return_cached_block(index) {
+                released += self.table.sizes[index];
+            }
+        }
+        released
+    }
+
+    /// 检查各个类的空闲列表，再在每个类上分配一个块、写入并读回、释放，返回发现的第一个问题。
+    ///
+    /// 列表中的每个指针都必须在堆内、按块大小对齐，列表的长度必须等于统计中缓存的块数；
+    /// 前 256 个空闲块还会检查有没有重复。只使用固定大小的数组，不从堆上分配。
+    /// 测试用的分配会计入统计。启用 `heap-slab` 时使用 slab 的类只做分配测试。
+    pub fn self_test(&mut self) -> Result<(), SelfTestError> {
+        let mut seen = [0; SELF_TEST_SCAN];
+        let mut seen_count = 0;
+        for index in 0..N {
+            #[cfg(feature = "heap-slab")]
+            let walk = !self.uses_slabs(index);
+            #[cfg(not(feature = "heap-slab"))]
+            let walk = true;
+            if walk {
+                self.check_free_list(index, &mut seen, &mut seen_count)?;
+            }
+        }
+        for index in 0..N {
+            self.exercise_class(index)?;
+        }
+        Ok(())
+    }
+
+    /// 检查第 `index` 个类的空闲列表，把见到的块记在 `seen` 中，直到记满。
+    fn check_free_list(
+        &self,
+        index: usize,
+        seen: &mut [usize; SELF_TEST_SCAN],
+        seen_count: &mut usize,
+    ) -> Result<(), SelfTestError> {
+        let block_size = self.table.sizes[index];
+        let fail = |addr, reason| {
+            Err(SelfTestError {
+                block_size,
+                addr,
+                reason,
+            })
+        };
+        let cached = self.class_stats[index].cached;
+        let mut count = 0;
+        let mut node = self.list_heads[index].as_deref();
+        while let Some(current) = node {
+            let addr = current as *const ListNode as usize;
+            // 列表比统计中长时停下，有环的列表也不会一直走下去
+            if count == cached {
+                return fail(addr, SelfTestReason::CountMismatch);
+            }
+            if !self.in_heap(addr, block_size) {
+                return fail(addr, SelfTestReason::OutsideHeap);
+            }
+            if !is_aligned(addr, block_size) {
+                return fail(addr, SelfTestReason::Misaligned);
+            }
+            if seen[..*seen_count].contains(&addr) {
+                return fail(addr, SelfTestReason::Duplicate);
+            }
+            if *seen_count < SELF_TEST_SCAN {
+                seen[*seen_count] = addr;
+                *seen_count += 1;
+            }
+            count += 1;
+            // 上面已经确认这个节点在堆内并且对齐
+            node = current.next.as_deref();
+        }
+        if count != cached {
+            return fail(0, SelfTestReason::CountMismatch);
+        }
+        Ok(())
+    }
+
+    /// 在第 `index` 个类上分配一个块，写满 `SELF_TEST_PATTERN` 后读回，再释放。
+    fn exercise_class(&mut self, index: usize) -> Result<(), SelfTestError> {
+        let block_size = self.table.sizes[index];
+        let layout = self.table.block_layout(index);
+        let (ptr, _) = self.allocate(layout);
+        if ptr.is_null() {
+            return Err(SelfTestError {
+                block_size,
+                addr: 0,
+                reason: SelfTestReason::AllocationFailed,
+            });
+        }
+        // 这个块刚刚分配，只有这里使用
+        let intact = unsafe {
+            ptr.write_bytes(SELF_TEST_PATTERN, block_size);
+            core::slice::from_raw_parts(ptr, block_size)
+                .iter()
+                .all(|&byte| byte == SELF_TEST_PATTERN)
+        };
+        unsafe { self.deallocate(ptr, layout) };
+        if !intact {
+            return Err(SelfTestError {
+                block_size,
+                addr: ptr as usize,
+                reason: SelfTestReason::PatternMismatch,
+            });
+        }
+        Ok(())
+    }
+
+    /// 把第 `index` 个类空闲列表头部的块还给后备分配器，列表为空时返回 `false`。
+    ///
+    /// 调用者必须保证这个类的块不小于 [`MIN_RETURNABLE_BLOCK`]。
+    fn return_cached_block(&mut self, index: usize) -> bool {
+        let Some(node) = self.list_heads[index].take() else {
+            return false;
+        };
+        let ptr = NonNull::from(node).cast();
+        // 块在空闲列表中，先检查 magic 再跟随 `next`
+        unsafe { verify_magic(ptr.as_ptr(), self.table.sizes[index]) };
+        self.list_heads[index] = unsafe { (*ptr.cast::<ListNode>().as_ptr()).next.take() };
+        let layout = self.table.block_layout(index);
+        // 块在空闲列表中，没有别人使用
+        unsafe { Fallback::deallocate(&mut self.fallback_allocator, ptr, layout) };
+        let stats = &mut self.class_stats[index];
+        stats.cached -= 1;
+        stats.returned += 1;
+        // 还回去的内存以后可能被重新切出，不再是全零的
+        self.fresh_blocks[index] = (0, 0);
+        true
+    }
+
+    /// 返回后备分配器中空闲的字节数。
+    pub fn fallback_free(&self) -> usize {
+        Fallback::free(&self.fallback_allocator)
+    }
+
+    /// 返回后备分配器中已分配的字节数，包括切给各个类的块。
+    pub fn fallback_used(&self) -> usize {
+        Fallback::used(&self.fallback_allocator)
+    }
+
+    /// 返回后备分配器管理的字节数。
+    pub fn fallback_size(&self) -> usize {
+        Fallback::size(&self.fallback_allocator)
+    }
+
+    /// Allocates using the fallback allocator.
+    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.fallback_alloc_fresh(layout).0
+    }
+    /// 用后备分配器分配，第二个返回值说明这段内存是否从未分配过、已经全部是零。
+    fn fallback_alloc_fresh(&mut self, layout: Layout) -> (*mut u8, bool) {
+        let ptr = match Fallback::allocate(&mut self.fallback_allocator, layout) {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(_) => return (ptr::null_mut(), false),
+        };
+        let start = ptr as usize;
+        let pristine = start >= self.pristine_start;
+        if pristine {
+            // 后备分配器只可能在 `pristine_start` 处留下不超过 `PRISTINE_RECORD` 字节的空闲记录
+            let dirty_end = self.pristine_start + fallback::Heap::PRISTINE_RECORD;
+            let dirty_len = dirty_end.saturating_sub(start).min(layout.size());
+            unsafe { ptr.write_bytes(0, dirty_len) };
+        }
+        // 后备分配器把每次分配的大小向上取到至少 `MIN_RETURNABLE_BLOCK`，并按 `usize` 对齐
+        let size = align_up(
+            layout.size().max(MIN_RETURNABLE_BLOCK),
+            mem::align_of::<usize>(),
+        );
+        self.pristine_start = self.pristine_start.max(start + size);
+        (ptr, pristine)
+    }
+}
+impl<const N: usize> Locked<FixedSizeBlockAllocator<N>> {
+    /// 返回 `layout` 的分配实际可用的字节数：小块是整个块，大块是请求的大小。
+    pub fn usable_size(&self, _ptr: *mut u8, layout: Layout) -> usize {
+        if layout.size() == 0 {
+            return 0;
+        }
+        let allocator = self.lock();
+        match allocator.table.index(&layout) {
+            Some(index) => allocator.table.sizes[index],
+            None => layout.size(),
+        }
+    }
+
+    /// 返回各个类、大块分配和后备分配器的统计，见 [`FixedSizeBlockAllocator::stats`]。
+    pub fn stats(&self) -> BlockStats<N> {
+        self.lock().stats()
+    }
+
+    /// 返回尚未释放的分配浪费的字节数，见
+    /// [`FixedSizeBlockAllocator::internal_fragmentation_bytes`]。
+    pub fn internal_fragmentation_bytes(&self) -> usize {
+        self.lock().internal_fragmentation_bytes()
+    }
+
+    /// 返回所有分配请求的大小分布，见 [`FixedSizeBlockAllocator::size_histogram`]。
+    pub fn size_histogram(&self) -> SizeHistogram {
+        self.lock().size_histogram()
+    }
+
+    /// 返回交给后备分配器的请求的大小分布，见 [`FixedSizeBlockAllocator::fallback_histogram`]。
+    pub fn fallback_histogram(&self) -> SizeHistogram {
+        self.lock().fallback_histogram()
+    }
+
+    /// 返回后备分配器中空闲的字节数。
+    pub fn fallback_free(&self) -> usize {
+        self.lock().fallback_free()
+    }
+
+    /// 返回后备分配器中已分配的字节数，包括切给各个类的块。
+    pub fn fallback_used(&self) -> usize {
+        self.lock().fallback_used()
+    }
+
+    /// 返回后备分配器管理的字节数。
+    pub fn fallback_size(&self) -> usize {
+        self.lock().fallback_size()
+    }
+
+    /// 检查所有分配都已经释放，否则列出还有尚未释放的分配的类并 panic。
+    pub fn assert_balanced(&self) {
+        // 先复制计数再 panic，不在持有锁的时候格式化
+        let live = self.lock().live_counts();
+        if !live.is_balanced() {
+            panic!("unbalanced heap: {}", live);
+        }
+    }
+
+    /// 把所有缓存的块还给后备分配器，返回释放的字节数，见 [`FixedSizeBlockAllocator::drain`]。
+    pub fn drain(&self) -> usize {
+        self.lock().drain()
+    }
+
+    /// 从最大的类开始归还缓存的块，直到释放了至少 `target_bytes` 字节，见
+    /// [`FixedSizeBlockAllocator::trim`]。可以作为回收函数注册给
+    /// [`register_shrinker`](super::register_shrinker)。
+    ///
+    /// 只在锁内工作，其他代码同时分配也是安全的。
+    pub fn trim(&self, target_bytes: usize) -> usize {
+        self.lock().trim(target_bytes)
+    }
+
+    /// 按最近的分配次数调整各个类的缓存上限，返回释放的字节数，见
+    /// [`FixedSizeBlockAllocator::tick`]。
+    pub fn tick(&self) -> usize {
+        self.lock().tick()
+    }
+
+    /// 检查空闲列表并在每个类上做一次分配测试，见 [`FixedSizeBlockAllocator::self_test`]。
+    pub fn self_test(&self) -> Result<(), SelfTestError> {
+        self.lock().self_test()
+    }
+
+    /// 把 `start..start + size` 加入后备堆，见 [`FixedSizeBlockAllocator::extend`]。
+    ///
+    /// 这个函数是不安全的，因为调用者必须保证给定的内存是有效的并且未被使用。
+    pub unsafe fn extend(&self, start: usize, size: usize) {
+        self.lock().extend(start, size);
+    }
+
+    /// 两个大小不为零的布局是否落在同一个类里，这时原来的块已经够用，调整大小不必移动；
+    /// 是的话按新的大小重新计算这个块浪费的字节数，调用者应当原样返回块。
+    ///
+    /// 类按大小和对齐一起选出，所以同一个类的块也满足新的对齐。
+    fn resize_in_place(&self, old_layout: &Layout, new_layout: &Layout) -> bool {
+        self.lock().resize_in_place(old_layout, new_layout)
+    }
+
+    /// `Allocator::grow` 和 `Allocator::shrink` 的共同实现。
+    ///
+    /// 与 `realloc` 一样，新旧布局落在同一个类里时原样返回块，否则分配新的内存再复制过去。
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if self.resize_in_place(&old_layout, &new_layout) {
+            let len = self.usable_size(ptr.as_ptr(), new_layout);
+            return Ok(NonNull::slice_from_raw_parts(ptr, len));
+        }
+        let new_ptr = self.allocate(new_layout)?;
+        let copied = old_layout.size().min(new_layout.size());
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, copied);
+        self.dealloc(ptr.as_ptr(), old_layout);
+        Ok(new_ptr)
+    }
+
+    /// 分配失败时先请所有注册的回收函数释放内存，再试一次。
+    fn allocate_or_shrink(&self, layout: Layout) -> (*mut u8, bool) {
+        let allocated = self.lock().allocate(layout);
+        if !allocated.0.is_null() {
+            return allocated;
+        }
+        // 回收函数可能会锁上这个分配器，调用之前必须先释放锁
+        if super::run_shrinkers(layout.size() + layout.align()) == 0 {
+            return allocated;
+        }
+        self.lock().allocate(layout)
+    }
+}
+unsafe impl<const N: usize> GlobalAlloc for Locked<FixedSizeBlockAllocator<N>> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return dangling(&layout);
+        }
+        self.allocate_or_shrink(layout).0
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return dangling(&layout);
+        }
+        let (ptr, pristine) = self.allocate_or_shrink(layout);
+        if ptr.is_null() {
+            return ptr;
+        }
+        ptr.write_bytes(0, dirty_len(layout.size(), pristine));
+        ptr
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if layout.size() == 0 {
+            return self.alloc(new_layout);
+        }
+        // 新旧大小落在同一个类里 -> 原来的块已经够用，不必移动
+        if self.resize_in_place(&layout, &new_layout) {
+            return ptr;
+        }
+
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        self.lock().deallocate(ptr, layout);
+    }
+}
+
+/// 让 `Vec::new_in` 等集合可以使用独立的块分配器，返回的切片长度是整个块。
+unsafe impl<const N: usize> Allocator for Locked<FixedSizeBlockAllocator<N>> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        // 零大小的分配得到按对齐悬空的指针，长度为零
+        let ptr = NonNull::new(unsafe { self.alloc(layout) }).ok_or(AllocError)?;
+        let len = self.usable_size(ptr.as_ptr(), layout);
+        Ok(NonNull::slice_from_raw_parts(ptr, len))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = NonNull::new(unsafe { self.alloc_zeroed(layout) }).ok_or(AllocError)?;
+        let len = self.usable_size(ptr.as_ptr(), layout);
+        // `alloc_zeroed` 只清零了请求的部分
+        unsafe {
+            ptr.as_ptr()
+                .add(layout.size())
+                .write_bytes(0, len - layout.size())
+        };
+        Ok(NonNull::slice_from_raw_parts(ptr, len))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.dealloc(ptr.as_ptr(), layout);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.resize(ptr, old_layout, new_layout)?;
+        let start = block.as_ptr() as *mut u8;
+        start
+            .add(old_layout.size())
+            .write_bytes(0, block.len() - old_layout.size());
+        Ok(block)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
     }
 }
@@ -0,0 +1,166 @@
+//! [`PerCpuBlockAllocator`](super::PerCpuBlockAllocator) 和
+//! [`ClassLockedBlockAllocator`](super::ClassLockedBlockAllocator) 共用的部分：前端缓存块的空闲
+//! 列表，以及成批地从共享分配器取出块、把块还回去。
+
+use super::super::{Locked, LockedGuard};
+use super::{FixedSizeBlockAllocator, ListNode, SizeTable};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// 前端缓存的一个类的空闲列表，最多缓存 `2 * batch` 个块。
+pub(super) struct BlockList {
+    head: Option<&'static mut ListNode>,
+    count: usize,
+}
+
+impl BlockList {
+    pub(super) const fn new() -> Self {
+        BlockList {
+            head: None,
+            count: 0,
+        }
+    }
+
+    fn pop(&mut self) -> Option<*mut u8> {
+        let node = self.head.take()?;
+        self.head = node.next.take();
+        self.count -= 1;
+        Some(node as *mut ListNode as *mut u8)
+    }
+
+    /// 调用者必须保证 `ptr` 是这个类的一个未被使用的块。
+    unsafe fn push(&mut self, ptr: *mut u8) {
+        let node = ptr as *mut ListNode;
+        node.write(ListNode {
+            next: self.head.take(),
+        });
+        self.head = Some(&mut *node);
+        self.count += 1;
+    }
+}
+
+/// 前端后面的共享分配器，记下锁上它的次数。持有它的锁时关闭中断，中断处理程序里的分配不会在
+/// 这把锁上自旋。
+pub(super) struct SharedBlocks<const N: usize> {
+    allocator: Locked<FixedSizeBlockAllocator<N>>,
+    /// 共享分配器的大小类，复制一份，这样查找所属的类不需要锁。
+    table: SizeTable<N>,
+    /// 锁上共享分配器的次数。
+    locks: AtomicUsize,
+}
+
+impl<const N: usize> SharedBlocks<N> {
+    pub(super) const fn with_block_sizes(block_sizes: [usize; N]) -> Self {
+        let allocator = FixedSizeBlockAllocator::with_block_sizes(block_sizes);
+        SharedBlocks {
+            table: allocator.table,
+            allocator: Locked::new_irq_safe(allocator),
+            locks: AtomicUsize::new(0),
+        }
+    }
+
+    pub(super) fn allocator(&self) -> &Locked<FixedSizeBlockAllocator<N>> {
+        &self.allocator
+    }
+
+    pub(super) fn lock_count(&self) -> usize {
+        self.locks.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn lock(&self) -> LockedGuard<'_, FixedSizeBlockAllocator<N>> {
+        self.locks.fetch_add(1, Ordering::Relaxed);
+        self.allocator.lock()
+    }
+
+    /// 分配 `layout` 时使用的类，大块返回 `None`。`layout` 的大小不为零。
+    pub(super) fn alloc_class(&self, layout: &Layout) -> Option<usize> {
+        self.table.index(layout)
+    }
+
+    /// 释放 `ptr` 时放进的类，大块和空指针返回 `None`：空指针交给共享分配器，由它忽略并计数。
+    pub(super) fn dealloc_class(&self, ptr: *mut u8, layout: &Layout) -> Option<usize> {
+        self.table.index(layout).filter(|_| !ptr.is_null())
+    }
+
+    /// 不经过前端的列表，直接从共享分配器分配；后备堆不够用时由它请回收函数释放内存。
+    pub(super) unsafe fn alloc_direct(&self, layout: Layout) -> *mut u8 {
+        self.locks.fetch_add(1, Ordering::Relaxed);
+        self.allocator.alloc(layout)
+    }
+
+    /// 不经过前端的列表，直接还给共享分配器。
+    pub(super) unsafe fn dealloc_direct(&self, ptr: *mut u8, layout: Layout) {
+        self.locks.fetch_add(1, Ordering::Relaxed);
+        self.allocator.dealloc(ptr, layout)
+    }
+
+    /// 从第 `index` 个类的 `list` 取出一个块；列表空了就锁一次共享分配器，取出 `batch` 个块。
+    ///
+    /// 后备堆不够用时返回 `None`，调用者应当先放开 `list` 的锁，再用 [`Self::alloc_block`]
+    /// 分配：回收函数可能会清空前端的列表。
+    pub(super) unsafe fn take(
+        &self,
+        list: &mut BlockList,
+        index: usize,
+        batch: usize,
+    ) -> Option<*mut u8> {
+        if let Some(ptr) = list.pop() {
+            return Some(ptr);
+        }
+        let layout = self.table.block_layout(index);
+        let mut shared = self.lock();
+        for _ in 0..batch {
+            let (ptr, _) = shared.allocate(layout);
+            if ptr.is_null() {
+                break;
+            }
+            list.push(ptr);
+        }
+        list.pop()
+    }
+
+    /// `take` 取不到块时直接从共享分配器分配第 `index` 个类的一个块。
+    pub(super) unsafe fn alloc_block(&self, index: usize) -> *mut u8 {
+        self.alloc_direct(self.table.block_layout(index))
+    }
+
+    /// 把第 `index` 个类的块 `ptr` 放进 `list`；列表超过 `2 * batch` 个块时锁一次共享分配器，
+    /// 还回去 `batch` 个。
+    ///
+    /// 调用者必须保证 `ptr` 是这个类的一个尚未释放的块。
+    pub(super) unsafe fn put(
+        &self,
+        list: &mut BlockList,
+        index: usize,
+        ptr: *mut u8,
+        batch: usize,
+    ) {
+        list.push(ptr);
+        if list.count <= 2 * batch {
+            return;
+        }
+        let layout = self.table.block_layout(index);
+        let mut shared = self.lock();
+        for _ in 0..batch {
+            let ptr = list.pop().unwrap();
+            // 列表中的块是共享分配器用这个布局分配的
+            shared.deallocate(ptr, layout);
+        }
+    }
+
+    /// 把第 `index` 个类的 `list` 中的块全部还给已经锁上的共享分配器。
+    pub(super) fn drain(
+        &self,
+        shared: &mut FixedSizeBlockAllocator<N>,
+        list: &mut BlockList,
+        index: usize,
+    ) {
+        let layout = self.table.block_layout(index);
+        while let Some(ptr) = list.pop() {
+            // 列表中的块是共享分配器用这个布局分配的
+            unsafe { shared.deallocate(ptr, layout) };
+        }
+    }
+}
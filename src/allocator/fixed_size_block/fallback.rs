@@ -0,0 +1,156 @@
+//! [`FixedSizeBlockAllocator`](super::FixedSizeBlockAllocator) 的后备分配器。
+//!
+//! 默认使用本 crate 的 [`LinkedListAllocator`]，通过它不写边界标记的 `allocate` 和
+//! `deallocate` 分配；启用 `external-fallback` feature 时改用 `linked_list_allocator` crate 的
+//! `Heap`，用于比较两者。它们都只按给定的范围记录空闲内存，所以一次切出的一批块可以逐个还回去。
+
+use super::super::linked_list::{self, LinkedListAllocator};
+use core::{alloc::Layout, ptr::NonNull};
+
+/// 后备分配器需要提供的操作。
+pub(super) trait Fallback {
+    /// 能单独记录的最小空闲范围，分配的大小也至少取到这么大并按 `usize` 对齐。
+    const MIN_FREE: usize;
+    /// 从未分配过的内存分出去时，开头可能残留的空闲记录的字节数。
+    const PRISTINE_RECORD: usize;
+
+    /// 调用者必须保证给定的堆边界是有效的，并且堆未被使用。
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+
+    /// 与 `init` 相同，调用者还保证整个堆已经被清零。
+    unsafe fn init_zeroed(&mut self, heap_start: usize, heap_size: usize);
+
+    fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, ()>;
+
+    /// 调用者必须保证 `ptr..ptr + layout.size()` 是分配出去的内存中尚未释放的一段。
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout);
+
+    /// 把 `start..start + size` 加入堆，调用者的要求和 `init` 相同。
+    unsafe fn extend(&mut self, start: usize, size: usize);
+
+    /// 包含 `addr` 的那一段堆内存 (start, end)，`addr` 不在堆内时返回 `None`。
+    fn heap_range_of(&self, addr: usize) -> Option<(usize, usize)>;
+
+    fn free(&self) -> usize;
+
+    fn used(&self) -> usize;
+
+    fn size(&self) -> usize;
+}
+
+#[cfg(not(feature = "external-fallback"))]
+pub(super) type Heap = LinkedListAllocator;
+#[cfg(feature = "external-fallback")]
+pub(super) type Heap = linked_list_allocator::Heap;
+
+/// 一个空的后备分配器。
+#[cfg(not(feature = "external-fallback"))]
+pub(super) const fn empty() -> Heap {
+    LinkedListAllocator::new()
+}
+#[cfg(feature = "external-fallback")]
+pub(super) const fn empty() -> Heap {
+    linked_list_allocator::Heap::empty()
+}
+
+impl Fallback for LinkedListAllocator {
+    const MIN_FREE: usize = linked_list::MIN_BLOCK_SIZE;
+    // `allocate` 自己清掉了从未分配过的内存中的记录
+    const PRISTINE_RECORD: usize = 0;
+
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        LinkedListAllocator::init(self, heap_start, heap_size);
+    }
+
+    unsafe fn init_zeroed(&mut self, heap_start: usize, heap_size: usize) {
+        LinkedListAllocator::init_zeroed(self, heap_start, heap_size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
+        LinkedListAllocator::allocate(self, layout)
+    }
+
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        LinkedListAllocator::deallocate(self, ptr, layout);
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        if let Err(err) = self.extend_untagged(start, size) {
+            panic!("cannot extend the fallback heap: {:?}", err);
+        }
+    }
+
+    fn heap_range_of(&self, addr: usize) -> Option<(usize, usize)> {
+        self.heap_ranges()
+            .iter()
+            .copied()
+            .find(|&(start, end)| start <= addr && addr < end)
+    }
+
+    fn free(&self) -> usize {
+        self.free_bytes()
+    }
+
+    fn used(&self) -> usize {
+        self.current_usage()
+    }
+
+    fn size(&self) -> usize {
+        // 不算 `init` 对齐堆边界时丢掉的字节
+        self.heap_ranges()
+            .iter()
+            .map(|&(start, end)| end - start)
+            .sum()
+    }
+}
+
+#[cfg(feature = "external-fallback")]
+impl Fallback for linked_list_allocator::Heap {
+    // 空洞记录是两个 `usize`
+    const MIN_FREE: usize = 2 * core::mem::size_of::<usize>();
+    const PRISTINE_RECORD: usize = 2 * core::mem::size_of::<usize>();
+
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        linked_list_allocator::Heap::init(self, heap_start, heap_size);
+    }
+
+    unsafe fn init_zeroed(&mut self, heap_start: usize, heap_size: usize) {
+        linked_list_allocator::Heap::init(self, heap_start, heap_size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
+        self.allocate_first_fit(layout)
+    }
+
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        linked_list_allocator::Heap::deallocate(self, ptr, layout);
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        // 这个 crate 的堆只能在结尾处扩展
+        assert_eq!(
+            start,
+            self.top(),
+            "the fallback heap can only grow at its end"
+        );
+        linked_list_allocator::Heap::extend(self, size);
+    }
+
+    fn heap_range_of(&self, addr: usize) -> Option<(usize, usize)> {
+        // 只能在结尾处扩展，所以堆总是连续的一段
+        let (start, end) = (self.bottom() as usize, self.top() as usize);
+        (start <= addr && addr < end).then_some((start, end))
+    }
+
+    fn free(&self) -> usize {
+        linked_list_allocator::Heap::free(self)
+    }
+
+    fn used(&self) -> usize {
+        linked_list_allocator::Heap::used(self)
+    }
+
+    fn size(&self) -> usize {
+        linked_list_allocator::Heap::size(self)
+    }
+}
@@ -0,0 +1,102 @@
+//! 每个类的空闲列表各有一把锁的 [`FixedSizeBlockAllocator`] 前端。
+//!
+//! 所有类共用 `Locked` 的一把锁时，一次较慢的后备分配会挡住另一个 CPU 或中断处理程序里只需要从
+//! 空闲列表取一个块的小分配。这里每个类的列表有自己的锁，命中时只锁上这个类；列表空了才再锁上
+//! 后面的共享分配器，一次取出 `PER_CLASS_BATCH` 个块，列表太长时一次还回去这么多。大块仍然直接
+//! 交给共享分配器，后备堆由它自己的锁保护。
+//!
+//! 从共享分配器的角度，缓存在各个类的列表中的块都是尚未释放的，`heap-debug` 的检查在块还回去
+//! 的时候才进行。
+
+use super::super::{dangling, Locked};
+use super::batch::{BlockList, SharedBlocks};
+use super::{FixedSizeBlockAllocator, BLOCK_SIZES, CLASS_COUNT};
+use core::alloc::{GlobalAlloc, Layout};
+
+/// 每次从共享分配器取出或者还回去的块数。
+pub const PER_CLASS_BATCH: usize = 8;
+
+/// 每个类各有一把锁的 [`FixedSizeBlockAllocator`]，默认使用 [`BLOCK_SIZES`]。
+///
+/// 可以直接作为 `#[global_allocator]`。持有各个类的列表和共享分配器的锁时都关闭中断，
+/// 中断处理程序也可以分配。
+pub struct ClassLockedBlockAllocator<const N: usize = CLASS_COUNT> {
+    shared: SharedBlocks<N>,
+    lists: [Locked<BlockList>; N],
+}
+
+impl ClassLockedBlockAllocator {
+    /// 创建一个空的分配器。
+    pub const fn new() -> Self {
+        Self::with_block_sizes(BLOCK_SIZES)
+    }
+}
+
+impl Default for ClassLockedBlockAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ClassLockedBlockAllocator<N> {
+    /// 创建一个使用给定块大小的空的分配器，块大小的要求见
+    /// [`FixedSizeBlockAllocator::with_block_sizes`]。
+    pub const fn with_block_sizes(block_sizes: [usize; N]) -> Self {
+        ClassLockedBlockAllocator {
+            shared: SharedBlocks::with_block_sizes(block_sizes),
+            lists: [const { Locked::new_irq_safe(BlockList::new()) }; N],
+        }
+    }
+
+    /// 后面的共享分配器，用于初始化和读取统计。
+    pub fn shared(&self) -> &Locked<FixedSizeBlockAllocator<N>> {
+        self.shared.allocator()
+    }
+
+    /// 返回锁上共享分配器的次数，越少说明各个类之间的竞争越少。
+    pub fn shared_lock_count(&self) -> usize {
+        self.shared.lock_count()
+    }
+
+    /// 把各个类的列表中缓存的块还给共享分配器。
+    pub fn flush(&self) {
+        for (index, list) in self.lists.iter().enumerate() {
+            let mut list = list.lock();
+            self.shared.drain(&mut self.shared.lock(), &mut list, index);
+        }
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for ClassLockedBlockAllocator<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return dangling(&layout);
+        }
+        let index = match self.shared.alloc_class(&layout) {
+            Some(index) => index,
+            None => return self.shared.alloc_direct(layout),
+        };
+        let mut list = self.lists[index].lock();
+        match self.shared.take(&mut list, index, PER_CLASS_BATCH) {
+            Some(ptr) => ptr,
+            // 后备堆不够用了，交给共享分配器请回收函数释放内存
+            None => {
+                drop(list);
+                self.shared.alloc_block(index)
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        match self.shared.dealloc_class(ptr, &layout) {
+            Some(index) => {
+                self.shared
+                    .put(&mut self.lists[index].lock(), index, ptr, PER_CLASS_BATCH)
+            }
+            None => self.shared.dealloc_direct(ptr, layout),
+        }
+    }
+}
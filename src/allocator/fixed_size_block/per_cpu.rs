@@ -0,0 +1,108 @@
+//! 在共享的 [`FixedSizeBlockAllocator`] 前面为每个 CPU 缓存一些块的前端。
+//!
+//! 每个 CPU 有自己的一组空闲列表，由注入的 `current_cpu` 函数选出。分配和释放先在当前 CPU 的
+//! 列表上进行，只锁上这个 CPU 自己的锁，几乎不会和别的 CPU 竞争；列表空了才从共享分配器一次取出
+//! `PER_CPU_BATCH` 个块，列表太长时一次还回去 `PER_CPU_BATCH` 个。在一个 CPU 上分配、在另一个
+//! CPU 上释放的块进入释放它的 CPU 的列表，之后和其他块一样被分配或者还给共享分配器。
+//!
+//! 从共享分配器的角度，缓存在各个 CPU 上的块都是尚未释放的。
+
+use super::super::{dangling, Locked, LockedGuard};
+use super::batch::{BlockList, SharedBlocks};
+use super::{FixedSizeBlockAllocator, BLOCK_SIZES, CLASS_COUNT};
+use core::alloc::{GlobalAlloc, Layout};
+
+/// 每次从共享分配器取出或者还回去的块数。
+pub const PER_CPU_BATCH: usize = 8;
+
+/// 一个 CPU 的空闲列表，每个类一个。
+type CpuCache<const N: usize> = [BlockList; N];
+
+/// 为最多 `CPUS` 个 CPU 缓存块的 [`FixedSizeBlockAllocator`]，默认使用 [`BLOCK_SIZES`]。
+///
+/// 可以直接作为 `#[global_allocator]`；`current_cpu` 返回的编号按 `CPUS` 取模。持有各个 CPU 的
+/// 列表和共享分配器的锁时都关闭中断，中断处理程序也可以分配。
+/// 共享分配器的锁靠 [`set_current_cpu`](super::super::set_current_cpu) 区分持有它的 CPU，
+/// 通常设置成同一个函数。
+pub struct PerCpuBlockAllocator<const CPUS: usize, const N: usize = CLASS_COUNT> {
+    shared: SharedBlocks<N>,
+    current_cpu: fn() -> usize,
+    caches: [Locked<CpuCache<N>>; CPUS],
+}
+
+impl<const CPUS: usize> PerCpuBlockAllocator<CPUS> {
+    /// 创建一个空的分配器，`current_cpu` 返回当前 CPU 的编号。
+    pub const fn new(current_cpu: fn() -> usize) -> Self {
+        Self::with_block_sizes(BLOCK_SIZES, current_cpu)
+    }
+}
+
+impl<const CPUS: usize, const N: usize> PerCpuBlockAllocator<CPUS, N> {
+    /// 创建一个使用给定块大小的空的分配器，块大小的要求见
+    /// [`FixedSizeBlockAllocator::with_block_sizes`]。
+    pub const fn with_block_sizes(block_sizes: [usize; N], current_cpu: fn() -> usize) -> Self {
+        PerCpuBlockAllocator {
+            shared: SharedBlocks::with_block_sizes(block_sizes),
+            current_cpu,
+            caches: [const { Locked::new_irq_safe([const { BlockList::new() }; N]) }; CPUS],
+        }
+    }
+
+    /// 后面的共享分配器，用于初始化和读取统计。
+    pub fn shared(&self) -> &Locked<FixedSizeBlockAllocator<N>> {
+        self.shared.allocator()
+    }
+
+    /// 返回锁上共享分配器的次数，越少说明 CPU 之间的竞争越少。
+    pub fn shared_lock_count(&self) -> usize {
+        self.shared.lock_count()
+    }
+
+    /// 把所有 CPU 上缓存的块还给共享分配器。
+    pub fn flush(&self) {
+        for cache in self.caches.iter() {
+            let mut cache = cache.lock();
+            let mut shared = self.shared.lock();
+            for (index, list) in cache.iter_mut().enumerate() {
+                self.shared.drain(&mut shared, list, index);
+            }
+        }
+    }
+
+    fn cache(&self) -> LockedGuard<'_, CpuCache<N>> {
+        self.caches[(self.current_cpu)() % CPUS].lock()
+    }
+}
+
+unsafe impl<const CPUS: usize, const N: usize> GlobalAlloc for PerCpuBlockAllocator<CPUS, N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return dangling(&layout);
+        }
+        let index = match self.shared.alloc_class(&layout) {
+            Some(index) => index,
+            None => return self.shared.alloc_direct(layout),
+        };
+        let mut cache = self.cache();
+        match self.shared.take(&mut cache[index], index, PER_CPU_BATCH) {
+            Some(ptr) => ptr,
+            // 后备堆不够用了，交给共享分配器请回收函数释放内存
+            None => {
+                drop(cache);
+                self.shared.alloc_block(index)
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        match self.shared.dealloc_class(ptr, &layout) {
+            Some(index) => self
+                .shared
+                .put(&mut self.cache()[index], index, ptr, PER_CPU_BATCH),
+            None => self.shared.dealloc_direct(ptr, layout),
+        }
+    }
+}
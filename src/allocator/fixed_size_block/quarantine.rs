@@ -0,0 +1,106 @@
+//! 启用 `heap-quarantine` feature 时释放的块先进入的隔离区。
+//!
+//! 每个类一个先进先出的队列：块按释放的顺序排队，队列超过深度时最早的块才真正放回空闲列表或
+//! slab。这样释放后使用的代码在较长的时间里读写的仍然是一块没有被重新分配的内存，不会悄悄写坏
+//! 别人的数据；启用 `heap-poison` 时块出队前检查填充，能确定地发现这些写入。
+//!
+//! 队列直接串在块里，和空闲列表一样占用块开头的 `usize`（以及启用 `heap-verify` 时的 magic）。
+
+use super::{verify_magic, write_magic};
+use core::ptr;
+
+/// 每个类的隔离区默认最多容纳的块数。
+pub const DEFAULT_QUARANTINE_DEPTH: usize = 32;
+
+/// 每个类一个的隔离队列。
+pub(super) struct Quarantine<const N: usize> {
+    heads: [*mut u8; N],
+    tails: [*mut u8; N],
+    lens: [usize; N],
+    depth: usize,
+}
+
+// 队列中的指针只指向分配器自己管理的堆内存，所有访问都经过 `Locked` 的锁
+unsafe impl<const N: usize> Send for Quarantine<N> {}
+
+impl<const N: usize> Quarantine<N> {
+    pub(super) const fn new() -> Self {
+        Quarantine {
+            heads: [ptr::null_mut(); N],
+            tails: [ptr::null_mut(); N],
+            lens: [0; N],
+            depth: DEFAULT_QUARANTINE_DEPTH,
+        }
+    }
+
+    pub(super) fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// 只改变深度；超出新深度的块由调用者用 [`pop_excess`](Self::pop_excess) 取出。
+    pub(super) fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    /// 第 `index` 个类的隔离区中的块数。
+    pub(super) fn len(&self, index: usize) -> usize {
+        self.lens[index]
+    }
+
+    /// 把第 `index` 个类（块大小为 `block_size`）刚释放的块 `ptr` 放到队尾。
+    ///
+    /// 调用者必须保证 `ptr` 是这个类的一个不再被使用的块。
+    pub(super) unsafe fn push(&mut self, index: usize, block_size: usize, ptr: *mut u8) {
+        (ptr as *mut *mut u8).write(ptr::null_mut());
+        write_magic(ptr, block_size);
+        let tail = self.tails[index];
+        if tail.is_null() {
+            self.heads[index] = ptr;
+        } else {
+            (tail as *mut *mut u8).write(ptr);
+        }
+        self.tails[index] = ptr;
+        self.lens[index] += 1;
+    }
+
+    /// 第 `index` 个类的队列超过深度时取出最早进入的块，否则返回 `None`。
+    pub(super) fn pop_excess(&mut self, index: usize, block_size: usize) -> Option<*mut u8> {
+        if self.lens[index] <= self.depth {
+            return None;
+        }
+        self.pop(index, block_size)
+    }
+
+    /// 取出第 `index` 个类中最早进入隔离区的块，队列为空时返回 `None`。
+    pub(super) fn pop(&mut self, index: usize, block_size: usize) -> Option<*mut u8> {
+        let head = self.heads[index];
+        if head.is_null() {
+            return None;
+        }
+        // 队列中的块都是分配器管理的内存，先检查 magic 再跟随其中的指针
+        let next = unsafe {
+            verify_magic(head, block_size);
+            (head as *const *mut u8).read()
+        };
+        self.heads[index] = next;
+        if next.is_null() {
+            self.tails[index] = ptr::null_mut();
+        }
+        self.lens[index] -= 1;
+        Some(head)
+    }
+
+    /// `ptr` 是否在第 `index` 个类的隔离区中。
+    #[cfg(feature = "heap-debug")]
+    pub(super) fn contains(&self, index: usize, ptr: *mut u8) -> bool {
+        let mut node = self.heads[index];
+        while !node.is_null() {
+            if node == ptr {
+                return true;
+            }
+            // 队列中的块都是分配器管理的内存
+            node = unsafe { (node as *const *mut u8).read() };
+        }
+        false
+    }
+}
@@ -0,0 +1,184 @@
+//! 启用 `heap-slab` feature 时较小的类使用的 slab。
+//!
+//! 每个 slab 是从后备分配器按自身大小对齐分配的一页，开头是 [`SlabHeader`]，之后切成同一个类的块。
+//! 空闲块串在所属 slab 自己的链表里，所以释放时由块地址向下对齐就能找到 slab 并更新空闲计数；
+//! 块全部空闲时整个 slab 还给后备分配器。每个类还有空闲块的 slab 组成一个双向链表。
+
+use super::super::align_down;
+use super::{align_up, verify_magic, write_magic, ListNode};
+use core::{mem, ptr};
+
+/// 每个 slab 的大小，也是它的对齐。
+pub(super) const SLAB_SIZE: usize = 4096;
+
+/// 使用 slab 的最大块，保证每个 slab 至少有 7 个块；更大的类仍然使用空闲列表。
+pub(super) const MAX_SLAB_BLOCK: usize = SLAB_SIZE / 8;
+
+/// 位于每个 slab 开头的记录。
+struct SlabHeader {
+    /// slab 中空闲块的个数。
+    free: usize,
+    /// slab 中的空闲块链表。
+    blocks: Option<&'static mut ListNode>,
+    /// 同一个类中还有空闲块的前一个和后一个 slab。
+    prev: *mut SlabHeader,
+    next: *mut SlabHeader,
+}
+
+/// 块大小为 `block_size` 的类是否使用 slab。
+pub(super) fn uses_slabs(block_size: usize) -> bool {
+    block_size <= MAX_SLAB_BLOCK
+}
+
+/// 块大小为 `block_size` 的一个 slab 中块的个数：第一个块从对齐到块大小的头部之后开始。
+pub(super) fn capacity(block_size: usize) -> usize {
+    (SLAB_SIZE - align_up(mem::size_of::<SlabHeader>(), block_size)) / block_size
+}
+
+/// 包含 `ptr` 的 slab 的头部。
+fn header_of(ptr: *mut u8) -> *mut SlabHeader {
+    align_down(ptr as usize, SLAB_SIZE) as *mut SlabHeader
+}
+
+/// 每个类还有空闲块的 slab 链表。
+pub(super) struct Slabs<const N: usize> {
+    partial: [*mut SlabHeader; N],
+}
+
+// 链表指针只指向分配器自己管理的堆内存，所有访问都经过 `Locked` 的锁
+unsafe impl<const N: usize> Send for Slabs<N> {}
+
+impl<const N: usize> Slabs<N> {
+    pub(super) const fn new() -> Self {
+        Slabs {
+            partial: [ptr::null_mut(); N],
+        }
+    }
+
+    /// 把 `slab` 处刚从后备分配器分配的内存切成第 `index` 个类的 `block_size` 字节的块，
+    /// 返回块数。
+    ///
+    /// 调用者必须保证 `slab` 是 `SLAB_SIZE` 对齐的、大小为 `SLAB_SIZE` 的未使用内存。
+    pub(super) unsafe fn add(&mut self, index: usize, block_size: usize, slab: *mut u8) -> usize {
+        let first = align_up(mem::size_of::<SlabHeader>(), block_size);
+        let header = slab as *mut SlabHeader;
+        header.write(SlabHeader {
+            free: 0,
+            blocks: None,
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+        });
+        // 倒着压入，让块按地址从低到高分配
+        for i in (0..capacity(block_size)).rev() {
+            Self::push(&mut *header, block_size, slab.add(first + i * block_size));
+        }
+        self.link(index, header);
+        (*header).free
+    }
+
+    /// 从第 `index` 个类（块大小为 `block_size`）中还有空闲块的 slab 取出一个块，
+    /// 没有这样的 slab 时返回 `None`。
+    pub(super) fn alloc(&mut self, index: usize, block_size: usize) -> Option<*mut u8> {
+        let header = self.partial[index];
+        if header.is_null() {
+            return None;
+        }
+        // 链表中的 slab 都有空闲块
+        let slab = unsafe { &mut *header };
+        let node = slab.blocks.take()?;
+        let ptr = node as *mut ListNode as *mut u8;
+        // 块在 slab 的空闲链表中，先检查 magic 再跟随 `next`
+        unsafe { verify_magic(ptr, block_size) };
+        slab.blocks = unsafe { (*(ptr as *mut ListNode)).next.take() };
+        slab.free -= 1;
+        if slab.free == 0 {
+            unsafe { self.unlink(index, header) };
+        }
+        Some(ptr)
+    }
+
+    /// 把从第 `index` 个类分配的 `block_size` 字节的 `ptr` 放回它的 slab。
+    ///
+    /// slab 因此全部空闲时把它从链表中摘下并返回它的地址，调用者应当把它还给后备分配器。
+    pub(super) unsafe fn free(
+        &mut self,
+        index: usize,
+        block_size: usize,
+        ptr: *mut u8,
+    ) -> Option<*mut u8> {
+        let header = header_of(ptr);
+        Self::push(&mut *header, block_size, ptr);
+        let free = (*header).free;
+        if free == 1 {
+            self.link(index, header);
+        }
+        if free == capacity(block_size) {
+            self.unlink(index, header);
+            return Some(header as *mut u8);
+        }
+        None
+    }
+
+    /// 摘下第 `index` 个类（块大小为 `block_size`）中一个全部空闲的 slab 并返回它的地址，
+    /// 没有时返回 `None`。
+    pub(super) fn take_empty(&mut self, index: usize, block_size: usize) -> Option<*mut u8> {
+        let mut header = self.partial[index];
+        while !header.is_null() {
+            // 链表中的 slab 都是分配器管理的内存
+            let (free, next) = unsafe { ((*header).free, (*header).next) };
+            if free == capacity(block_size) {
+                unsafe { self.unlink(index, header) };
+                return Some(header as *mut u8);
+            }
+            header = next;
+        }
+        None
+    }
+
+    /// `ptr` 是否已经在它所属的 slab 的空闲链表中。
+    #[cfg(feature = "heap-debug")]
+    pub(super) unsafe fn is_free(&self, ptr: *mut u8) -> bool {
+        let mut node = (*header_of(ptr)).blocks.as_deref();
+        while let Some(current) = node {
+            if current as *const ListNode as *mut u8 == ptr {
+                return true;
+            }
+            node = current.next.as_deref();
+        }
+        false
+    }
+
+    unsafe fn push(slab: &mut SlabHeader, block_size: usize, ptr: *mut u8) {
+        let node = ptr as *mut ListNode;
+        node.write(ListNode {
+            next: slab.blocks.take(),
+        });
+        write_magic(ptr, block_size);
+        slab.blocks = Some(&mut *node);
+        slab.free += 1;
+    }
+
+    /// 把 `header` 挂到第 `index` 个类的链表头部。
+    unsafe fn link(&mut self, index: usize, header: *mut SlabHeader) {
+        let head = self.partial[index];
+        (*header).prev = ptr::null_mut();
+        (*header).next = head;
+        if !head.is_null() {
+            (*head).prev = header;
+        }
+        self.partial[index] = header;
+    }
+
+    /// 把 `header` 从第 `index` 个类的链表中摘下。
+    unsafe fn unlink(&mut self, index: usize, header: *mut SlabHeader) {
+        let (prev, next) = ((*header).prev, (*header).next);
+        if prev.is_null() {
+            self.partial[index] = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+    }
+}
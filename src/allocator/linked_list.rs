@@ -1,141 +1,2309 @@
 use core::{
-    alloc::{GlobalAlloc, Layout},
-    mem, ptr,
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    fmt,
+    mem::{self, MaybeUninit},
+    ptr::{self, NonNull},
 };
 
-use crate::allocator::align_up;
+use crate::{
+    allocator::{align_down, align_up, checked_align_up, dangling, is_aligned},
+    serial_println,
+};
 
 use super::Locked;
 
-struct ListNode {
-    size: usize,
-    next: Option<&'static mut ListNode>,
+#[cfg(not(feature = "heap-tree"))]
+mod bins;
+mod node;
+#[cfg(feature = "heap-tree")]
+mod tree;
+
+#[cfg(not(feature = "heap-tree"))]
+use bins::{bin_of, Nodes, BIN_COUNT};
+use node::{ListNode, FREE, NODE_MAGIC};
+
+/// 边界标记占用的字节数。每个块开头是头部标记，结尾是内容相同的脚标。
+const TAG_SIZE: usize = mem::size_of::<usize>();
+
+/// 启用 `heap-redzone` feature 时分配前后各留出的红区字节数。
+#[cfg(feature = "heap-redzone")]
+const REDZONE_SIZE: usize = 8;
+#[cfg(not(feature = "heap-redzone"))]
+const REDZONE_SIZE: usize = 0;
+/// 红区的填充值。
+#[cfg(feature = "heap-redzone")]
+const REDZONE: u8 = 0xFD;
+
+/// 块开头到分配起始地址之间的字节数：头部标记，以及启用时的前红区。
+pub const HEADER_SIZE: usize = TAG_SIZE + REDZONE_SIZE;
+/// 分配结尾（向上取整到 8 字节）到块结尾之间至少留出的字节数：启用时的后红区，以及脚标。
+pub const FOOTER_SIZE: usize = REDZONE_SIZE + TAG_SIZE;
+/// 最小的块：空闲时要能放下 `ListNode`（包括 `magic`）和脚标。
+pub const MIN_BLOCK_SIZE: usize = mem::size_of::<ListNode>() + TAG_SIZE;
+
+/// 启用 `heap-poison` feature 时，空闲块中 `ListNode` 之后的字节都填充为这个值。
+#[cfg(feature = "heap-poison")]
+pub const POISON: u8 = 0xDE;
+
+/// 启用 `heap-registry` feature 时，分配记录表最多容纳这么多个分配。
+#[cfg(feature = "heap-registry")]
+pub const REGISTRY_CAPACITY: usize = 128;
+
+/// 分配记录表中的一项。
+#[cfg(feature = "heap-registry")]
+#[derive(Clone, Copy)]
+struct LiveAllocation {
+    addr: usize,
+    layout: Layout,
+}
+
+/// 按 (大小, 地址) 从小到大遍历树中的空闲区域的节点，每一步都从根查找后继。
+#[cfg(feature = "heap-tree")]
+struct Nodes<'a> {
+    node: *mut ListNode,
+    root: &'a *mut ListNode,
+}
+#[cfg(feature = "heap-tree")]
+impl<'a> Iterator for Nodes<'a> {
+    type Item = *mut ListNode;
+
+    fn next(&mut self) -> Option<*mut ListNode> {
+        if self.node.is_null() {
+            return None;
+        }
+        let node = self.node;
+        // 树中的指针要么为空，要么指向堆内的空闲块
+        self.node = unsafe { tree::successor(*self.root, &*node) };
+        Some(node)
+    }
+}
+
+/// 读取 `addr` 处的边界标记。
+unsafe fn read_tag(addr: usize) -> usize {
+    (addr as *const usize).read()
+}
+
+/// 写入 `start..start + size` 这个块的头部标记和脚标。
+unsafe fn set_tags(start: usize, size: usize, flags: usize) {
+    let tag = size | flags;
+    (start as *mut usize).write(tag);
+    ((start + size - TAG_SIZE) as *mut usize).write(tag);
+}
+
+/// 启用 `heap-redzone` 时填充 `ptr` 这个大小为 `size` 的分配前后的红区。
+///
+/// 后红区从分配的结尾一直延伸到脚标，包括取整和并入分配的剩余部分。
+#[inline]
+unsafe fn fill_redzones(ptr: usize, size: usize) {
+    #[cfg(feature = "heap-redzone")]
+    {
+        let block_start = ptr - HEADER_SIZE;
+        let block_end = block_start + read_tag(block_start);
+        ptr::write_bytes((block_start + TAG_SIZE) as *mut u8, REDZONE, REDZONE_SIZE);
+        ptr::write_bytes(
+            (ptr + size) as *mut u8,
+            REDZONE,
+            block_end - TAG_SIZE - (ptr + size),
+        );
+    }
+    #[cfg(not(feature = "heap-redzone"))]
+    let _ = (ptr, size);
+}
+
+/// 启用 `heap-redzone` 时检查 `ptr` 这个分配前后的红区，被改写时以地址和布局 panic。
+#[inline]
+unsafe fn check_redzones(ptr: usize, layout: Layout) {
+    #[cfg(feature = "heap-redzone")]
+    {
+        let block_start = ptr - HEADER_SIZE;
+        let block_end = block_start + read_tag(block_start);
+        let front =
+            core::slice::from_raw_parts((block_start + TAG_SIZE) as *const u8, REDZONE_SIZE);
+        // 布局由调用者传入，不能假定加上大小不会溢出
+        let rear_start = ptr.saturating_add(layout.size());
+        let rear = core::slice::from_raw_parts(
+            rear_start as *const u8,
+            (block_end - TAG_SIZE).saturating_sub(rear_start),
+        );
+        if front.iter().chain(rear).any(|&byte| byte != REDZONE) {
+            panic!("heap redzone overwritten around {:#x} ({:?})", ptr, layout);
+        }
+    }
+    #[cfg(not(feature = "heap-redzone"))]
+    let _ = (ptr, layout);
+}
+
+/// 传给 `dealloc`/`realloc` 的指针无法对应到一个已分配的块。
+enum InvalidPointer {
+    /// 头部标记不在堆内，指针不是从这个堆分配的。
+    OutsideHeap,
+    /// 头部标记表明块已经被释放过。
+    Freed,
+    /// 边界标记不合理，已被覆盖或指针不指向分配的起点。
+    BadHeader,
+}
+
+/// `extend` 拒绝给定内存区域的原因。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtendError {
+    /// 起始地址或大小不是 `ListNode` 对齐的倍数。
+    Misaligned,
+    /// 区域放不下一个最小的空闲块。
+    TooSmall,
+    /// 区域与已有的堆范围重叠，或者越过了地址空间的末尾。
+    Overlapping,
+    /// 已经记录了 `MAX_HEAP_RANGES` 个互不相邻的堆范围。
+    TooManyRanges,
+}
+
+/// 堆最多由这么多段互不相邻的内存组成。
+pub const MAX_HEAP_RANGES: usize = 8;
+
+/// `release_tail` 按这个粒度归还内存。
+pub const PAGE_SIZE: usize = 4096;
+
+/// 查找空闲区域时使用的放置策略。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// 使用第一个足够大的区域（默认），但前面几个区域里有正好放得下的区域时优先使用它。
+    FirstFit,
+    /// 扫描整个列表，使用能满足请求的最小区域。
+    BestFit,
+    /// 从上一次成功分配的位置继续查找，到达表尾后从表头绕回一次。
+    NextFit,
+}
+
+/// `dump` 最多跟随的节点数，超过时认为列表已损坏（例如出现了环）。
+const MAX_DUMP_NODES: usize = 10_000;
+
+/// first-fit 在最前面的这么多个区域里优先选择不用拆分的区域。
+#[cfg(not(feature = "heap-tree"))]
+const EXACT_FIT_LOOKAHEAD: usize = 16;
+
+/// 空闲列表的统计信息快照。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HeapStats {
+    /// 所有空闲区域的总字节数。
+    pub free_bytes: usize,
+    /// 空闲区域的个数。
+    pub region_count: usize,
+    /// 最大空闲区域的字节数。
+    pub largest_free_region: usize,
+    /// 尚未释放的分配个数。
+    pub allocations: usize,
+    /// 这些分配的头部标记和脚标（以及启用时的红区）共占用的字节数。
+    pub tag_bytes: usize,
+    /// 这些分配请求的 `Layout::size()` 之和。
+    pub user_bytes: usize,
+    /// 为这些分配实际划出的块的总字节数，包括大小的调整、边界标记和并入分配的剩余部分。
+    pub reserved_bytes: usize,
+    /// 分配器自身的开销：`reserved_bytes - user_bytes`。
+    pub overhead: usize,
+    /// 因为未对齐或放不下空闲块而没有加入空闲列表的字节数。
+    pub dropped_bytes: usize,
+}
+
+/// `check_consistency` 通过时的结果。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapCheck {
+    /// 空闲列表中的区域个数。
+    pub regions: usize,
+    /// 这些区域的总字节数。
+    pub free_bytes: usize,
+    /// 按地址遍历所有堆范围时找到的块（空闲的和已分配的）个数。
+    pub blocks: usize,
 }
-impl ListNode {
-    const fn new(size: usize) -> Self {
-        ListNode { size, next: None }
+
+/// `check_consistency` 发现的第一处损坏。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapCorruption {
+    /// 出问题的节点或块的地址。
+    pub addr: usize,
+    pub reason: CorruptionReason,
+}
+
+/// 堆损坏的具体原因。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionReason {
+    /// 节点不在任何堆范围内，或者块越过了范围的结尾。
+    OutsideHeap,
+    /// 节点地址没有按 `ListNode` 对齐。
+    Misaligned,
+    /// 块的大小小于最小块或不是对齐的倍数。
+    BadSize,
+    /// 列表中的节点没有被标记为空闲。
+    NotFree,
+    /// 块的脚标与头部标记不一致。
+    BadFooter,
+    /// 节点的 `magic` 与它的大小不符，节点多半被释放后使用改写了。
+    BadMagic,
+    /// 节点的 `prev` 指针没有指向列表中的前一个节点；启用 `heap-tree` 时表示树的顺序被破坏。
+    BrokenLink,
+    /// 区域所在的分箱与它的大小不符。
+    WrongBin,
+    /// 两个空闲块物理上相邻却没有合并。
+    NotCoalesced,
+    /// 按地址遍历时块没有恰好铺满堆范围，说明有块相互重叠。
+    Overlapping,
+    /// 列表的节点数超过了堆能容纳的最小块数，多半是出现了环。
+    TooManyNodes,
+    /// 空闲列表与按地址遍历找到的空闲块不一致。
+    ListMismatch,
+}
+
+impl fmt::Display for HeapCorruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "heap corrupted at {:#x}: {:?}", self.addr, self.reason)
+    }
+}
+
+/// `check_accounting` 发现的账目不平：交给分配器的每个字节都应该正好属于其中一项。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountingMismatch {
+    /// 已分配的块（包括边界标记）占用的字节数。
+    pub used_bytes: usize,
+    /// 所有空闲区域的总字节数。
+    pub free_bytes: usize,
+    /// 因为未对齐或放不下空闲块而丢弃的字节数。
+    pub dropped_bytes: usize,
+    /// `init` 和 `extend` 交给分配器、且没有被 `release_tail` 归还的字节数。
+    pub heap_size: usize,
+}
+
+impl fmt::Display for AccountingMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "heap accounting mismatch: {} used + {} free + {} dropped != {} bytes",
+            self.used_bytes, self.free_bytes, self.dropped_bytes, self.heap_size
+        )
+    }
+}
+
+/// 延迟直方图的桶数：第 `i` 个桶统计耗时在 `2^i..2^(i+1)` 个周期之间的调用，最后一个桶没有上限。
+#[cfg(feature = "heap-latency")]
+pub const LATENCY_BUCKETS: usize = 24;
+
+/// 读取周期计数器的函数，见 [`LinkedListAllocator::set_cycle_counter`]。
+#[cfg(feature = "heap-latency")]
+pub type CycleCounter = fn() -> u64;
+
+/// 默认的周期计数器：读取 TSC。
+#[cfg(feature = "heap-latency")]
+fn read_tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// 一类分配器调用的耗时统计，单位是周期计数器的计数。
+#[cfg(feature = "heap-latency")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// 测量过的调用次数。
+    pub count: u64,
+    /// 这些调用的总耗时。
+    pub total_cycles: u64,
+    /// 最快和最慢的一次调用，还没有调用时分别是 `u64::MAX` 和 0。
+    pub min_cycles: u64,
+    pub max_cycles: u64,
+    /// 按耗时的以 2 为底的对数分桶的调用次数。
+    pub histogram: [u64; LATENCY_BUCKETS],
+}
+
+#[cfg(feature = "heap-latency")]
+impl LatencyStats {
+    const fn new() -> Self {
+        Self {
+            count: 0,
+            total_cycles: 0,
+            min_cycles: u64::MAX,
+            max_cycles: 0,
+            histogram: [0; LATENCY_BUCKETS],
+        }
+    }
+
+    /// 返回平均每次调用的耗时，还没有调用时返回 0。
+    pub fn average_cycles(&self) -> u64 {
+        self.total_cycles.checked_div(self.count).unwrap_or(0)
     }
 
-    fn start_addr(&self) -> usize {
-        self as *const Self as usize
+    fn record(&mut self, cycles: u64) {
+        self.count += 1;
+        self.total_cycles = self.total_cycles.saturating_add(cycles);
+        self.min_cycles = self.min_cycles.min(cycles);
+        self.max_cycles = self.max_cycles.max(cycles);
+        let bucket = cycles.checked_ilog2().unwrap_or(0) as usize;
+        self.histogram[bucket.min(LATENCY_BUCKETS - 1)] += 1;
     }
+}
+
+#[cfg(feature = "heap-latency")]
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "no calls");
+        }
+        write!(
+            f,
+            "{} calls, min {} / avg {} / max {} cycles",
+            self.count,
+            self.min_cycles,
+            self.average_cycles(),
+            self.max_cycles
+        )?;
+        for (bucket, &count) in self.histogram.iter().enumerate() {
+            if count > 0 {
+                write!(f, "\n  >= 2^{:<2} cycles: {}", bucket, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `GlobalAlloc` 各个方法的耗时统计，见 [`LinkedListAllocator::latency_stats`]。
+#[cfg(feature = "heap-latency")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyReport {
+    /// `alloc` 和 `alloc_zeroed`。
+    pub alloc: LatencyStats,
+    pub dealloc: LatencyStats,
+    pub realloc: LatencyStats,
+}
 
-    fn end_addr(&self) -> usize {
-        self.start_addr() + self.size
+#[cfg(feature = "heap-latency")]
+impl LatencyReport {
+    const fn new() -> Self {
+        Self {
+            alloc: LatencyStats::new(),
+            dealloc: LatencyStats::new(),
+            realloc: LatencyStats::new(),
+        }
     }
 }
+
+/// 被测量耗时的 `GlobalAlloc` 方法。
+#[derive(Clone, Copy)]
+enum Operation {
+    Alloc,
+    Dealloc,
+    Realloc,
+}
+
+/// 启用 `heap-verify` feature 时，每隔这么多次分配自动检查一次堆。
+#[cfg(feature = "heap-verify")]
+const VERIFY_INTERVAL: usize = 64;
+
+/// 分配失败、即将返回空指针时调用的钩子，见 [`LinkedListAllocator::set_oom_hook`]。
+pub type OomHook = fn(&LinkedListAllocator, Layout);
+
+/// 默认的 OOM 钩子：通过串口打印失败的布局和堆的状态。
+fn log_oom(allocator: &LinkedListAllocator, layout: Layout) {
+    serial_println!(
+        "heap: out of memory allocating {:?}: {} bytes free, largest region {} bytes, fragmentation {}‰",
+        layout,
+        allocator.free_bytes(),
+        allocator.largest_free_region(),
+        allocator.fragmentation()
+    );
+}
+
 pub struct LinkedListAllocator {
-    head: ListNode,
+    /// 各个分箱的表头。
+    #[cfg(not(feature = "heap-tree"))]
+    bins: [*mut ListNode; BIN_COUNT],
+    /// 启用 `heap-tree` 时代替分箱的树的根。
+    #[cfg(feature = "heap-tree")]
+    root: *mut ListNode,
+    #[cfg_attr(feature = "heap-tree", allow(dead_code))]
+    policy: Policy,
+    /// next-fit 的游标：下一次查找从这个节点开始，空指针表示从表头开始。
+    ///
+    /// 游标必须始终指向列表中的某个节点，所以摘除该节点时要同步修正它。
+    #[cfg(not(feature = "heap-tree"))]
+    rover: *mut ListNode,
+    /// 分配时累计检查过的空闲区域数。
+    regions_scanned: usize,
+    /// `init` 和 `extend` 给定的堆范围 (start, end)，前 `range_count` 项有效。
+    ///
+    /// 用于检查释放的指针，以及判断相邻块是否存在：合并永远不会越过范围的边界。
+    ranges: [(usize, usize); MAX_HEAP_RANGES],
+    range_count: usize,
+    /// 从这个地址到堆尾的内存自 `init_zeroed` 以来从未分配出去过，仍然是全零的，
+    /// 只有恰好位于这个地址的 `ListNode` 和堆尾的脚标例外。
+    pristine_start: usize,
+    /// 尚未释放的分配个数。
+    allocations: usize,
+    /// 通过 `GlobalAlloc` 成功分配、释放和调整大小的累计次数，零大小的分配不计入。
+    total_allocs: usize,
+    total_deallocs: usize,
+    total_reallocs: usize,
+    /// 已分配的块（包括边界标记）当前占用的字节数，以及它曾经达到的最大值。
+    used_bytes: usize,
+    peak_bytes: usize,
+    /// 尚未释放的分配请求的字节数之和，按调用者传入的布局计算。
+    user_bytes: usize,
+    /// `add_free_region` 丢弃的字节数，见 [`HeapStats::dropped_bytes`]。
+    dropped_bytes: usize,
+    /// `init` 和 `extend` 交给分配器的总字节数，减去 `release_tail` 归还的部分。
+    heap_size: usize,
+    /// 距离下一次自动检查还剩的分配次数。
+    #[cfg(feature = "heap-verify")]
+    verify_countdown: usize,
+    /// 分配或原地调整后剩下的部分至少有这么大才会拆分成空闲块，否则并入分配。
+    min_split: usize,
+    oom_hook: OomHook,
+    /// 启用 `heap-registry` 时记录尚未释放的分配的表，前 `registry_len` 项有效。
+    ///
+    /// 表放在分配器自身里而不是堆上，记录时不会再去分配内存。
+    #[cfg(feature = "heap-registry")]
+    registry: [LiveAllocation; REGISTRY_CAPACITY],
+    #[cfg(feature = "heap-registry")]
+    registry_len: usize,
+    /// 启用 `heap-latency` 时测量耗时用的周期计数器和统计结果。
+    #[cfg(feature = "heap-latency")]
+    cycle_counter: CycleCounter,
+    #[cfg(feature = "heap-latency")]
+    latency: LatencyReport,
 }
 
+// 列表指针只指向分配器自己管理的堆内存，所有访问都经过 `Locked` 的锁
+unsafe impl Send for LinkedListAllocator {}
+
 impl LinkedListAllocator {
     /// 创建一个空的 LinkedListAllocator。
     pub const fn new() -> Self {
         Self {
-            head: ListNode::new(0),
+            #[cfg(not(feature = "heap-tree"))]
+            bins: [ptr::null_mut(); BIN_COUNT],
+            #[cfg(feature = "heap-tree")]
+            root: ptr::null_mut(),
+            policy: Policy::FirstFit,
+            #[cfg(not(feature = "heap-tree"))]
+            rover: ptr::null_mut(),
+            regions_scanned: 0,
+            ranges: [(0, 0); MAX_HEAP_RANGES],
+            range_count: 0,
+            pristine_start: usize::MAX,
+            allocations: 0,
+            total_allocs: 0,
+            total_deallocs: 0,
+            total_reallocs: 0,
+            used_bytes: 0,
+            peak_bytes: 0,
+            user_bytes: 0,
+            dropped_bytes: 0,
+            heap_size: 0,
+            #[cfg(feature = "heap-verify")]
+            verify_countdown: VERIFY_INTERVAL,
+            min_split: MIN_BLOCK_SIZE,
+            oom_hook: log_oom,
+            #[cfg(feature = "heap-registry")]
+            registry: [LiveAllocation {
+                addr: 0,
+                layout: Layout::new::<u8>(),
+            }; REGISTRY_CAPACITY],
+            #[cfg(feature = "heap-registry")]
+            registry_len: 0,
+            #[cfg(feature = "heap-latency")]
+            cycle_counter: read_tsc,
+            #[cfg(feature = "heap-latency")]
+            latency: LatencyReport::new(),
         }
     }
 
+    /// 设置之后的分配所使用的放置策略。
+    ///
+    /// 启用 `heap-tree` 时策略不起作用，查找总是使用能满足请求的最小区域。
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
+    }
+
+    /// 设置拆分空闲区域的最小剩余大小，默认是能放下空闲块的最小值。
+    ///
+    /// 剩余部分小于这个值时整个并入分配，由边界标记记录，释放时一并归还，
+    /// 这样就不会留下几乎没用的小碎片。小于最小块的值会被提高到最小块。
+    pub fn set_min_split(&mut self, min_split: usize) {
+        self.min_split = align_up(min_split, mem::align_of::<ListNode>()).max(MIN_BLOCK_SIZE);
+    }
+
+    /// 设置分配失败时调用的钩子，默认通过串口打印堆的状态。
+    ///
+    /// 钩子在持有分配器的锁时调用，这时堆已经耗尽，所以钩子不能分配内存。
+    pub fn set_oom_hook(&mut self, hook: OomHook) {
+        self.oom_hook = hook;
+    }
+
+    /// 设置测量耗时用的周期计数器，默认读取 TSC。计数器会在分配器内部调用，不能使用堆。
+    #[cfg(feature = "heap-latency")]
+    pub fn set_cycle_counter(&mut self, counter: CycleCounter) {
+        self.cycle_counter = counter;
+    }
+
+    /// 返回自创建或上一次 `reset_latency_stats` 以来 `GlobalAlloc` 各个方法的耗时统计。
+    #[cfg(feature = "heap-latency")]
+    pub fn latency_stats(&self) -> LatencyReport {
+        self.latency
+    }
+
+    /// 清空耗时统计。
+    #[cfg(feature = "heap-latency")]
+    pub fn reset_latency_stats(&mut self) {
+        self.latency = LatencyReport::new();
+    }
+
+    /// 返回所有空闲区域的总字节数。
+    pub fn free_bytes(&self) -> usize {
+        self.regions().map(|region| region.size()).sum()
+    }
+
+    /// 返回空闲区域的个数。
+    pub fn region_count(&self) -> usize {
+        self.regions().count()
+    }
+
+    /// 按列表顺序遍历空闲区域，产生 (起始地址, 大小)。
+    ///
+    /// 迭代器不可变地借用分配器，不分配内存；通过 `Locked` 使用时它必须在锁守卫内用完，
+    /// 所以不会跨越分配而失效。
+    pub fn free_regions(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.regions()
+            .map(|region| (region.start_addr(), region.size()))
+    }
+
+    /// 返回小于 `size` 字节的空闲区域的个数。
+    pub fn regions_smaller_than(&self, size: usize) -> usize {
+        self.regions().filter(|region| region.size() < size).count()
+    }
+
+    /// 返回最大空闲区域的字节数，没有空闲区域时返回 0。
+    pub fn largest_free_region(&self) -> usize {
+        self.regions()
+            .map(|region| region.size())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// 返回已分配的块当前占用的字节数，包括边界标记和并入分配的剩余部分。
+    pub fn current_usage(&self) -> usize {
+        self.used_bytes
+    }
+
+    /// 返回 [`current_usage`](Self::current_usage) 自 `init` 以来到达过的最大值。
+    pub fn peak_usage(&self) -> usize {
+        self.peak_bytes
+    }
+
+    /// 返回自创建以来成功分配的次数，零大小的分配不计入。
+    pub fn total_allocs(&self) -> usize {
+        self.total_allocs
+    }
+
+    /// 返回自创建以来释放的次数。
+    pub fn total_deallocs(&self) -> usize {
+        self.total_deallocs
+    }
+
+    /// 返回成功调整大小的次数。调整大小不算作分配或释放，即使数据被搬到了新的块里。
+    pub fn total_reallocs(&self) -> usize {
+        self.total_reallocs
+    }
+
+    /// 返回尚未释放的分配个数，即 `total_allocs - total_deallocs`。
+    pub fn live_allocs(&self) -> usize {
+        self.total_allocs - self.total_deallocs
+    }
+
+    /// 把调整大小时内部的一次分配加一次释放改记为一次调整大小。
+    pub(super) fn count_moved_realloc(&mut self) {
+        self.total_allocs -= 1;
+        self.total_deallocs -= 1;
+        self.total_reallocs += 1;
+    }
+
+    /// 记录已分配的块多占用了 `bytes` 字节。
+    fn add_usage(&mut self, bytes: usize) {
+        self.used_bytes += bytes;
+        self.peak_bytes = self.peak_bytes.max(self.used_bytes);
+    }
+
+    /// 对每个尚未释放的分配调用 `f(地址, 布局)`，用来在测试结束时找出泄漏。
+    ///
+    /// 记录表只能容纳 `REGISTRY_CAPACITY` 个分配，表满以后的分配不会出现在这里。
+    #[cfg(feature = "heap-registry")]
+    pub fn live_allocations(&self, mut f: impl FnMut(usize, Layout)) {
+        for entry in &self.registry[..self.registry_len] {
+            f(entry.addr, entry.layout);
+        }
+    }
+
+    /// 返回记录表中尚未释放的分配个数。
+    #[cfg(feature = "heap-registry")]
+    pub fn live_allocation_count(&self) -> usize {
+        self.registry_len
+    }
+
+    /// 返回外部碎片率的千分比：`1 - 最大空闲区域 / 总空闲字节`。
+    ///
+    /// 只遍历一次列表。堆完全空闲（只有一个区域）或者没有空闲内存时都返回 0。
+    pub fn fragmentation(&self) -> usize {
+        let stats = self.stats();
+        if stats.free_bytes == 0 {
+            return 0;
+        }
+        1000 - stats.largest_free_region * 1000 / stats.free_bytes
+    }
+
+    /// 返回交给分配器管理的总字节数，见 [`AccountingMismatch::heap_size`]。
+    pub fn heap_size(&self) -> usize {
+        self.heap_size
+    }
+
+    /// 返回当前记录的堆范围 (start, end)，顺序不固定。
+    ///
+    /// 每个块和空闲区域都完整地位于其中一个范围内，范围之间的空隙不属于堆。
+    pub fn heap_ranges(&self) -> &[(usize, usize)] {
+        &self.ranges[..self.range_count]
+    }
+
+    /// 检查已分配、空闲和丢弃的字节数加起来正好等于 `heap_size`，即没有字节凭空消失。
+    ///
+    /// 需要遍历一次空闲列表。
+    pub fn check_accounting(&self) -> Result<(), AccountingMismatch> {
+        let accounting = AccountingMismatch {
+            used_bytes: self.used_bytes,
+            free_bytes: self.free_bytes(),
+            dropped_bytes: self.dropped_bytes,
+            heap_size: self.heap_size,
+        };
+        if accounting.used_bytes + accounting.free_bytes + accounting.dropped_bytes
+            == accounting.heap_size
+        {
+            Ok(())
+        } else {
+            Err(accounting)
+        }
+    }
+
+    /// 启用 `heap-accounting` feature 时，在 debug 构建中每次操作后检查账目。
+    #[inline]
+    fn debug_check_accounting(&self) {
+        #[cfg(all(feature = "heap-accounting", debug_assertions))]
+        if let Err(mismatch) = self.check_accounting() {
+            panic!("{}", mismatch);
+        }
+    }
+
+    /// 遍历一次空闲列表，返回全部统计信息。
+    ///
+    /// 只读地遍历列表，不会分配内存。
+    pub fn stats(&self) -> HeapStats {
+        let stats = HeapStats {
+            allocations: self.allocations,
+            tag_bytes: self.allocations * (HEADER_SIZE + FOOTER_SIZE),
+            user_bytes: self.user_bytes,
+            reserved_bytes: self.used_bytes,
+            overhead: self.used_bytes.saturating_sub(self.user_bytes),
+            dropped_bytes: self.dropped_bytes,
+            ..HeapStats::default()
+        };
+        self.regions().fold(stats, |stats, region| HeapStats {
+            free_bytes: stats.free_bytes + region.size(),
+            region_count: stats.region_count + 1,
+            largest_free_region: stats.largest_free_region.max(region.size()),
+            ..stats
+        })
+    }
+
+    /// 把空闲列表逐个区域打印到 `writer`，最后输出空闲和已分配内存的汇总。
+    ///
+    /// 不分配内存，所以可以在 panic 处理函数里调用；最多跟随 `MAX_DUMP_NODES`
+    /// 个节点，以免在损坏的列表上无限循环。
+    pub fn dump(&self, writer: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(writer, "heap free list:")?;
+        let mut free_bytes = 0;
+        let mut region_count = 0;
+        for region in self.regions() {
+            if region_count == MAX_DUMP_NODES {
+                writeln!(
+                    writer,
+                    "warning: stopped after {} regions, free list may be corrupted",
+                    MAX_DUMP_NODES
+                )?;
+                break;
+            }
+            writeln!(
+                writer,
+                "  {:#x}..{:#x} {} bytes",
+                region.start_addr(),
+                region.end_addr(),
+                region.size()
+            )?;
+            free_bytes += region.size();
+            region_count += 1;
+        }
+        writeln!(
+            writer,
+            "total free: {} bytes in {} regions",
+            free_bytes, region_count
+        )?;
+        writeln!(
+            writer,
+            "total allocated: {} bytes reserved for {} requested bytes in {} allocations, {} bytes overhead",
+            self.used_bytes,
+            self.user_bytes,
+            self.allocations,
+            self.used_bytes.saturating_sub(self.user_bytes)
+        )
+    }
+
+    /// 检查空闲列表和所有块的边界标记是否完好。
+    ///
+    /// 先沿着每个分箱的列表检查节点的位置、对齐、大小、分箱、标记和链接，再按地址遍历每个
+    /// 堆范围，确认块恰好铺满范围，并且其中的空闲块与列表一致。列表的遍历步数以堆能
+    /// 容纳的最小块数为上限，所以在有环的列表上也会结束。不分配内存，也不会 panic。
+    ///
+    /// 启用 `heap-tree` 时没有分箱，第一步改为检查树中的节点是否按 (大小, 地址) 排好序。
+    pub fn check_consistency(&self) -> Result<HeapCheck, HeapCorruption> {
+        let corrupt = |addr, reason| Err(HeapCorruption { addr, reason });
+        let align = mem::align_of::<ListNode>();
+        let heap_size: usize = self.ranges[..self.range_count]
+            .iter()
+            .map(|&(start, end)| end - start)
+            .sum();
+
+        let mut regions = 0;
+        let mut free_bytes = 0;
+        #[cfg(not(feature = "heap-tree"))]
+        for (bin, &head) in self.bins.iter().enumerate() {
+            let mut prev = ptr::null_mut();
+            let mut node = head;
+            while !node.is_null() {
+                let addr = node as usize;
+                if regions == heap_size / MIN_BLOCK_SIZE {
+                    return corrupt(addr, CorruptionReason::TooManyNodes);
+                }
+                let region = self.check_node(node)?;
+                if bin_of(region.size()) != bin {
+                    return corrupt(addr, CorruptionReason::WrongBin);
+                }
+                if region.prev != prev {
+                    return corrupt(addr, CorruptionReason::BrokenLink);
+                }
+                regions += 1;
+                free_bytes += region.size();
+                prev = node;
+                node = region.next;
+            }
+        }
+        #[cfg(feature = "heap-tree")]
+        self.check_subtree(
+            self.root,
+            ((0, 0), (usize::MAX, usize::MAX)),
+            heap_size / MIN_BLOCK_SIZE,
+            &mut regions,
+            &mut free_bytes,
+        )?;
+
+        let mut blocks = 0;
+        let mut free_blocks = 0;
+        let mut block_bytes = 0;
+        for &(start, end) in &self.ranges[..self.range_count] {
+            let mut addr = start;
+            let mut prev_free = false;
+            while addr < end {
+                // addr 总是范围内的块边界，之前的块都已经检查过
+                let tag = unsafe { read_tag(addr) };
+                let size = tag & !FREE;
+                if size < MIN_BLOCK_SIZE || !size.is_multiple_of(align) {
+                    return corrupt(addr, CorruptionReason::BadSize);
+                }
+                if size > end - addr {
+                    return corrupt(addr, CorruptionReason::Overlapping);
+                }
+                if unsafe { read_tag(addr + size - TAG_SIZE) } != tag {
+                    return corrupt(addr, CorruptionReason::BadFooter);
+                }
+                let free = tag & FREE != 0;
+                if free && prev_free {
+                    return corrupt(addr, CorruptionReason::NotCoalesced);
+                }
+                if free {
+                    free_blocks += 1;
+                    block_bytes += size;
+                }
+                prev_free = free;
+                blocks += 1;
+                addr += size;
+            }
+        }
+        if free_blocks != regions || block_bytes != free_bytes {
+            let first = self
+                .regions()
+                .next()
+                .map_or(0, |region| region.start_addr());
+            return corrupt(first, CorruptionReason::ListMismatch);
+        }
+
+        Ok(HeapCheck {
+            regions,
+            free_bytes,
+            blocks,
+        })
+    }
+
+    /// 检查空闲列表中的节点本身：位置、对齐、空闲标志、大小和脚标。
+    fn check_node(&self, node: *mut ListNode) -> Result<&ListNode, HeapCorruption> {
+        let corrupt = |addr, reason| Err(HeapCorruption { addr, reason });
+        let align = mem::align_of::<ListNode>();
+        let addr = node as usize;
+        if !is_aligned(addr, align) {
+            return corrupt(addr, CorruptionReason::Misaligned);
+        }
+        if !self.in_heap(addr, MIN_BLOCK_SIZE) {
+            return corrupt(addr, CorruptionReason::OutsideHeap);
+        }
+        // 节点在堆内并且对齐，可以读取
+        let region = unsafe { &*node };
+        if region.tag & FREE == 0 {
+            return corrupt(addr, CorruptionReason::NotFree);
+        }
+        let size = region.size();
+        if size < MIN_BLOCK_SIZE || !size.is_multiple_of(align) {
+            return corrupt(addr, CorruptionReason::BadSize);
+        }
+        if !self.in_heap(addr, size) {
+            return corrupt(addr, CorruptionReason::OutsideHeap);
+        }
+        if unsafe { read_tag(addr + size - TAG_SIZE) } != region.tag {
+            return corrupt(addr, CorruptionReason::BadFooter);
+        }
+        if region.magic != region.expected_magic() {
+            return corrupt(addr, CorruptionReason::BadMagic);
+        }
+        Ok(region)
+    }
+
+    /// 检查以 `node` 为根的子树，其中每个节点的 (大小, 地址) 都必须严格位于 `bounds` 之间。
+    ///
+    /// 键在每一层都严格收窄，所以出现环时一定会违反顺序；节点数仍以 `max_regions` 为上限。
+    #[cfg(feature = "heap-tree")]
+    fn check_subtree(
+        &self,
+        node: *mut ListNode,
+        bounds: ((usize, usize), (usize, usize)),
+        max_regions: usize,
+        regions: &mut usize,
+        free_bytes: &mut usize,
+    ) -> Result<(), HeapCorruption> {
+        if node.is_null() {
+            return Ok(());
+        }
+        let addr = node as usize;
+        if *regions == max_regions {
+            return Err(HeapCorruption {
+                addr,
+                reason: CorruptionReason::TooManyNodes,
+            });
+        }
+        let region = self.check_node(node)?;
+        let key = (region.size(), addr);
+        let (low, high) = bounds;
+        if key <= low || key >= high {
+            return Err(HeapCorruption {
+                addr,
+                reason: CorruptionReason::BrokenLink,
+            });
+        }
+        *regions += 1;
+        *free_bytes += region.size();
+        self.check_subtree(region.next, (low, key), max_regions, regions, free_bytes)?;
+        self.check_subtree(region.prev, (key, high), max_regions, regions, free_bytes)
+    }
+
+    /// 把每个分箱按地址排序，并合并所有物理上相邻的空闲区域，返回合并的次数。
+    ///
+    /// 排序是对节点本身的插入排序，不分配内存。释放时已经通过边界标记立即合并，
+    /// 所以在完好的堆上这里通常不会有可合并的区域；排序之后 first-fit 会优先使用
+    /// 低地址的区域，把高地址的内存留成大块。启用 `heap-tree` 时树本身有序，只做合并。
+    pub fn defragment(&mut self) -> usize {
+        // 节点都在堆内，重新链接时只改动 next/prev（insert_free 写回的标记不变）
+        unsafe {
+            #[cfg(not(feature = "heap-tree"))]
+            for bin in 0..BIN_COUNT {
+                let mut node = self.bins[bin];
+                self.bins[bin] = ptr::null_mut();
+                while !node.is_null() {
+                    let next = (*node).next;
+                    let mut after = ptr::null_mut();
+                    let mut cursor = self.bins[bin];
+                    while !cursor.is_null() && (cursor as usize) < (node as usize) {
+                        after = cursor;
+                        cursor = (*cursor).next;
+                    }
+                    self.insert_free(node as usize, (*node).size(), after);
+                    node = next;
+                }
+            }
+
+            let mut merges = 0;
+            loop {
+                let adjacent = self
+                    .regions()
+                    .map(|region| (region.start_addr(), region.end_addr()))
+                    .find(|&(start, end)| {
+                        self.range_of(start)
+                            .is_some_and(|(_, range_end)| end < range_end)
+                            && read_tag(end) & FREE != 0
+                    });
+                let Some((start, end)) = adjacent else {
+                    break;
+                };
+                let size = (end - start) + (read_tag(end) & !FREE);
+                self.unlink(start as *mut ListNode);
+                self.unlink(end as *mut ListNode);
+                self.insert_free(start, size, ptr::null_mut());
+                self.poison(end - TAG_SIZE, end + mem::size_of::<ListNode>());
+                merges += 1;
+            }
+            merges
+        }
+    }
+
+    /// 返回到目前为止所有分配在查找时检查过的空闲区域总数。
+    pub fn regions_scanned(&self) -> usize {
+        self.regions_scanned
+    }
+
+    /// 尝试原地把 `ptr` 这个分配扩大到 `new_size` 字节。
+    ///
+    /// 块里剩下的空间已经够用，或者物理上紧随其后的空闲区域能补上差额时成功，
+    /// 否则返回 `Err` 并且不做任何修改。调用者必须保证 `ptr` 和 `old_layout`
+    /// 描述的是这个分配器上一个尚未释放的分配。
+    #[allow(clippy::result_unit_err)]
+    pub unsafe fn grow_in_place(
+        &mut self,
+        ptr: *mut u8,
+        old_layout: Layout,
+        new_size: usize,
+    ) -> Result<(), ()> {
+        let (start, size) = self.block_of(ptr as usize).map_err(|_| ())?;
+        check_redzones(ptr as usize, old_layout);
+        let new_layout = Layout::from_size_align(new_size, old_layout.align()).map_err(|_| ())?;
+        let new_end = Self::block_end(ptr, new_layout).ok_or(())?;
+        if new_end > start + size && !self.grow_block(start, start + size, new_end) {
+            return Err(());
+        }
+        self.user_bytes = (self.user_bytes + new_size).saturating_sub(old_layout.size());
+        fill_redzones(ptr as usize, new_size);
+        self.update_record(ptr as usize, Some(new_layout));
+        self.debug_check_accounting();
+        Ok(())
+    }
+
+    /// 原地把 `ptr` 这个分配缩小到 `new_size` 字节。
+    ///
+    /// 空出来的尾部放得下空闲块时拆分出去，否则只有紧随其后的是空闲区域时才能并入其中。
+    /// `new_size` 大于原来的大小时返回 `Err`，对调用者的要求和 `grow_in_place` 相同。
+    #[allow(clippy::result_unit_err)]
+    pub unsafe fn shrink_in_place(
+        &mut self,
+        ptr: *mut u8,
+        old_layout: Layout,
+        new_size: usize,
+    ) -> Result<(), ()> {
+        if new_size > old_layout.size() {
+            return Err(());
+        }
+        let (start, size) = self.block_of(ptr as usize).map_err(|_| ())?;
+        check_redzones(ptr as usize, old_layout);
+        let new_layout = Layout::from_size_align(new_size, old_layout.align()).map_err(|_| ())?;
+        let new_end = Self::block_end(ptr, new_layout).ok_or(())?;
+        self.shrink_block(start, start + size, new_end);
+        self.user_bytes = (self.user_bytes + new_size).saturating_sub(old_layout.size());
+        fill_redzones(ptr as usize, new_size);
+        self.update_record(ptr as usize, Some(new_layout));
+        self.debug_check_accounting();
+        Ok(())
+    }
+
+    /// `GlobalAlloc::alloc` 在锁内的部分：切出带边界标记的块，记账并填充红区。
+    ///
+    /// 返回分配的起始地址；无法调整的布局和内存不足一样调用内存不足的钩子并返回 `None`。
+    pub(super) unsafe fn alloc_tagged(&mut self, layout: Layout) -> Option<usize> {
+        let allocated =
+            Self::size_align(layout).and_then(|(size, align)| self.allocate_block(size, align));
+        match allocated {
+            Some(alloc_start) => {
+                self.total_allocs += 1;
+                self.user_bytes += layout.size();
+                self.record(alloc_start, layout);
+                self.debug_check_accounting();
+                fill_redzones(alloc_start, layout.size());
+                Some(alloc_start)
+            }
+            None => {
+                (self.oom_hook)(self, layout);
+                None
+            }
+        }
+    }
+
+    /// `GlobalAlloc::alloc_zeroed` 在锁内的部分：和 `alloc_tagged` 一样分配，另外返回开头需要
+    /// 清零的字节数，由调用者清零。
+    pub(super) unsafe fn alloc_zeroed_tagged(&mut self, layout: Layout) -> Option<(usize, usize)> {
+        let pristine_start = self.pristine_start;
+        let alloc_start = self.alloc_tagged(layout)?;
+        let dirty_len = if alloc_start - HEADER_SIZE < pristine_start {
+            layout.size()
+        } else {
+            // 从未分配过的内存只可能在 `pristine_start` 处留有一个 ListNode，
+            // 堆尾的脚标则总在分配的结尾之后
+            let dirty_end = pristine_start + mem::size_of::<ListNode>();
+            dirty_end.saturating_sub(alloc_start).min(layout.size())
+        };
+        Some((alloc_start, dirty_len))
+    }
+
+    /// `GlobalAlloc::dealloc` 的实现，`layout` 的大小不为零。
+    ///
+    /// 重复释放时 panic；不是从这个堆分配的指针只打印一条提示。
+    pub(super) unsafe fn dealloc_tagged(&mut self, ptr: *mut u8, layout: Layout) {
+        match self.block_of(ptr as usize) {
+            Ok((start, size)) => {
+                check_redzones(ptr as usize, layout);
+                self.allocations -= 1;
+                self.total_deallocs += 1;
+                self.used_bytes -= size;
+                // 释放时的布局可能和分配时不同（`Allocator` 允许用不超过可用大小的布局释放，
+                // 块的大小也总是以标记为准），所以不能让计数下溢；全部释放以后一定归零
+                self.user_bytes = if self.allocations == 0 {
+                    0
+                } else {
+                    self.user_bytes.saturating_sub(layout.size())
+                };
+                self.update_record(ptr as usize, None);
+                self.add_free_region(start, size);
+                self.debug_check_accounting();
+            }
+            Err(InvalidPointer::Freed) => {
+                panic!("double free: {:#x} is already free", ptr as usize)
+            }
+            // 不是从这个堆分配的指针（栈地址、MMIO 等）-> 不能在那里写入 ListNode
+            Err(InvalidPointer::OutsideHeap) => {
+                serial_println!(
+                    "heap: ignoring dealloc of {:#x} outside the heap",
+                    ptr as usize
+                );
+            }
+            Err(InvalidPointer::BadHeader) => {
+                serial_println!(
+                    "heap: ignoring dealloc of {:#x} with a corrupted header",
+                    ptr as usize
+                );
+            }
+        }
+    }
+
+    /// `GlobalAlloc::realloc` 在锁内的部分：尝试原地调整 `ptr` 这个分配的大小。
+    ///
+    /// 调整成功时返回 `Ok(true)`，需要移动时返回 `Ok(false)`，之后移动的调用者应当调用
+    /// `count_moved_realloc`。无效的指针打印一条提示并返回 `Err`；已经释放的指针 panic。
+    pub(super) unsafe fn realloc_in_place(
+        &mut self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<bool, ()> {
+        match self.block_of(ptr as usize) {
+            Ok(_) => {}
+            Err(InvalidPointer::Freed) => {
+                panic!("realloc of freed pointer {:#x}", ptr as usize)
+            }
+            Err(_) => {
+                serial_println!(
+                    "heap: ignoring realloc of invalid pointer {:#x}",
+                    ptr as usize
+                );
+                return Err(());
+            }
+        }
+
+        let resized = if new_size <= layout.size() {
+            self.shrink_in_place(ptr, layout, new_size)
+        } else {
+            self.grow_in_place(ptr, layout, new_size)
+        };
+        if resized.is_ok() {
+            self.total_reallocs += 1;
+        }
+        Ok(resized.is_ok())
+    }
+
+    /// 返回 `ptr` 这个分配调整为 `new_layout` 以后块的结束地址，溢出时返回 `None`。
+    fn block_end(ptr: *mut u8, new_layout: Layout) -> Option<usize> {
+        let (size, _) = Self::size_align(new_layout)?;
+        (ptr as usize).checked_add(size)?.checked_add(FOOTER_SIZE)
+    }
+
+    /// 不经过 `GlobalAlloc`，从空闲区域中切出正好 `layout` 这么大的一段内存，不写边界标记。
+    ///
+    /// 用作别的分配器的后备堆，例如 `FixedSizeBlockAllocator`。
+    /// 大小向上取到 `ListNode` 的对齐并且不小于 [`MIN_BLOCK_SIZE`]，`deallocate` 按同样的方式取整，
+    /// 所以分出去的内存中任何满足这个要求的一段都可以单独释放。这样的分配没有边界标记，
+    /// 不能和 `GlobalAlloc` 的分配混用，也不能用 `check_consistency` 检查；它们计入
+    /// `current_usage`，但不计入 `total_allocs` 等次数。
+    ///
+    /// 从未分配过的内存分出去时，空闲区域留在其中的记录会被清零。
+    #[allow(clippy::result_unit_err)]
+    pub fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
+        let size = Self::range_size(layout.size()).ok_or(())?;
+        let align = layout.align().max(mem::align_of::<ListNode>());
+        let (node, start) = self
+            .nodes()
+            .find_map(|node| {
+                // 遍历产生的节点都在列表中，只在这里短暂地读取
+                Self::range_in_region(unsafe { &*node }, size, align).map(|start| (node, start))
+            })
+            .ok_or(())?;
+        let end = start + size;
+        unsafe {
+            let (region_start, region_end) = ((*node).start_addr(), (*node).end_addr());
+            self.unlink(node);
+            self.verify_poison(
+                start.max(region_start + mem::size_of::<ListNode>()),
+                end.min(region_end - TAG_SIZE),
+            );
+            // 从未分配过的内存中只有 `pristine_start` 处的节点和区域结尾的脚标不是零
+            if (start..end).contains(&self.pristine_start) {
+                let dirty_end = (self.pristine_start + mem::size_of::<ListNode>()).min(end);
+                ptr::write_bytes(
+                    self.pristine_start as *mut u8,
+                    0,
+                    dirty_end - self.pristine_start,
+                );
+            }
+            if end == region_end && end - TAG_SIZE >= self.pristine_start {
+                ptr::write_bytes((end - TAG_SIZE) as *mut u8, 0, TAG_SIZE);
+            }
+            if start > region_start {
+                self.insert_free(region_start, start - region_start, ptr::null_mut());
+            }
+            if end < region_end {
+                self.insert_free(end, region_end - end, ptr::null_mut());
+            }
+        }
+        self.add_usage(size);
+        self.pristine_start = self.pristine_start.max(end);
+        // 找到的起始地址在堆内，不为零
+        Ok(unsafe { NonNull::new_unchecked(start as *mut u8) })
+    }
+
+    /// 把 `allocate` 分出去的内存中的 `ptr..ptr + layout.size()` 放回空闲列表，大小按
+    /// `allocate` 的方式取整。
+    ///
+    /// 与相邻的空闲区域合并时遍历空闲列表，而不是读取边界标记：旁边的内存可能是同一次分配中
+    /// 尚未释放的部分。调用者必须保证这段内存来自 `allocate`，并且没有被释放过。
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let addr = ptr.as_ptr() as usize;
+        let size = Self::range_size(layout.size()).expect("deallocate with an invalid layout");
+        assert!(self.in_heap(addr, size), "free region outside the heap");
+        self.used_bytes -= size;
+        let before = self.nodes().find(|&node| (*node).end_addr() == addr);
+        let after = self.nodes().find(|&node| node as usize == addr + size);
+        let (mut start, mut end) = (addr, addr + size);
+        let (mut poison_start, mut poison_end) =
+            (addr + mem::size_of::<ListNode>(), addr + size - TAG_SIZE);
+        if let Some(node) = before {
+            start = (*node).start_addr();
+            poison_start = addr - TAG_SIZE;
+            self.unlink(node);
+        }
+        if let Some(node) = after {
+            end = (*node).end_addr();
+            poison_end = addr + size + mem::size_of::<ListNode>();
+            self.unlink(node);
+        }
+        // 释放的范围和被吞并的记录都填充；相邻区域里原有的填充保持不变
+        self.poison(poison_start, poison_end);
+        self.insert_free(start, end - start, ptr::null_mut());
+    }
+
+    /// `allocate` 和 `deallocate` 实际使用的大小，溢出时返回 `None`。
+    fn range_size(size: usize) -> Option<usize> {
+        let size = size.max(MIN_BLOCK_SIZE);
+        checked_align_up(size, mem::align_of::<ListNode>())
+    }
+
+    /// 在 `region` 中为 `allocate` 找一个 `align` 对齐的 `size` 字节的范围，返回起始地址。
+    ///
+    /// 范围前后剩下的部分要么为空，要么放得下空闲块。
+    fn range_in_region(region: &ListNode, size: usize, align: usize) -> Option<usize> {
+        let aligned_after = |addr: usize| checked_align_up(addr, align);
+        let mut start = aligned_after(region.start_addr())?;
+        if start > region.start_addr() && start - region.start_addr() < MIN_BLOCK_SIZE {
+            start = aligned_after(region.start_addr() + MIN_BLOCK_SIZE)?;
+        }
+        let rest = region.end_addr().checked_sub(start.checked_add(size)?)?;
+        (rest == 0 || rest >= MIN_BLOCK_SIZE).then_some(start)
+    }
+
+    /// 创建一个管理 `mem` 的分配器，例如从主堆借来的一块临时子堆。
+    ///
+    /// 这个函数是不安全的，因为分配器只保存指针：`mem` 的借用结束以后，调用者必须保证
+    /// 不再使用这个分配器，也不再使用从它分配出去的内存。
+    pub unsafe fn from_slice(mem: &mut [MaybeUninit<u8>]) -> Self {
+        let mut allocator = Self::new();
+        allocator.init(mem.as_mut_ptr() as usize, mem.len());
+        allocator
+    }
+
     /// 使用给定的堆边界初始化分配器。
     ///
     /// 这个函数是不安全的，因为调用者必须保证给定的堆边界是有效的，并且堆是未使用的。这个方法只能被调用一次。
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.init_with(heap_start, heap_size, false);
+    }
+
+    /// 与 [`init`](Self::init) 相同，但调用者还保证整个堆已经被清零（例如刚映射的页面），
+    /// 这样 `alloc_zeroed` 就可以跳过从未分配过的内存的清零。
+    pub unsafe fn init_zeroed(&mut self, heap_start: usize, heap_size: usize) {
+        self.init_with(heap_start, heap_size, true);
+    }
+
+    unsafe fn init_with(&mut self, heap_start: usize, heap_size: usize, zeroed: bool) {
+        // 堆范围只记录对齐后的部分，这样相邻块的检查不会读到范围之外
+        let (start, end) = Self::trim(heap_start, heap_size);
+        self.ranges[0] = if end >= start + MIN_BLOCK_SIZE {
+            (start, end)
+        } else {
+            (start, start)
+        };
+        self.range_count = 1;
+        // 先确定 pristine_start，这样全零的堆不会被填充
+        self.pristine_start = if zeroed { start } else { end };
+        self.heap_size = heap_size;
         self.add_free_region(heap_start, heap_size);
     }
 
-    /// 将给定的内存区域添加到列表的前面。
-    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
-        // 确保释放的区域能够容纳 ListNode
-        assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
-        assert!(size >= mem::size_of::<ListNode>());
-
-        // 创建一个新的列表节点并将其附加到列表的开头
-        let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
-        let node_ptr = addr as *mut ListNode;
-        node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr)
-    }
-    /// 查找具有给定大小和对齐方式的空闲区域，并将其从列表中移除。
-    ///
-    /// 返回一个包含列表节点和分配起始地址的元组。
-    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
-        // 对当前列表节点的引用，每次迭代都会更新
-        let mut current = &mut self.head;
-        // 在链表中查找足够大的内存区域
-        while let Some(ref mut region) = current.next {
-            if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
-                // 适合分配的区域 -> 从列表中移除节点
-                let next = region.next.take();
-                let ret = Some((current.next.take().unwrap(), alloc_start));
-                current.next = next;
-                return ret;
+    /// 在 `init` 之后把 `start..start + size` 加入堆。
+    ///
+    /// 与已有范围首尾相接的区域直接并入该范围，其余的作为新的范围记录下来。
+    /// 这段内存不保证为零：如有必要，`pristine_start` 会被推到它的结尾。
+    ///
+    /// 这个函数是不安全的，因为调用者必须保证给定的内存是有效的并且未被使用。
+    pub unsafe fn extend(&mut self, start: usize, size: usize) -> Result<(), ExtendError> {
+        self.add_range(start, size)?;
+        self.add_free_region(start, size);
+        Ok(())
+    }
+
+    /// 与 [`extend`](Self::extend) 相同，但和 [`deallocate`](Self::deallocate) 一样通过遍历
+    /// 空闲列表合并相邻的区域，用于扩展由 [`allocate`](Self::allocate) 分配的堆：
+    /// 新内存前面可能是一段没有边界标记的分配。
+    pub unsafe fn extend_untagged(&mut self, start: usize, size: usize) -> Result<(), ExtendError> {
+        self.add_range(start, size)?;
+        // 当作一次刚分配出去的分配释放
+        self.used_bytes += size;
+        let layout = Layout::from_size_align_unchecked(size, mem::align_of::<ListNode>());
+        self.deallocate(NonNull::new_unchecked(start as *mut u8), layout);
+        Ok(())
+    }
+
+    /// 检查 `start..start + size` 并把它记入堆范围，但还不放进空闲列表。
+    unsafe fn add_range(&mut self, start: usize, size: usize) -> Result<(), ExtendError> {
+        let align = mem::align_of::<ListNode>();
+        if !is_aligned(start, align) || !size.is_multiple_of(align) {
+            return Err(ExtendError::Misaligned);
+        }
+        if size < MIN_BLOCK_SIZE {
+            return Err(ExtendError::TooSmall);
+        }
+        let end = start.checked_add(size).ok_or(ExtendError::Overlapping)?;
+        let ranges = &self.ranges[..self.range_count];
+        if ranges
+            .iter()
+            .any(|&(range_start, range_end)| start < range_end && range_start < end)
+        {
+            return Err(ExtendError::Overlapping);
+        }
+
+        let below = ranges.iter().position(|&(_, range_end)| range_end == start);
+        let above = ranges
+            .iter()
+            .position(|&(range_start, _)| range_start == end);
+        match (below, above) {
+            (Some(below), Some(above)) => {
+                // 正好填上两个范围之间的空隙 -> 三段合成一个范围
+                self.ranges[below].1 = self.ranges[above].1;
+                self.range_count -= 1;
+                self.ranges[above] = self.ranges[self.range_count];
+            }
+            (Some(below), None) => self.ranges[below].1 = end,
+            (None, Some(above)) => self.ranges[above].0 = start,
+            (None, None) => {
+                if self.range_count == MAX_HEAP_RANGES {
+                    return Err(ExtendError::TooManyRanges);
+                }
+                self.ranges[self.range_count] = (start, end);
+                self.range_count += 1;
+            }
+        }
+        if end > self.pristine_start {
+            let old = self.pristine_start;
+            self.pristine_start = end;
+            // 原来从未分配过的内存不再算作全零，它所在的空闲块要补上填充
+            if cfg!(feature = "heap-poison") {
+                let pristine_region = self
+                    .free_regions()
+                    .find(|&(start, size)| start <= old && old < start + size);
+                if let Some((start, size)) = pristine_region {
+                    self.poison(
+                        old.max(start + mem::size_of::<ListNode>()),
+                        start + size - TAG_SIZE,
+                    );
+                }
+            }
+        }
+        self.heap_size += size;
+        Ok(())
+    }
+
+    /// 把每段堆范围末尾完全空闲的整页从堆中移除，对每段移除的内存调用 `unmap(start, size)`，
+    /// 返回移除的总字节数。
+    ///
+    /// 只有结尾页对齐的范围才会缩短；空闲区域前面不足一页的部分仍然留在空闲列表中。
+    /// 调用者负责取消映射并归还对应的帧，之后可以再用 `extend` 把它们加回来。
+    pub fn release_tail(&mut self, mut unmap: impl FnMut(usize, usize)) -> usize {
+        let mut released = 0;
+        let mut i = 0;
+        while i < self.range_count {
+            let (range_start, range_end) = self.ranges[i];
+            let tail = self
+                .nodes()
+                .find(|&node| unsafe { (*node).end_addr() } == range_end);
+            let tail = match tail {
+                Some(tail) if range_end.is_multiple_of(PAGE_SIZE) => tail,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let region_start = unsafe { (*tail).start_addr() };
+            let mut page_start = align_up(region_start, PAGE_SIZE);
+            if page_start - region_start < MIN_BLOCK_SIZE && page_start > region_start {
+                // 留下的部分放不下空闲块 -> 多留一页
+                page_start += PAGE_SIZE;
+            }
+            if page_start >= range_end {
+                i += 1;
+                continue;
+            }
+
+            unsafe { self.unlink(tail) };
+            self.ranges[i].1 = page_start;
+            if page_start > region_start {
+                unsafe { self.add_free_region(region_start, page_start - region_start) };
+            }
+            if page_start == range_start {
+                // 整段范围都归还了
+                self.range_count -= 1;
+                self.ranges[i] = self.ranges[self.range_count];
             } else {
-                // 区域不适合 -> 继续下一个区域
-                current = current.next.as_mut().unwrap();
+                i += 1;
             }
+            unmap(page_start, range_end - page_start);
+            released += range_end - page_start;
+        }
+        self.heap_size -= released;
+        released
+    }
+
+    /// 返回包含 `addr` 的堆范围。
+    fn range_of(&self, addr: usize) -> Option<(usize, usize)> {
+        self.ranges[..self.range_count]
+            .iter()
+            .copied()
+            .find(|&(start, end)| start <= addr && addr < end)
+    }
+
+    /// `addr..addr + size` 是否完全位于同一个堆范围内。
+    fn in_heap(&self, addr: usize, size: usize) -> bool {
+        self.range_of(addr).is_some_and(|(_, range_end)| {
+            addr.checked_add(size).is_some_and(|end| end <= range_end)
+        })
+    }
+
+    /// 把 `addr..addr + size` 向内收缩到 `ListNode` 对齐的边界，返回 (start, end)。
+    ///
+    /// 区域小于一个对齐单位时 `end` 可能小于 `start`。
+    fn trim(addr: usize, size: usize) -> (usize, usize) {
+        let align = mem::align_of::<ListNode>();
+        (align_up(addr, align), align_down(addr + size, align))
+    }
+
+    /// 把 `addr..addr + size` 归还到空闲列表。
+    ///
+    /// 区域先被收缩到 `ListNode` 对齐的边界，收缩后放不下空闲块的区域直接丢弃，
+    /// 丢掉的字节计入 `dropped_bytes`；启用 `strict-free-regions` feature 时
+    /// 这种情况会在 debug 构建中触发断言。
+    ///
+    /// 通过边界标记直接检查物理上相邻的两个块，空闲的就立即合并，不需要遍历列表：
+    /// 与前一个块合并时沿用它在列表中的位置，与后一个块合并时新块接替后者的位置，
+    /// 两边都不空闲时插入到表头。合并后换了分箱的区域插到新分箱的表头。
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        let (start, end) = Self::trim(addr, size);
+        #[cfg(feature = "strict-free-regions")]
+        debug_assert!(
+            start == addr && end == addr + size && size >= MIN_BLOCK_SIZE,
+            "bad free region {:#x} ({} bytes)",
+            addr,
+            size
+        );
+        if end < start + MIN_BLOCK_SIZE {
+            self.dropped_bytes += size;
+            return;
+        }
+        self.dropped_bytes += size - (end - start);
+        let (addr, size) = (start, end - start);
+
+        let (range_start, range_end) = self.range_of(addr).expect("free region outside the heap");
+
+        // 先把整个块标记为空闲：即使它随后被并入前一个块，留下的头部标记也能让重复释放被发现
+        set_tags(addr, size, FREE);
+        self.poison(addr + mem::size_of::<ListNode>(), addr + size - TAG_SIZE);
+        let mut size = size;
+        let mut after = ptr::null_mut();
+
+        let end = addr + size;
+        if end < range_end && read_tag(end) & FREE != 0 {
+            // 与后一个块相邻 -> 吞并它，并接替它在列表中的位置
+            let next = end as *mut ListNode;
+            (*next).verify();
+            after = (*next).prev;
+            size += (*next).size();
+            self.unlink(next);
+            self.poison(end - TAG_SIZE, end + mem::size_of::<ListNode>());
+        }
+
+        if addr > range_start {
+            let prev_tag = read_tag(addr - TAG_SIZE);
+            if prev_tag & FREE != 0 {
+                // 与前一个块相邻 -> 扩大前一个块，分箱不变时留在原来的位置
+                let prev_size = prev_tag & !FREE;
+                let prev = (addr - prev_size) as *mut ListNode;
+                (*prev).verify();
+                let prev_after = (*prev).prev;
+                self.poison(addr - TAG_SIZE, addr + mem::size_of::<ListNode>());
+                self.unlink(prev);
+                self.insert_free(addr - prev_size, prev_size + size, prev_after);
+                return;
+            }
+        }
+
+        self.insert_free(addr, size, after);
+    }
+
+    /// debug 构建中检查要插入的 `start..start + size` 不与已有的空闲区域重叠，例如同一块内存
+    /// 用不同的布局释放了两次。
+    ///
+    /// 列表不再按地址排序，所以要遍历所有区域；release 构建中不检查。
+    fn debug_check_overlap(&self, start: usize, size: usize) {
+        #[cfg(debug_assertions)]
+        if let Some(region) = self
+            .regions()
+            .find(|region| region.start_addr() < start + size && start < region.end_addr())
+        {
+            panic!(
+                "free region {:#x}..{:#x} overlaps free region {:#x}..{:#x}",
+                start,
+                start + size,
+                region.start_addr(),
+                region.end_addr()
+            );
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = (start, size);
+    }
+
+    /// 把 `start..start + size` 标记为空闲块，并插入到它的分箱中 `after` 之后。
+    ///
+    /// `after` 为空指针或者属于别的分箱时插入到表头。调用者保证物理上相邻的块都不空闲。
+    #[cfg(not(feature = "heap-tree"))]
+    unsafe fn insert_free(
+        &mut self,
+        start: usize,
+        size: usize,
+        after: *mut ListNode,
+    ) -> *mut ListNode {
+        self.debug_check_overlap(start, size);
+        set_tags(start, size, FREE);
+        let node = start as *mut ListNode;
+        (*node).magic = NODE_MAGIC ^ size;
+        bins::insert(&mut self.bins, node, after);
+        node
+    }
+
+    /// 启用 `heap-poison` 时把 `start..end` 中位于 `pristine_start` 之前的部分填充为 `POISON`，
+    /// 否则什么也不做。
+    #[inline]
+    unsafe fn poison(&self, start: usize, end: usize) {
+        #[cfg(feature = "heap-poison")]
+        {
+            let end = end.min(self.pristine_start);
+            if start < end {
+                ptr::write_bytes(start as *mut u8, POISON, end - start);
+            }
+        }
+        #[cfg(not(feature = "heap-poison"))]
+        let _ = (start, end);
+    }
+
+    /// 启用 `heap-registry` 时把新的分配记入表中，表满时不再记录。
+    fn record(&mut self, ptr: usize, layout: Layout) {
+        #[cfg(feature = "heap-registry")]
+        if self.registry_len < REGISTRY_CAPACITY {
+            self.registry[self.registry_len] = LiveAllocation { addr: ptr, layout };
+            self.registry_len += 1;
+        }
+        #[cfg(not(feature = "heap-registry"))]
+        let _ = (ptr, layout);
+    }
+
+    /// 启用 `heap-registry` 时更新表中 `ptr` 的布局，`None` 表示这个分配已经释放。
+    fn update_record(&mut self, ptr: usize, layout: Option<Layout>) {
+        #[cfg(feature = "heap-registry")]
+        {
+            let live = &self.registry[..self.registry_len];
+            if let Some(i) = live.iter().position(|entry| entry.addr == ptr) {
+                match layout {
+                    Some(layout) => self.registry[i].layout = layout,
+                    None => {
+                        self.registry_len -= 1;
+                        self.registry[i] = self.registry[self.registry_len];
+                    }
+                }
+            }
+        }
+        #[cfg(not(feature = "heap-registry"))]
+        let _ = (ptr, layout);
+    }
+
+    /// 启用 `heap-poison` 时检查 `start..end` 中位于 `pristine_start` 之前的部分仍然是
+    /// `POISON`，否则说明释放后的内存被写过，以第一个被改动的字节的地址 panic。
+    #[inline]
+    unsafe fn verify_poison(&self, start: usize, end: usize) {
+        #[cfg(feature = "heap-poison")]
+        {
+            let end = end.min(self.pristine_start);
+            if start < end {
+                let bytes = core::slice::from_raw_parts(start as *const u8, end - start);
+                if let Some(offset) = bytes.iter().position(|&byte| byte != POISON) {
+                    panic!(
+                        "use after free: heap byte {:#x} was modified after being freed",
+                        start + offset
+                    );
+                }
+            }
+        }
+        #[cfg(not(feature = "heap-poison"))]
+        let _ = (start, end);
+    }
+
+    /// 把 `start..start + size` 标记为空闲块并插入树中；树按大小排序，`after` 不起作用。
+    #[cfg(feature = "heap-tree")]
+    unsafe fn insert_free(
+        &mut self,
+        start: usize,
+        size: usize,
+        _after: *mut ListNode,
+    ) -> *mut ListNode {
+        self.debug_check_overlap(start, size);
+        set_tags(start, size, FREE);
+        let node = start as *mut ListNode;
+        (*node).magic = NODE_MAGIC ^ size;
+        tree::insert(&mut self.root, node);
+        node
+    }
+
+    /// 从树中摘下 `node`，不修改它的边界标记。
+    #[cfg(feature = "heap-tree")]
+    unsafe fn unlink(&mut self, node: *mut ListNode) {
+        tree::remove(&mut self.root, node);
+    }
+
+    /// 从列表中摘下 `node`，不修改它的边界标记。
+    #[cfg(not(feature = "heap-tree"))]
+    unsafe fn unlink(&mut self, node: *mut ListNode) {
+        let next = bins::remove(&mut self.bins, node);
+        if self.rover == node {
+            self.rover = next;
+        }
+    }
+
+    /// 只读地遍历所有空闲区域，顺序与 [`nodes`](Self::nodes) 相同。
+    fn regions(&self) -> impl Iterator<Item = &ListNode> {
+        // 遍历期间借用着分配器，节点不会被摘除或改写
+        self.nodes().map(|node| unsafe { &*node })
+    }
+
+    /// 从小到大逐个分箱、按列表顺序遍历空闲区域的节点。
+    #[cfg(not(feature = "heap-tree"))]
+    fn nodes(&self) -> Nodes<'_> {
+        self.nodes_in(0)
+    }
+
+    /// 按 (大小, 地址) 从小到大遍历树中的空闲区域的节点。
+    #[cfg(feature = "heap-tree")]
+    fn nodes(&self) -> Nodes<'_> {
+        Nodes {
+            node: unsafe { tree::first_at_least(self.root, (0, 0)) },
+            root: &self.root,
+        }
+    }
+
+    /// 遍历从 `first_bin` 开始的各个分箱中的节点。
+    #[cfg(not(feature = "heap-tree"))]
+    fn nodes_in(&self, first_bin: usize) -> Nodes<'_> {
+        Nodes {
+            node: ptr::null_mut(),
+            bins: &self.bins[first_bin..],
+        }
+    }
+
+    /// 从 `start` 开始遍历它所在的分箱的剩余部分以及之后的分箱，`start` 为空指针时什么也不产生。
+    #[cfg(not(feature = "heap-tree"))]
+    fn nodes_from(&self, start: *mut ListNode) -> Nodes<'_> {
+        // 非空的 start 总是列表中的节点
+        let bins = match unsafe { start.as_ref() } {
+            Some(region) => &self.bins[bin_of(region.size()) + 1..],
+            None => &[],
+        };
+        Nodes { node: start, bins }
+    }
+
+    /// 按当前策略查找能满足给定大小和对齐方式的空闲区域。
+    ///
+    /// 更小的分箱里的区域都放不下这么大的块，所以查找从请求所在的分箱开始，
+    /// 依次检查更大的分箱。返回区域的节点和分配起始地址，节点仍留在列表中。
+    #[cfg(not(feature = "heap-tree"))]
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(*mut ListNode, usize)> {
+        let first_bin = bin_of(size + HEADER_SIZE + FOOTER_SIZE);
+        let mut scanned = 0;
+        let found = {
+            let fits = |node: *mut ListNode| {
+                // 遍历产生的节点都在列表中，只在这里短暂地读取
+                let region = unsafe { &*node };
+                Self::alloc_from_region(region, size, align)
+                    .ok()
+                    .map(|alloc_start| (node, region.size(), alloc_start))
+            };
+            // 正好放得下：没有前部空隙，剩余部分也不够拆分
+            let min_split = self.min_split;
+            let exact = |(node, region_size, alloc_start): (*mut ListNode, usize, usize)| {
+                alloc_start - HEADER_SIZE == node as usize
+                    && region_size - (size + HEADER_SIZE + FOOTER_SIZE) < min_split
+            };
+            match self.policy {
+                Policy::FirstFit => {
+                    let mut found = None;
+                    for (i, node) in self.nodes_in(first_bin).enumerate() {
+                        if i >= EXACT_FIT_LOOKAHEAD && found.is_some() {
+                            break;
+                        }
+                        scanned += 1;
+                        if let Some(fit) = fits(node) {
+                            if exact(fit) {
+                                found = Some(fit);
+                                break;
+                            }
+                            found = found.or(Some(fit));
+                        }
+                    }
+                    found
+                }
+                // 更大的分箱里的区域都比这个分箱里的大，找到合适的分箱就不用再往后找
+                Policy::BestFit => (first_bin..BIN_COUNT).find_map(|bin| {
+                    Nodes {
+                        node: self.bins[bin],
+                        bins: &[],
+                    }
+                    .inspect(|_| scanned += 1)
+                    .filter_map(fits)
+                    .min_by_key(|&(_, region_size, _)| region_size)
+                }),
+                Policy::NextFit => {
+                    // 先检查游标及其之后的区域，再从第一个分箱绕回检查到游标为止；
+                    // 游标在更小的分箱里时直接从第一个分箱开始
+                    let rover = match unsafe { self.rover.as_ref() } {
+                        Some(rover) if bin_of(rover.size()) >= first_bin => self.rover,
+                        _ => self.nodes_in(first_bin).next().unwrap_or_default(),
+                    };
+                    self.nodes_from(rover)
+                        .chain(self.nodes_in(first_bin).take_while(|&node| node != rover))
+                        .inspect(|_| scanned += 1)
+                        .find_map(fits)
+                }
+            }
+        };
+        self.regions_scanned += scanned;
+        let (node, _, alloc_start) = found?;
+        Some((node, alloc_start))
+    }
+
+    /// 在树中查找能满足给定大小和对齐方式的最小区域。
+    ///
+    /// 从第一个放得下块的区域开始按大小递增检查，通常第一个就满足对齐；只有前部空隙
+    /// 放不下时才继续检查后继。返回区域的节点和分配起始地址，节点仍留在树中。
+    #[cfg(feature = "heap-tree")]
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(*mut ListNode, usize)> {
+        let needed = size + HEADER_SIZE + FOOTER_SIZE;
+        let mut scanned = 0;
+        let found = Nodes {
+            node: unsafe { tree::first_at_least(self.root, (needed, 0)) },
+            root: &self.root,
+        }
+        .inspect(|_| scanned += 1)
+        .find_map(|node| {
+            // 遍历产生的节点都在树中，只在这里短暂地读取
+            Self::alloc_from_region(unsafe { &*node }, size, align)
+                .ok()
+                .map(|alloc_start| (node, alloc_start))
+        });
+        self.regions_scanned += scanned;
+        found
+    }
+
+    /// 尝试把块 `start..end` 原地扩大到 `new_end`。
+    ///
+    /// 只有紧随其后的是足够大的空闲块时才会成功；剩下的部分小于 `min_split` 时一并并入。
+    unsafe fn grow_block(&mut self, start: usize, end: usize, new_end: usize) -> bool {
+        let range_end = self.range_of(start).map_or(0, |(_, range_end)| range_end);
+        if end >= range_end || read_tag(end) & FREE == 0 {
+            return false;
+        }
+        let next = end as *mut ListNode;
+        (*next).verify();
+        let next_end = (*next).end_addr();
+        if new_end > next_end {
+            return false;
+        }
+
+        let after = (*next).prev;
+        self.unlink(next);
+        let mut block_end = new_end;
+        if next_end - new_end < self.min_split {
+            block_end = next_end;
+        } else {
+            self.insert_free(new_end, next_end - new_end, after);
+        }
+        set_tags(start, block_end - start, 0);
+        self.add_usage(block_end - end);
+        self.pristine_start = self.pristine_start.max(block_end);
+        true
+    }
+
+    /// 把块 `start..end` 原地缩小到 `new_end`，把尾部归还到空闲列表。
+    ///
+    /// 尾部小于 `min_split` 时，只有紧随其后的是空闲块才能把尾部并入其中；
+    /// 否则这几个字节留在块里，随整个块一起释放。
+    unsafe fn shrink_block(&mut self, start: usize, end: usize, new_end: usize) {
+        let excess = end - new_end;
+        let range_end = self.range_of(start).map_or(0, |(_, range_end)| range_end);
+        if excess >= self.min_split {
+            set_tags(start, new_end - start, 0);
+            self.used_bytes -= excess;
+            self.add_free_region(new_end, excess);
+        } else if excess > 0 && end < range_end && read_tag(end) & FREE != 0 {
+            let next = end as *mut ListNode;
+            let (after, next_size) = ((*next).prev, (*next).size());
+            self.unlink(next);
+            set_tags(start, new_end - start, 0);
+            self.used_bytes -= excess;
+            self.insert_free(new_end, excess + next_size, after);
+            self.poison(
+                new_end + mem::size_of::<ListNode>(),
+                end + mem::size_of::<ListNode>(),
+            );
+        }
+    }
+
+    /// 分配 `size` 字节（已按 `size_align` 调整），在前后写入边界标记。
+    ///
+    /// 对齐留下的前部空隙和剩余部分接替原来的区域在列表中的位置；剩余部分小于
+    /// `min_split` 时直接并入这次分配。返回分配的起始地址，并推进 `pristine_start`。
+    unsafe fn allocate_block(&mut self, size: usize, align: usize) -> Option<usize> {
+        #[cfg(feature = "heap-verify")]
+        {
+            self.verify_countdown -= 1;
+            if self.verify_countdown == 0 {
+                self.verify_countdown = VERIFY_INTERVAL;
+                if let Err(corruption) = self.check_consistency() {
+                    panic!("{}", corruption);
+                }
+            }
+        }
+        let (region, alloc_start) = self.find_region(size, align)?;
+        let (region_start, region_end) = ((*region).start_addr(), (*region).end_addr());
+        #[cfg(not(feature = "heap-tree"))]
+        let region_bin = bin_of((*region).size());
+        let prev = (*region).prev;
+        self.unlink(region);
+
+        let block_start = alloc_start - HEADER_SIZE;
+        let mut block_end = alloc_start + size + FOOTER_SIZE;
+        if region_end - block_end < self.min_split {
+            block_end = region_end;
+        }
+        self.verify_poison(
+            block_start.max(region_start + mem::size_of::<ListNode>()),
+            block_end.min(region_end - TAG_SIZE),
+        );
+        set_tags(block_start, block_end - block_start, 0);
+
+        let mut after = prev;
+        if block_start > region_start {
+            after = self.insert_free(region_start, block_start - region_start, after);
+        }
+        if block_end < region_end {
+            self.insert_free(block_end, region_end - block_end, after);
+        }
+        // next-fit 从原来的区域所在的位置继续
+        #[cfg(not(feature = "heap-tree"))]
+        {
+            self.rover = if prev.is_null() {
+                self.bins[region_bin]
+            } else {
+                (*prev).next
+            };
+        }
+
+        self.allocations += 1;
+        self.add_usage(block_end - block_start);
+        self.pristine_start = self.pristine_start.max(block_end);
+        Some(alloc_start)
+    }
+
+    /// 根据边界标记找到 `ptr` 这个分配所在的块，返回块的起始地址和大小。
+    fn block_of(&self, ptr: usize) -> Result<(usize, usize), InvalidPointer> {
+        let start = ptr.wrapping_sub(HEADER_SIZE);
+        if ptr < HEADER_SIZE
+            || !is_aligned(start, mem::align_of::<ListNode>())
+            || !self.in_heap(start, MIN_BLOCK_SIZE)
+        {
+            return Err(InvalidPointer::OutsideHeap);
         }
 
-        // 未找到合适的区域
-        None
+        let tag = unsafe { read_tag(start) };
+        // 并入相邻空闲块的块，头部标记会被填充覆盖
+        #[cfg(feature = "heap-poison")]
+        if tag == usize::from_ne_bytes([POISON; mem::size_of::<usize>()]) {
+            return Err(InvalidPointer::Freed);
+        }
+        if tag & FREE != 0 {
+            return Err(InvalidPointer::Freed);
+        }
+        let size = tag;
+        if size < MIN_BLOCK_SIZE
+            || !size.is_multiple_of(mem::align_of::<ListNode>())
+            || !self.in_heap(start, size)
+            || unsafe { read_tag(start + size - TAG_SIZE) } != tag
+        {
+            return Err(InvalidPointer::BadHeader);
+        }
+        Ok((start, size))
     }
+
     /// Try to use the given region for an allocation with given size and
     /// alignment.
     ///
-    /// Returns the allocation start address on success.
+    /// Returns the allocation start address on success. Any padding in front
+    /// of the block is large enough to be returned to the free list.
     fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        let alloc_start = align_up(region.start_addr(), align);
-        let alloc_end = alloc_start.checked_add(size).ok_or(())?;
-
-        if alloc_end > region.end_addr() {
-            // region too small
-            return Err(());
+        // 起始地址之前要留出头部标记；对齐可能很大（例如 2 MiB），向上对齐要检查溢出
+        let aligned_after = |addr: usize| checked_align_up(addr, align).ok_or(());
+        let mut alloc_start = aligned_after(region.start_addr() + HEADER_SIZE)?;
+        let gap = alloc_start - HEADER_SIZE - region.start_addr();
+        if gap > 0 && gap < MIN_BLOCK_SIZE {
+            // 前部空隙放不下空闲块 -> 把起点后移到能放下为止
+            alloc_start = aligned_after(region.start_addr() + MIN_BLOCK_SIZE + HEADER_SIZE)?;
         }
+        let block_end = alloc_start
+            .checked_add(size)
+            .and_then(|end| end.checked_add(FOOTER_SIZE))
+            .ok_or(())?;
 
-        let excess_size = region.end_addr() - alloc_end;
-        if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
-            // rest of region too small to hold a ListNode (required because the
-            // allocation splits the region in a used and a free part)
+        if block_end > region.end_addr() {
+            // region too small
             return Err(());
         }
 
         // region suitable for allocation
         Ok(alloc_start)
     }
-    /// 调整给定的布局，以便生成的分配内存区域也能够存储 `ListNode`。
+    /// 调整给定的布局，使分配结尾的脚标满足 `ListNode` 的对齐，并且整个块不小于最小块。
+    ///
+    /// 大小只向上取整到 `ListNode` 的对齐，而不是请求的对齐：大对齐只影响起始地址，
+    /// 把大小也填充到对齐会让每个页对齐的小分配白白占掉整页。
+    ///
+    /// 返回调整后的大小和对齐方式作为 (size, align) 元组。调整后的布局超过 `isize::MAX`
+    /// 时返回 `None`，调用者把它当作内存不足处理。
+    fn size_align(layout: Layout) -> Option<(usize, usize)> {
+        let layout = layout.align_to(mem::align_of::<ListNode>()).ok()?;
+        let size = align_up(layout.size(), mem::align_of::<ListNode>());
+        Some((
+            size.max(MIN_BLOCK_SIZE - HEADER_SIZE - FOOTER_SIZE),
+            layout.align(),
+        ))
+    }
+
+    /// 返回 `ptr` 这个分配实际可用的字节数，包括取整和并入分配的剩余部分。
     ///
-    /// 返回调整后的大小和对齐方式作为 (size, align) 元组。
-    fn size_align(layout: Layout) -> (usize, usize) {
-        let layout = layout
-            .align_to(mem::align_of::<ListNode>())
-            .expect("adjusting alignment failed")
-            .pad_to_align();
-        let size = layout.size().max(mem::size_of::<ListNode>());
-        (size, layout.align())
+    /// 启用 `heap-redzone` 时结尾之后就是红区，只能使用请求的大小。
+    unsafe fn usable_size(ptr: *mut u8, layout: Layout) -> usize {
+        if cfg!(feature = "heap-redzone") || layout.size() == 0 {
+            return layout.size();
+        }
+        let start = ptr as usize - HEADER_SIZE;
+        start + read_tag(start) - FOOTER_SIZE - ptr as usize
     }
 }
-unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // 执行布局调整
-        let (size, align) = LinkedListAllocator::size_align(layout);
-        let mut allocator = self.lock();
+impl Locked<LinkedListAllocator> {
+    /// 见 [`LinkedListAllocator::extend`]。
+    pub unsafe fn extend(&self, start: usize, size: usize) -> Result<(), ExtendError> {
+        self.lock().extend(start, size)
+    }
+
+    /// 见 [`LinkedListAllocator::release_tail`]。`unmap` 在持有锁时调用，不能分配内存。
+    pub fn release_tail(&self, unmap: impl FnMut(usize, usize)) -> usize {
+        self.lock().release_tail(unmap)
+    }
+
+    /// 见 [`LinkedListAllocator::free_bytes`]。
+    pub fn free_bytes(&self) -> usize {
+        self.lock().free_bytes()
+    }
+
+    /// 见 [`LinkedListAllocator::region_count`]。
+    pub fn region_count(&self) -> usize {
+        self.lock().region_count()
+    }
+
+    /// 见 [`LinkedListAllocator::largest_free_region`]。
+    pub fn largest_free_region(&self) -> usize {
+        self.lock().largest_free_region()
+    }
+
+    /// 见 [`LinkedListAllocator::check_consistency`]。
+    pub fn check_consistency(&self) -> Result<HeapCheck, HeapCorruption> {
+        self.lock().check_consistency()
+    }
+
+    /// 见 [`LinkedListAllocator::check_accounting`]。
+    pub fn check_accounting(&self) -> Result<(), AccountingMismatch> {
+        self.lock().check_accounting()
+    }
+
+    /// 返回 `ptr` 这个分配实际可用的字节数，包括取整和并入分配的剩余部分。
+    ///
+    /// 调用者必须保证 `ptr` 和 `layout` 来自这个分配器上一个尚未释放的分配。
+    /// 启用 `heap-redzone` 时只返回请求的大小，因为结尾之后就是红区。
+    pub unsafe fn usable_size(&self, ptr: *mut u8, layout: Layout) -> usize {
+        LinkedListAllocator::usable_size(ptr, layout)
+    }
+
+    /// 见 [`LinkedListAllocator::grow_in_place`]。
+    #[allow(clippy::result_unit_err)]
+    pub unsafe fn grow_in_place(
+        &self,
+        ptr: *mut u8,
+        old_layout: Layout,
+        new_size: usize,
+    ) -> Result<(), ()> {
+        self.lock().grow_in_place(ptr, old_layout, new_size)
+    }
+
+    /// 见 [`LinkedListAllocator::shrink_in_place`]。
+    #[allow(clippy::result_unit_err)]
+    pub unsafe fn shrink_in_place(
+        &self,
+        ptr: *mut u8,
+        old_layout: Layout,
+        new_size: usize,
+    ) -> Result<(), ()> {
+        self.lock().shrink_in_place(ptr, old_layout, new_size)
+    }
+
+    /// 见 [`LinkedListAllocator::live_allocations`]。`f` 在持有锁时调用，不能使用这个分配器。
+    #[cfg(feature = "heap-registry")]
+    pub fn live_allocations(&self, f: impl FnMut(usize, Layout)) {
+        self.lock().live_allocations(f)
+    }
 
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
-            let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
-            if excess_size > 0 {
-                allocator.add_free_region(alloc_end, excess_size);
+    /// 见 [`LinkedListAllocator::live_allocation_count`]。
+    #[cfg(feature = "heap-registry")]
+    pub fn live_allocation_count(&self) -> usize {
+        self.lock().live_allocation_count()
+    }
+
+    /// 见 [`LinkedListAllocator::latency_stats`]。
+    #[cfg(feature = "heap-latency")]
+    pub fn latency_stats(&self) -> LatencyReport {
+        self.lock().latency_stats()
+    }
+
+    /// 见 [`LinkedListAllocator::reset_latency_stats`]。
+    #[cfg(feature = "heap-latency")]
+    pub fn reset_latency_stats(&self) {
+        self.lock().reset_latency_stats()
+    }
+
+    /// 见 [`LinkedListAllocator::current_usage`]。
+    pub fn current_usage(&self) -> usize {
+        self.lock().current_usage()
+    }
+
+    /// 见 [`LinkedListAllocator::total_allocs`]。
+    pub fn total_allocs(&self) -> usize {
+        self.lock().total_allocs()
+    }
+
+    /// 见 [`LinkedListAllocator::total_deallocs`]。
+    pub fn total_deallocs(&self) -> usize {
+        self.lock().total_deallocs()
+    }
+
+    /// 见 [`LinkedListAllocator::total_reallocs`]。
+    pub fn total_reallocs(&self) -> usize {
+        self.lock().total_reallocs()
+    }
+
+    /// 见 [`LinkedListAllocator::live_allocs`]。
+    pub fn live_allocs(&self) -> usize {
+        self.lock().live_allocs()
+    }
+
+    /// 见 [`LinkedListAllocator::peak_usage`]。
+    pub fn peak_usage(&self) -> usize {
+        self.lock().peak_usage()
+    }
+
+    /// 见 [`LinkedListAllocator::fragmentation`]。
+    pub fn fragmentation(&self) -> usize {
+        self.lock().fragmentation()
+    }
+
+    /// 见 [`LinkedListAllocator::defragment`]。
+    pub fn defragment(&self) -> usize {
+        self.lock().defragment()
+    }
+
+    /// 见 [`LinkedListAllocator::stats`]。
+    pub fn stats(&self) -> HeapStats {
+        self.lock().stats()
+    }
+
+    /// 通过串口打印空闲列表，见 [`LinkedListAllocator::dump`]。
+    ///
+    /// 如果锁正被持有（例如在分配器内部 panic），只打印一条提示而不是死锁。
+    pub fn dump_to_serial(&self) {
+        use core::fmt::Write;
+
+        let mut serial = crate::serial::SERIAL1.lock();
+        let _ = match self.inner.try_lock() {
+            Some(allocator) => allocator.dump(&mut *serial),
+            None => writeln!(serial, "heap free list: allocator is locked"),
+        };
+    }
+}
+
+impl Locked<LinkedListAllocator> {
+    /// 启用 `heap-latency` 时测量 `f` 花费的周期数，记入 `operation` 的统计。
+    ///
+    /// 读取计数器和记录结果都要短暂地获取锁，这两次获取不计入测量的时间。
+    #[inline]
+    fn timed<T>(&self, operation: Operation, f: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "heap-latency")]
+        {
+            let counter = self.lock().cycle_counter;
+            let start = counter();
+            let result = f();
+            let cycles = counter().wrapping_sub(start);
+            let mut allocator = self.lock();
+            let stats = match operation {
+                Operation::Alloc => &mut allocator.latency.alloc,
+                Operation::Dealloc => &mut allocator.latency.dealloc,
+                Operation::Realloc => &mut allocator.latency.realloc,
+            };
+            stats.record(cycles);
+            result
+        }
+        #[cfg(not(feature = "heap-latency"))]
+        {
+            let _ = operation;
+            f()
+        }
+    }
+
+    /// `GlobalAlloc` 各个方法的实现，不测量耗时；内部互相调用时不会重复计入统计。
+    unsafe fn untimed_alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return dangling(&layout);
+        }
+        let allocated = self.lock().alloc_tagged(layout);
+        allocated.map_or(ptr::null_mut(), |alloc_start| alloc_start as *mut u8)
+    }
+
+    unsafe fn untimed_alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return dangling(&layout);
+        }
+        // 在锁外清零
+        let allocated = self.lock().alloc_zeroed_tagged(layout);
+        match allocated {
+            Some((alloc_start, dirty_len)) => {
+                let ptr = alloc_start as *mut u8;
+                ptr.write_bytes(0, dirty_len);
+                ptr
             }
-            alloc_start as *mut u8
-        } else {
-            ptr::null_mut()
+            None => ptr::null_mut(),
         }
     }
 
+    unsafe fn untimed_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // 零大小的分配没有对应的块
+        if layout.size() == 0 {
+            return;
+        }
+        self.lock().dealloc_tagged(ptr, layout);
+    }
+
+    unsafe fn untimed_realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if layout.size() == 0 {
+            return self.untimed_alloc(new_layout);
+        }
+
+        match self.lock().realloc_in_place(ptr, layout, new_size) {
+            Ok(true) => return ptr,
+            Ok(false) => {}
+            Err(()) => return ptr::null_mut(),
+        }
+
+        // 无法原地调整 -> 分配新的内存，复制数据后释放旧的分配
+        let new_ptr = self.untimed_alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.untimed_dealloc(ptr, layout);
+            self.lock().count_moved_realloc();
+        }
+        new_ptr
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.timed(Operation::Alloc, || self.untimed_alloc(layout))
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.timed(Operation::Alloc, || self.untimed_alloc_zeroed(layout))
+    }
+
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        // 执行布局调整
-        let (size, _) = LinkedListAllocator::size_align(layout);
+        self.timed(Operation::Dealloc, || self.untimed_dealloc(ptr, layout))
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.timed(Operation::Realloc, || {
+            self.untimed_realloc(ptr, layout, new_size)
+        })
+    }
+}
+
+/// 让 `Vec::new_in` 等集合可以使用独立的堆，返回的切片长度是实际可用的大小。
+unsafe impl Allocator for Locked<LinkedListAllocator> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = NonNull::new(unsafe { self.alloc(layout) }).ok_or(AllocError)?;
+        let len = unsafe { LinkedListAllocator::usable_size(ptr.as_ptr(), layout) };
+        Ok(NonNull::slice_from_raw_parts(ptr, len))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = NonNull::new(unsafe { self.alloc_zeroed(layout) }).ok_or(AllocError)?;
+        let len = unsafe { LinkedListAllocator::usable_size(ptr.as_ptr(), layout) };
+        // `alloc_zeroed` 只清零了请求的部分
+        unsafe {
+            ptr.as_ptr()
+                .add(layout.size())
+                .write_bytes(0, len - layout.size())
+        };
+        Ok(NonNull::slice_from_raw_parts(ptr, len))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.dealloc(ptr.as_ptr(), layout);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
 
-        self.lock().add_free_region(ptr as usize, size)
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.resize(ptr, old_layout, new_layout)?;
+        let start = block.as_ptr() as *mut u8;
+        start
+            .add(old_layout.size())
+            .write_bytes(0, block.len() - old_layout.size());
+        Ok(block)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+}
+
+impl Locked<LinkedListAllocator> {
+    /// `Allocator::grow` 和 `Allocator::shrink` 的共同实现。
+    ///
+    /// 对齐不变或变小时交给 `realloc` 原地调整，否则只能分配新的内存再复制过去。
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if new_layout.size() == 0 {
+            self.dealloc(ptr.as_ptr(), old_layout);
+            return Ok(NonNull::slice_from_raw_parts(
+                NonNull::new_unchecked(dangling(&new_layout)),
+                0,
+            ));
+        }
+        if new_layout.align() > old_layout.align() {
+            let new_ptr = self.allocate(new_layout)?;
+            let copied = old_layout.size().min(new_layout.size());
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, copied);
+            self.dealloc(ptr.as_ptr(), old_layout);
+            self.lock().count_moved_realloc();
+            return Ok(new_ptr);
+        }
+        let new_ptr = NonNull::new(self.realloc(ptr.as_ptr(), old_layout, new_layout.size()))
+            .ok_or(AllocError)?;
+        let len = LinkedListAllocator::usable_size(new_ptr.as_ptr(), new_layout);
+        Ok(NonNull::slice_from_raw_parts(new_ptr, len))
     }
 }
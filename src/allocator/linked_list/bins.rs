@@ -0,0 +1,94 @@
+//! 不启用 `heap-tree` feature 时的空闲区域索引：按大小分成几个分箱，每个分箱是一条双向链表。
+//!
+//! 这里只修改节点的 `next` 和 `prev`，节点的头部标记由调用者写好，分箱由标记中的大小决定。
+
+use super::ListNode;
+use core::ptr;
+
+/// 分箱的上限：小于 `BIN_LIMITS[i]` 字节的空闲区域放在第 `i` 个分箱，其余的放在最后一个。
+const BIN_LIMITS: [usize; 3] = [64, 256, 1024];
+/// 分箱的个数。
+pub(super) const BIN_COUNT: usize = BIN_LIMITS.len() + 1;
+
+/// 返回大小为 `size` 的空闲区域所在的分箱。
+pub(super) fn bin_of(size: usize) -> usize {
+    BIN_LIMITS
+        .iter()
+        .position(|&limit| size < limit)
+        .unwrap_or(BIN_LIMITS.len())
+}
+
+/// 依次遍历若干个分箱中的空闲区域的节点。
+///
+/// 产生的是原始指针而不是引用：找到的节点随后要被摘除或改写，从共享引用转换回来的
+/// 指针不能用来写入。只读的遍历使用 `LinkedListAllocator::regions`。
+pub(super) struct Nodes<'a> {
+    pub(super) node: *mut ListNode,
+    pub(super) bins: &'a [*mut ListNode],
+}
+impl<'a> Iterator for Nodes<'a> {
+    type Item = *mut ListNode;
+
+    fn next(&mut self) -> Option<*mut ListNode> {
+        while self.node.is_null() {
+            let (&head, rest) = self.bins.split_first()?;
+            self.node = head;
+            self.bins = rest;
+        }
+        let node = self.node;
+        // 列表中的指针要么为空，要么指向堆内的空闲块
+        unsafe {
+            (*node).verify();
+            self.node = (*node).next;
+        }
+        Some(node)
+    }
+}
+
+/// 把 `node` 插入到它的分箱中 `after` 之后，`after` 为空指针或者属于别的分箱时插入到表头。
+///
+/// 调用者保证 `node` 不在任何分箱中，`after` 为空指针或者是分箱中的节点。
+pub(super) unsafe fn insert(
+    bins: &mut [*mut ListNode; BIN_COUNT],
+    node: *mut ListNode,
+    after: *mut ListNode,
+) {
+    let bin = bin_of((*node).size());
+    let after = if !after.is_null() && bin_of((*after).size()) == bin {
+        after
+    } else {
+        ptr::null_mut()
+    };
+    let next = if after.is_null() {
+        bins[bin]
+    } else {
+        (*after).next
+    };
+    (*node).prev = after;
+    (*node).next = next;
+    if !next.is_null() {
+        (*next).prev = node;
+    }
+    if after.is_null() {
+        bins[bin] = node;
+    } else {
+        (*after).next = node;
+    }
+}
+
+/// 从分箱中摘下 `node`，返回它原来的后继。节点的头部标记不能在插入之后修改过。
+pub(super) unsafe fn remove(
+    bins: &mut [*mut ListNode; BIN_COUNT],
+    node: *mut ListNode,
+) -> *mut ListNode {
+    let (prev, next) = ((*node).prev, (*node).next);
+    if prev.is_null() {
+        bins[bin_of((*node).size())] = next;
+    } else {
+        (*prev).next = next;
+    }
+    if !next.is_null() {
+        (*next).prev = prev;
+    }
+    next
+}
@@ -0,0 +1,54 @@
+//! 空闲块开头的节点。
+//!
+//! 这里以及 `bins`、`tree` 只通过原始指针操作节点，不依赖内核的其他部分，`host-tests`
+//! 在宿主机上用 Miri 运行它们的测试。
+
+/// 边界标记中表示块空闲的位。块大小总是 `ListNode` 对齐的倍数，最低位可以用作标志。
+pub(super) const FREE: usize = 1;
+
+/// 完好的节点中 `magic` 与块大小异或的结果。
+pub(super) const NODE_MAGIC: usize = 0xF00D_F00D;
+
+/// 空闲块开头的节点，`tag` 就是块的头部标记。
+///
+/// 每个分箱是一条双向链表，按释放顺序排列（新释放的块在表头），摘除任意节点都是 O(1)。
+/// 启用 `heap-tree` feature 时不再分箱，所有节点组成一棵树，`next` 和 `prev` 是左右子节点。
+/// `magic` 总是 `NODE_MAGIC ^ size`，释放后使用改写了节点时能在读取它的地方发现。
+pub(super) struct ListNode {
+    pub(super) tag: usize,
+    pub(super) next: *mut ListNode,
+    pub(super) prev: *mut ListNode,
+    pub(super) magic: usize,
+}
+impl ListNode {
+    pub(super) fn expected_magic(&self) -> usize {
+        NODE_MAGIC ^ self.size()
+    }
+
+    /// 启用 `heap-verify` feature 时检查 `magic`，不符时以节点地址 panic，
+    /// 而不是继续沿着被改写的指针走下去。
+    #[inline]
+    pub(super) fn verify(&self) {
+        #[cfg(feature = "heap-verify")]
+        if self.magic != self.expected_magic() {
+            panic!(
+                "heap corrupted: free region {:#x} has magic {:#x}, expected {:#x}",
+                self.start_addr(),
+                self.magic,
+                self.expected_magic()
+            );
+        }
+    }
+
+    pub(super) fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    pub(super) fn size(&self) -> usize {
+        self.tag & !FREE
+    }
+
+    pub(super) fn end_addr(&self) -> usize {
+        self.start_addr() + self.size()
+    }
+}
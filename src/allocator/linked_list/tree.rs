@@ -0,0 +1,134 @@
+//! 启用 `heap-tree` feature 时代替分箱链表的空闲区域索引。
+//!
+//! 所有空闲区域组成一棵按 (大小, 地址) 排序的 treap，节点就是区域开头的 `ListNode`，
+//! `next` 和 `prev` 分别用作左、右子节点。节点的优先级由地址散列得到，不需要额外的字段，
+//! 所以最小块的大小不变。查找、插入和摘除的期望复杂度都是 O(log n)。
+//!
+//! 节点的键取自它的头部标记，所以插入之前要先写好标记，摘除之前不能修改标记。
+
+use super::ListNode;
+use core::ptr;
+
+/// 节点的排序键：先按大小，大小相同时按地址。
+pub(super) type Key = (usize, usize);
+
+/// 节点的键；启用 `heap-verify` 时顺便检查节点的 `magic`，每经过一个节点都要取键。
+fn key(node: &ListNode) -> Key {
+    node.verify();
+    (node.size(), node.start_addr())
+}
+
+/// 由地址散列出的优先级，父节点的优先级总是不低于子节点。
+///
+/// 空闲区域的地址常常是等差的，所以用 splitmix64 的混合函数打散，而不是只乘一个常数。
+fn priority(node: *mut ListNode) -> u64 {
+    let mut x = node as u64;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// 把以 `root` 为根的子树拆成键小于 `split_key` 和大于等于 `split_key` 的两棵。
+unsafe fn split(mut root: *mut ListNode, split_key: Key) -> (*mut ListNode, *mut ListNode) {
+    let (mut left, mut right) = (ptr::null_mut(), ptr::null_mut());
+    let mut left_link: *mut *mut ListNode = &mut left;
+    let mut right_link: *mut *mut ListNode = &mut right;
+    while !root.is_null() {
+        if key(&*root) < split_key {
+            *left_link = root;
+            left_link = &mut (*root).prev;
+            root = (*root).prev;
+        } else {
+            *right_link = root;
+            right_link = &mut (*root).next;
+            root = (*root).next;
+        }
+    }
+    *left_link = ptr::null_mut();
+    *right_link = ptr::null_mut();
+    (left, right)
+}
+
+/// 合并两棵子树，`left` 中的键都小于 `right` 中的键。
+unsafe fn merge(mut left: *mut ListNode, mut right: *mut ListNode) -> *mut ListNode {
+    let mut root = ptr::null_mut();
+    let mut link: *mut *mut ListNode = &mut root;
+    loop {
+        if left.is_null() {
+            *link = right;
+            break;
+        }
+        if right.is_null() {
+            *link = left;
+            break;
+        }
+        if priority(left) > priority(right) {
+            *link = left;
+            link = &mut (*left).prev;
+            left = (*left).prev;
+        } else {
+            *link = right;
+            link = &mut (*right).next;
+            right = (*right).next;
+        }
+    }
+    root
+}
+
+/// 把 `node` 插入以 `*root` 为根的树，`node` 的标记必须已经写好。
+pub(super) unsafe fn insert(root: &mut *mut ListNode, node: *mut ListNode) {
+    let node_key = key(&*node);
+    let mut link: *mut *mut ListNode = root;
+    // 优先级更高的节点留在上面，在第一个优先级更低的位置把子树拆开挂到 node 下
+    while !(*link).is_null() && priority(*link) >= priority(node) {
+        let parent = *link;
+        link = if node_key < key(&*parent) {
+            &mut (*parent).next
+        } else {
+            &mut (*parent).prev
+        };
+    }
+    let (left, right) = split(*link, node_key);
+    (*node).next = left;
+    (*node).prev = right;
+    *link = node;
+}
+
+/// 从以 `*root` 为根的树中摘下 `node`，不修改它的标记。
+pub(super) unsafe fn remove(root: &mut *mut ListNode, node: *mut ListNode) {
+    let node_key = key(&*node);
+    let mut link: *mut *mut ListNode = root;
+    while *link != node {
+        let parent = *link;
+        debug_assert!(
+            !parent.is_null(),
+            "free region {:p} is not in the tree",
+            node
+        );
+        link = if node_key < key(&*parent) {
+            &mut (*parent).next
+        } else {
+            &mut (*parent).prev
+        };
+    }
+    *link = merge((*node).next, (*node).prev);
+}
+
+/// 返回键不小于 `lower` 的第一个节点，没有时返回空指针。
+pub(super) unsafe fn first_at_least(mut node: *mut ListNode, lower: Key) -> *mut ListNode {
+    let mut found = ptr::null_mut();
+    while !node.is_null() {
+        if key(&*node) < lower {
+            node = (*node).prev;
+        } else {
+            found = node;
+            node = (*node).next;
+        }
+    }
+    found
+}
+
+/// 返回树中紧跟在 `node` 之后的节点。
+pub(super) unsafe fn successor(root: *mut ListNode, node: &ListNode) -> *mut ListNode {
+    first_at_least(root, (node.size(), node.start_addr() + 1))
+}
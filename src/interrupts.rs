@@ -1,3 +1,4 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use pic8259::ChainedPics;
 use spin::Mutex;
@@ -15,6 +16,14 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
+/// 每次时钟中断时调用的函数的地址，0 表示没有。
+static TIMER_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// 设置每次时钟中断时调用的函数，例如测试在中断处理程序中分配内存。
+pub fn set_timer_hook(hook: fn()) {
+    TIMER_HOOK.store(hook as usize, Ordering::Release);
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
@@ -64,6 +73,12 @@ extern "x86-interrupt" fn double_fault_handler(
 }
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
     print!(".");
+    let hook = TIMER_HOOK.load(Ordering::Acquire);
+    if hook != 0 {
+        // TIMER_HOOK 中只会存入 fn() 的地址
+        let hook: fn() = unsafe { core::mem::transmute(hook) };
+        hook();
+    }
 
     //发送EOI（中断结束）信号
     unsafe {
@@ -0,0 +1,199 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::allocator::{align_down, align_up, checked_align_up, is_aligned};
+use core::panic::PanicInfo;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+/// 包括 0、对齐边界两侧和 `usize::MAX` 附近的地址。
+const ADDRS: [usize; 20] = [
+    0,
+    1,
+    2,
+    3,
+    7,
+    8,
+    9,
+    15,
+    16,
+    4095,
+    4096,
+    4097,
+    0x20_0000 - 1,
+    0x20_0000,
+    usize::MAX / 2,
+    usize::MAX / 2 + 1,
+    usize::MAX - 4096,
+    usize::MAX - 4095,
+    usize::MAX - 1,
+    usize::MAX,
+];
+
+/// 从 1 到 2^63 的所有2的幂。
+fn aligns() -> impl Iterator<Item = usize> {
+    (0..usize::BITS).map(|shift| 1 << shift)
+}
+
+// 三个函数都可以在常量中使用
+const _: () = assert!(align_up(5, 4) == 8);
+const _: () = assert!(align_down(5, 4) == 4);
+const _: () = assert!(is_aligned(8, 4) && !is_aligned(6, 4));
+
+#[test_case]
+fn align_one_is_identity() {
+    for &addr in ADDRS.iter() {
+        assert_eq!(align_down(addr, 1), addr);
+        assert_eq!(align_up(addr, 1), addr);
+        assert!(is_aligned(addr, 1));
+    }
+}
+
+#[test_case]
+fn zero_is_always_aligned() {
+    for align in aligns() {
+        assert!(is_aligned(0, align));
+        assert_eq!(align_down(0, align), 0);
+        assert_eq!(align_up(0, align), 0);
+    }
+}
+
+#[test_case]
+fn is_aligned_matches_remainder() {
+    for &addr in ADDRS.iter() {
+        for align in aligns() {
+            assert_eq!(
+                is_aligned(addr, align),
+                addr % align == 0,
+                "{:#x} {:#x}",
+                addr,
+                align
+            );
+        }
+    }
+}
+
+#[test_case]
+fn align_down_matrix() {
+    for &addr in ADDRS.iter() {
+        for align in aligns() {
+            let down = align_down(addr, align);
+            assert_eq!(down, addr - addr % align, "{:#x} {:#x}", addr, align);
+            assert!(is_aligned(down, align));
+            assert!(down <= addr && addr - down < align);
+        }
+    }
+}
+
+#[test_case]
+fn align_up_matrix() {
+    for &addr in ADDRS.iter() {
+        for align in aligns() {
+            // 结果超出 usize 的组合不在 align_up 的定义域内
+            let expected = match addr % align {
+                0 => addr,
+                rem => match (addr - rem).checked_add(align) {
+                    Some(up) => up,
+                    None => continue,
+                },
+            };
+            let up = align_up(addr, align);
+            assert_eq!(up, expected, "{:#x} {:#x}", addr, align);
+            assert!(is_aligned(up, align));
+            assert!(up >= addr && up - addr < align);
+            assert_eq!(align_down(up, align), up);
+        }
+    }
+}
+
+#[test_case]
+fn aligned_addresses_are_fixed_points() {
+    for &addr in ADDRS.iter() {
+        for align in aligns().filter(|&align| is_aligned(addr, align)) {
+            assert_eq!(align_down(addr, align), addr);
+            assert_eq!(align_up(addr, align), addr);
+        }
+    }
+}
+
+/// 测试用的 xorshift 伪随机数，每次运行得到同样的序列。
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+
+    /// 随机的地址：一半均匀分布，一半靠近 0 或 `usize::MAX`。
+    fn addr(&mut self) -> usize {
+        let value = self.next();
+        match value % 4 {
+            0 => value >> 48,
+            1 => usize::MAX - (value >> 48),
+            _ => self.next(),
+        }
+    }
+
+    fn align(&mut self) -> usize {
+        1 << (self.next() % usize::BITS as usize)
+    }
+}
+
+/// 原来用除法和乘法的写法，溢出时返回 `None`。
+fn align_up_by_division(addr: usize, align: usize) -> Option<usize> {
+    Some(addr.checked_add(align - 1)? / align * align)
+}
+
+#[test_case]
+fn align_up_agrees_with_division() {
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..100_000 {
+        let (addr, align) = (rng.addr(), rng.align());
+        if let Some(expected) = align_up_by_division(addr, align) {
+            assert_eq!(align_up(addr, align), expected, "{:#x} {:#x}", addr, align);
+        }
+    }
+}
+
+#[test_case]
+fn checked_align_up_agrees_with_division() {
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    for _ in 0..100_000 {
+        let (addr, align) = (rng.addr(), rng.align());
+        assert_eq!(
+            checked_align_up(addr, align),
+            align_up_by_division(addr, align),
+            "{:#x} {:#x}",
+            addr,
+            align
+        );
+    }
+}
+
+#[test_case]
+fn checked_align_up_only_fails_past_the_top() {
+    for align in aligns() {
+        let top = align_down(usize::MAX, align);
+        assert_eq!(checked_align_up(top, align), Some(top));
+        assert_eq!(checked_align_up(top - (align - 1), align), Some(top));
+        if align > 1 {
+            assert_eq!(checked_align_up(top + 1, align), None);
+            assert_eq!(checked_align_up(usize::MAX, align), None);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+// 在 tests/block_cache_bench.rs 中
+//
+// 在 FixedSizeBlockAllocator 和 LinkedListAllocator 上运行同一串大小混杂的分配和释放，
+// 打印两者消耗的 TSC 周期数，以及块缓存在各个类中的命中、未命中和绕过次数。
+// 同一串操作也在每个类各有一把锁的 ClassLockedBlockAllocator 上运行，打印它锁上共享分配器的次数：
+// 只有这些操作会和其他类的分配竞争。
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::{
+    allocator::{
+        fixed_size_block::{BlockStats, ClassLockedBlockAllocator, FixedSizeBlockAllocator},
+        linked_list::LinkedListAllocator,
+        Locked,
+    },
+    serial_println,
+};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    arch::x86_64::_rdtsc,
+    panic::PanicInfo,
+    ptr,
+};
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    test_main();
+    loop {}
+}
+
+/// 测试用的独立堆，两个分配器先后在上面新建。
+const ARENA_SIZE: usize = 4 * 1024 * 1024;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+fn arena_start() -> usize {
+    unsafe { ptr::addr_of_mut!(ARENA.0) as usize }
+}
+
+/// 同时存活的分配的槽位数，以及分配和释放的总次数。
+const SLOTS: usize = 512;
+const OPERATIONS: usize = 50_000;
+
+/// 简单的 xorshift 伪随机数生成器，测试不依赖外部 crate。
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+/// 内核里常见的分配：大多是几十字节的小对象，一些页大小的缓冲区，偶尔一个大块。
+fn next_layout(rng: &mut XorShift) -> Layout {
+    let size = match rng.next() % 32 {
+        0 => 16 * 1024 + rng.next() % (48 * 1024),
+        1..=4 => 4096,
+        5..=10 => 256 + rng.next() % 1024,
+        _ => 8 + rng.next() % 120,
+    };
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+/// 在 `allocator` 上运行固定的一串随机分配和释放，返回消耗的 TSC 周期数。
+fn run_workload(allocator: &impl GlobalAlloc) -> u64 {
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut live: [Option<(*mut u8, Layout)>; SLOTS] = [None; SLOTS];
+    let start = unsafe { _rdtsc() };
+    for _ in 0..OPERATIONS {
+        let slot = &mut live[rng.next() % SLOTS];
+        match slot.take() {
+            Some((ptr, layout)) => unsafe { allocator.dealloc(ptr, layout) },
+            None => {
+                let layout = next_layout(&mut rng);
+                let ptr = unsafe { allocator.alloc(layout) };
+                assert!(!ptr.is_null());
+                *slot = Some((ptr, layout));
+            }
+        }
+    }
+    for &(ptr, layout) in live.iter().flatten() {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    unsafe { _rdtsc() - start }
+}
+
+fn fixed_size_block_run() -> (u64, BlockStats) {
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { allocator.lock().init(arena_start(), ARENA_SIZE) };
+    let cycles = run_workload(&allocator);
+    allocator.assert_balanced();
+    (cycles, allocator.stats())
+}
+
+/// 返回消耗的周期数和锁上共享分配器的次数。
+fn class_locked_run() -> (u64, usize) {
+    let allocator = ClassLockedBlockAllocator::new();
+    unsafe { allocator.shared().lock().init(arena_start(), ARENA_SIZE) };
+    let cycles = run_workload(&allocator);
+    let shared_locks = allocator.shared_lock_count();
+    allocator.flush();
+    allocator.shared().assert_balanced();
+    (cycles, shared_locks)
+}
+
+fn linked_list_run() -> u64 {
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(arena_start(), ARENA_SIZE) };
+    let cycles = run_workload(&allocator);
+    assert!(allocator.check_consistency().is_ok());
+    cycles
+}
+
+/// 千分比按百分数打印，没有分配时打印 `-`。
+struct Percent(Option<usize>);
+
+impl core::fmt::Display for Percent {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0 {
+            Some(permille) => write!(f, "{:>3}.{}%", permille / 10, permille % 10),
+            None => write!(f, "{:>6}", "-"),
+        }
+    }
+}
+
+#[test_case]
+fn mixed_workload_on_both_allocators() {
+    let (block_cycles, stats) = fixed_size_block_run();
+    let list_cycles = linked_list_run();
+    let (class_cycles, shared_locks) = class_locked_run();
+
+    serial_println!();
+    serial_println!(
+        "{:<24} {:>12} {:>8} {:>8} {:>8} {:>6}",
+        "allocator",
+        "cycles",
+        "hits",
+        "misses",
+        "bypass",
+        "hit"
+    );
+    serial_println!(
+        "{:<24} {:>12} {:>8} {:>8} {:>8} {}",
+        "FixedSizeBlockAllocator",
+        block_cycles,
+        stats.hits(),
+        stats.misses(),
+        stats.bypasses(),
+        Percent(stats.hit_permille())
+    );
+    serial_println!(
+        "{:<24} {:>12} {:>8} {:>8} {:>8} {:>6}",
+        "LinkedListAllocator",
+        list_cycles,
+        "-",
+        "-",
+        "-",
+        "-"
+    );
+    serial_println!(
+        "{:<24} {:>12}   shared allocator locked {} times in {} operations",
+        "ClassLockedBlockAllocator",
+        class_cycles,
+        shared_locks,
+        OPERATIONS
+    );
+    for class in stats
+        .classes
+        .iter()
+        .filter(|class| class.hits + class.carves > 0)
+    {
+        serial_println!(
+            "  {:>5}-byte class {:>18} {:>8} {:>8} {:>8} {}",
+            class.block_size,
+            "",
+            class.hits,
+            class.carves,
+            "",
+            Percent(class.hit_permille())
+        );
+    }
+}
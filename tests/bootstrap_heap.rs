@@ -0,0 +1,86 @@
+// 在 tests/bootstrap_heap.rs 中
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use blog_os::allocator::{self, HeapStage};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+/// `init_heap` 之前从引导堆分配的数据，测试中释放。
+type Early = (Box<[u64; 32]>, Vec<u32>);
+static EARLY: spin::Mutex<Option<Early>> = spin::Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use blog_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    blog_os::init();
+    assert_eq!(allocator::heap_stage(), HeapStage::Bootstrap);
+    let early_box = Box::new([7u64; 32]);
+    let early_vec: Vec<u32> = (0..100).collect();
+    *EARLY.lock() = Some((early_box, early_vec));
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn early_allocations_stay_on_the_retired_bootstrap_heap() {
+    // 引导堆上还有分配，所以没有加入主堆
+    assert_eq!(allocator::heap_stage(), HeapStage::Retired);
+    let early = EARLY.lock();
+    let (early_box, early_vec) = early.as_ref().unwrap();
+    assert!(allocator::is_bootstrap_allocation(
+        early_box.as_ptr() as *const u8
+    ));
+    assert!(allocator::is_bootstrap_allocation(
+        early_vec.as_ptr() as *const u8
+    ));
+
+    let late = Box::new(41u64);
+    assert!(!allocator::is_bootstrap_allocation(
+        &*late as *const u64 as *const u8
+    ));
+    assert!(
+        (allocator::HEAP_START..allocator::HEAP_START + allocator::HEAP_SIZE)
+            .contains(&(&*late as *const u64 as usize))
+    );
+}
+
+#[test_case]
+fn allocations_from_both_heaps_free_cleanly_after_the_switch() {
+    let late: Vec<u64> = (0..200).collect();
+    let (early_box, mut early_vec) = EARLY.lock().take().unwrap();
+    assert!(early_box.iter().all(|&value| value == 7));
+
+    // 增长时移到主堆上，原来的块还给引导堆
+    early_vec.extend(100..1000);
+    assert!(!allocator::is_bootstrap_allocation(
+        early_vec.as_ptr() as *const u8
+    ));
+    assert!(early_vec.iter().copied().eq(0..1000));
+    assert!(late.iter().copied().eq(0..200));
+
+    drop(early_box);
+    drop((early_vec, late));
+    allocator::assert_balanced();
+}
@@ -0,0 +1,537 @@
+// 在 tests/bump_allocator.rs 中
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![feature(allocator_api)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use blog_os::allocator::{
+    self,
+    bump::{Bump, BumpAllocator, End},
+    Locked,
+};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    panic::PanicInfo,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+fn main(boot_info: &'static BootInfo) -> ! {
+    use blog_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    blog_os::init();
+    // `Bump` 从全局堆申请内存
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+/// 测试用的独立堆，每个测试都在它上面新建一个分配器。
+const ARENA_SIZE: usize = 16 * 1024;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+fn arena_start() -> usize {
+    unsafe { ptr::addr_of_mut!(ARENA.0) as usize }
+}
+
+fn new_allocator() -> Locked<BumpAllocator> {
+    let allocator = Locked::new(BumpAllocator::new());
+    unsafe { allocator.lock().init(arena_start(), ARENA_SIZE) };
+    allocator
+}
+
+#[test_case]
+fn zero_size_allocations_use_no_heap() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(0, 16).unwrap();
+    for _ in 0..1_000_000 {
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 16, 0);
+    }
+
+    // 零大小的分配既不推进 next，也不计入分配数
+    let real = Layout::from_size_align(8, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(real) };
+    assert_eq!(ptr as usize, arena_start());
+    unsafe { allocator.dealloc(layout.align() as *mut u8, layout) };
+    unsafe { allocator.dealloc(ptr, real) };
+    assert_eq!(unsafe { allocator.alloc(real) } as usize, arena_start());
+}
+
+#[test_case]
+fn rewind_reuses_the_space_after_a_checkpoint() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let kept = [(); 4].map(|_| unsafe { allocator.alloc(layout) });
+    assert!(kept.iter().all(|ptr| !ptr.is_null()));
+
+    let checkpoint = allocator.checkpoint();
+    let scratch = [(); 8].map(|_| unsafe { allocator.alloc(layout) });
+    assert!(scratch.iter().all(|ptr| !ptr.is_null()));
+
+    // 回到检查点之后，新的分配从检查点处的 next 开始
+    unsafe { allocator.rewind(checkpoint) };
+    assert_eq!(allocator.checkpoint(), checkpoint);
+    assert_eq!(unsafe { allocator.alloc(layout) }, scratch[0]);
+    assert_eq!(unsafe { allocator.alloc(layout) }, scratch[1]);
+
+    // 检查点之前的分配仍然计数，全部释放后整个堆重新可用
+    for &ptr in kept.iter().chain(&scratch[..2]) {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    assert_eq!(unsafe { allocator.alloc(layout) } as usize, arena_start());
+}
+
+#[test_case]
+fn freeing_the_latest_allocation_moves_next_back() {
+    let allocator = new_allocator();
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let pinned = unsafe { allocator.alloc(small) };
+    assert!(!pinned.is_null());
+
+    // 临时的大块按后进先出的顺序释放，每次都落在同一个位置，next 不会一直增长
+    let temporary = Layout::from_size_align(ARENA_SIZE / 2, 64).unwrap();
+    let first = unsafe { allocator.alloc(temporary) };
+    assert!(!first.is_null());
+    unsafe { allocator.dealloc(first, temporary) };
+    for _ in 0..1000 {
+        let ptr = unsafe { allocator.alloc(temporary) };
+        assert_eq!(ptr, first);
+        unsafe { allocator.dealloc(ptr, temporary) };
+    }
+
+    // 不是最近的分配时只减少计数
+    let a = unsafe { allocator.alloc(small) };
+    let b = unsafe { allocator.alloc(small) };
+    unsafe { allocator.dealloc(a, small) };
+    assert_eq!(
+        unsafe { allocator.alloc(small) } as usize,
+        b as usize + small.size()
+    );
+}
+
+#[test_case]
+fn accessors_track_the_reset_on_last_free() {
+    let empty = Locked::new(BumpAllocator::new());
+    assert_eq!((empty.remaining(), empty.used()), (0, 0));
+    assert_eq!((empty.allocation_count(), empty.peak_used()), (0, 0));
+
+    let allocator = new_allocator();
+    assert_eq!((allocator.remaining(), allocator.used()), (ARENA_SIZE, 0));
+    let byte = Layout::from_size_align(1, 1).unwrap();
+    let word = Layout::from_size_align(8, 8).unwrap();
+    let a = unsafe { allocator.alloc(byte) };
+    let b = unsafe { allocator.alloc(word) };
+    // 对齐留下的 7 字节空隙也算在 used 里
+    assert_eq!(allocator.used(), 16);
+    assert_eq!(allocator.remaining(), ARENA_SIZE - 16);
+    assert_eq!(allocator.allocation_count(), 2);
+
+    // 先释放的不是最近的分配，只减少计数；最后一个释放时整个堆重置
+    unsafe { allocator.dealloc(a, byte) };
+    assert_eq!((allocator.allocation_count(), allocator.used()), (1, 16));
+    unsafe { allocator.dealloc(b, word) };
+    assert_eq!((allocator.allocation_count(), allocator.used()), (0, 0));
+    assert_eq!(allocator.remaining(), ARENA_SIZE);
+    assert_eq!(allocator.peak_used(), 16);
+
+    // 正好用完整个堆
+    let all = Layout::from_size_align(ARENA_SIZE, 1).unwrap();
+    let ptr = unsafe { allocator.alloc(all) };
+    assert!(!ptr.is_null());
+    assert_eq!(
+        (allocator.remaining(), allocator.peak_used()),
+        (0, ARENA_SIZE)
+    );
+    assert!(unsafe { allocator.alloc(byte) }.is_null());
+    assert_eq!(allocator.allocation_count(), 1);
+    unsafe { allocator.dealloc(ptr, all) };
+    assert_eq!(allocator.remaining(), ARENA_SIZE);
+}
+
+#[test_case]
+fn downward_allocations_stay_in_bounds_without_overlap() {
+    let allocator = Locked::new(BumpAllocator::new_downward());
+    unsafe { allocator.lock().init(arena_start(), ARENA_SIZE) };
+    let arena_end = arena_start() + ARENA_SIZE;
+
+    // 大小是奇数、对齐在 1 和 64 之间轮换，直到堆放不下
+    let mut blocks = [(0, 0); 512];
+    let mut count = 0;
+    for i in 0..blocks.len() {
+        let size = 2 * (i % 37) + 1;
+        let layout = Layout::from_size_align(size, 1 << (i % 7)).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        if ptr.is_null() {
+            // 放不下时不会越过堆的开头
+            assert!(allocator.remaining() < size + layout.align());
+            break;
+        }
+        let start = ptr as usize;
+        assert_eq!(start % layout.align(), 0);
+        assert!(start >= arena_start() && start + size <= arena_end);
+        blocks[count] = (start, size);
+        count += 1;
+    }
+    assert!(count > 100);
+    let blocks = &mut blocks[..count];
+    blocks.sort_unstable();
+    assert!(blocks
+        .windows(2)
+        .all(|pair| pair[0].0 + pair[0].1 <= pair[1].0));
+    assert_eq!(allocator.used(), arena_end - blocks[0].0);
+
+    // 比整个堆还大的请求在 checked_sub 处失败
+    let huge = Layout::from_size_align(arena_end, 1).unwrap();
+    assert!(unsafe { allocator.alloc(huge) }.is_null());
+
+    // 最近的分配释放时 next 回到它的结尾，全部释放后回到堆尾
+    let layout = Layout::from_size_align(1, 1).unwrap();
+    for &(start, size) in blocks.iter() {
+        unsafe { allocator.dealloc(start as *mut u8, Layout::from_size_align(size, 1).unwrap()) };
+    }
+    assert_eq!(allocator.allocation_count(), 0);
+    assert_eq!(unsafe { allocator.alloc(layout) } as usize, arena_end - 1);
+}
+
+#[test_case]
+fn spurious_deallocs_are_ignored_and_counted() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let a = unsafe { allocator.alloc(layout) };
+    let b = unsafe { allocator.alloc(layout) };
+    unsafe { allocator.dealloc(b, layout) };
+    unsafe { allocator.dealloc(a, layout) };
+    // 重复释放时已经没有尚未释放的分配
+    unsafe { allocator.dealloc(a, layout) };
+    assert_eq!(allocator.spurious_deallocs(), 1);
+    assert_eq!(allocator.allocation_count(), 0);
+
+    // 计数没有下溢，分配器照常工作，全部释放后仍然重置
+    let c = unsafe { allocator.alloc(layout) };
+    assert_eq!(c, a);
+    let outside = (arena_start() + ARENA_SIZE) as *mut u8;
+    unsafe { allocator.dealloc(outside, layout) };
+    unsafe { allocator.dealloc(ptr::null_mut(), layout) };
+    assert_eq!(allocator.spurious_deallocs(), 3);
+    assert_eq!(allocator.allocation_count(), 1);
+    unsafe { allocator.dealloc(c, layout) };
+    assert_eq!(allocator.used(), 0);
+}
+
+#[test_case]
+fn bump_arena_returns_its_block_to_the_global_heap() {
+    allocator::assert_balanced();
+    {
+        let arena = Bump::with_capacity(16 * 1024);
+        // 从 4 个元素长到 512 个，每次增长都在分配区中重新分配
+        let mut values: Vec<u64, &Bump> = Vec::new_in(&arena);
+        for i in 0..500 {
+            values.push(i);
+        }
+        assert!(values.iter().copied().eq(0..500));
+        let boxed = Box::new_in(0x5au8, &arena);
+        assert_eq!(*boxed, 0x5a);
+        let leaked = arena.alloc_without_drop([7u32; 16]);
+        leaked[3] = 3;
+        assert!(arena.used() >= 500 * 8);
+        assert_eq!(arena.used() + arena.remaining(), 16 * 1024);
+    }
+    // 分配区连同其中的所有内容一起还给了全局堆
+    allocator::assert_balanced();
+}
+
+/// 追加的块的对齐。
+const CHUNK_ALIGN: usize = 16;
+
+fn grow_from_global_heap(min_size: usize) -> Option<(usize, usize)> {
+    let size = min_size.max(4096);
+    let layout = Layout::from_size_align(size, CHUNK_ALIGN).ok()?;
+    let ptr = unsafe { alloc::alloc::alloc(layout) };
+    (!ptr.is_null()).then_some((ptr as usize, size))
+}
+
+fn release_to_global_heap(start: usize, size: usize) {
+    let layout = Layout::from_size_align(size, CHUNK_ALIGN).unwrap();
+    unsafe { alloc::alloc::dealloc(start as *mut u8, layout) };
+}
+
+#[test_case]
+fn extra_chunks_from_the_global_heap_are_released_on_reset() {
+    const CHUNK: usize = 4096;
+    allocator::assert_balanced();
+    let bump = Locked::new(BumpAllocator::new());
+    unsafe { bump.lock().init(arena_start(), CHUNK) };
+    bump.set_grow_fn(grow_from_global_heap);
+    bump.set_release_fn(release_to_global_heap);
+
+    // 分配初始块的 3 倍，中途换了块也不会失败
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let mut ptrs = [ptr::null_mut(); 3 * CHUNK / 256];
+    for (i, slot) in ptrs.iter_mut().enumerate() {
+        let ptr = unsafe { bump.alloc(layout) };
+        assert!(!ptr.is_null(), "allocation {} failed", i);
+        unsafe { ptr.write_bytes(i as u8, 256) };
+        *slot = ptr;
+    }
+    assert!(bump.extra_chunk_count() >= 2);
+    for (i, &ptr) in ptrs.iter().enumerate() {
+        assert_eq!(unsafe { *ptr.add(255) }, i as u8);
+    }
+
+    for &ptr in ptrs.iter() {
+        unsafe { bump.dealloc(ptr, layout) };
+    }
+    assert_eq!(bump.spurious_deallocs(), 0);
+    assert_eq!(bump.extra_chunk_count(), 0);
+    // 追加的块都还给了全局堆，又从初始块的开头分配
+    allocator::assert_balanced();
+    assert_eq!(unsafe { bump.alloc(layout) } as usize, arena_start());
+}
+
+#[test_case]
+fn reset_starts_over_and_can_wipe_the_used_range() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(64, 16).unwrap();
+    // 只分配不释放，不会自动重置
+    for _ in 0..8 {
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { ptr.write_bytes(0xee, 64) };
+    }
+    assert_eq!(allocator.allocation_count(), 8);
+
+    unsafe { allocator.reset() };
+    assert_eq!(allocator.allocation_count(), 0);
+    assert_eq!(allocator.used(), 0);
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert_eq!(ptr as usize, arena_start());
+    // 没有清零时上一轮的数据还在
+    assert_eq!(unsafe { *ptr }, 0xee);
+
+    allocator.set_zero_on_reset(true);
+    for _ in 1..8 {
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { ptr.write_bytes(0xee, 64) };
+    }
+    unsafe { allocator.reset() };
+    let ptr = unsafe { allocator.alloc(Layout::from_size_align(8 * 64, 16).unwrap()) };
+    assert_eq!(ptr as usize, arena_start());
+    let bytes = unsafe { core::slice::from_raw_parts(ptr, 8 * 64) };
+    assert!(bytes.iter().all(|&byte| byte == 0));
+}
+
+#[test_case]
+fn alignment_near_the_top_of_the_address_space_never_wraps() {
+    const TOP: usize = usize::MAX - 64;
+    for shift in 0..13 {
+        let align = 1 << shift;
+        for &size in &[1, 8, 63, 64, 65] {
+            let allocator = Locked::new(BumpAllocator::new());
+            // 分配器本身不访问堆内存，这块地址不需要映射
+            unsafe { allocator.lock().init(TOP, 64) };
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let ptr = unsafe { allocator.alloc(layout) } as usize;
+            if ptr == 0 {
+                continue;
+            }
+            assert!(
+                ptr >= TOP,
+                "align {} size {} wrapped to {:#x}",
+                align,
+                size,
+                ptr
+            );
+            assert_eq!(ptr % align, 0);
+            assert!(size <= usize::MAX - ptr);
+        }
+    }
+    // 向上对齐到 128 会越过地址空间的顶端
+    let allocator = Locked::new(BumpAllocator::new());
+    unsafe { allocator.lock().init(TOP, 64) };
+    let layout = Layout::from_size_align(1, 128).unwrap();
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+}
+
+#[test_case]
+fn alignment_gaps_are_counted_as_waste() {
+    let allocator = new_allocator();
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let page = Layout::from_size_align(8, 4096).unwrap();
+    let mut ptrs = [(ptr::null_mut(), small); 7];
+    // 堆的开头按页对齐：第一个按页对齐的分配跳过 4096 - 8 字节，之后的跳过 4096 - 16 字节
+    for (i, slot) in ptrs.iter_mut().enumerate() {
+        let layout = if i % 2 == 0 { small } else { page };
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        *slot = (ptr, layout);
+    }
+    assert!(unsafe { allocator.alloc(page) }.is_null());
+    assert_eq!(allocator.wasted_bytes(), (4096 - 8) + 2 * (4096 - 16));
+    assert_eq!(allocator.max_alignment_gap(), 4096 - 8);
+    assert!(allocator.wasted_bytes() < allocator.used());
+
+    for &(ptr, layout) in ptrs.iter() {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    // 全部释放后和分配器一起重置
+    assert_eq!(allocator.wasted_bytes(), 0);
+    assert_eq!(allocator.max_alignment_gap(), 0);
+}
+
+#[test_case]
+fn scratch_allocator_on_a_boxed_slice_fails_cleanly_and_frees_its_memory() {
+    allocator::assert_balanced();
+    {
+        let mut scratch = BumpAllocator::with_memory(Box::new_uninit_slice(4096));
+        let layout = Layout::from_size_align(48, 16).unwrap();
+        let mut count = 0;
+        while let Some(ptr) = scratch.alloc(layout) {
+            assert_eq!(ptr.as_ptr() as usize % 16, 0);
+            unsafe { ptr.as_ptr().write_bytes(count as u8, 48) };
+            count += 1;
+        }
+        // 4096 字节放得下 85 个按 16 字节对齐的 48 字节，开头的对齐最多再少一个
+        assert!(count == 85 || count == 84, "{} allocations fit", count);
+        assert_eq!(scratch.allocation_count(), count);
+        assert!(scratch
+            .alloc(Layout::from_size_align(1, 1).unwrap())
+            .is_some());
+        assert!(scratch.alloc(layout).is_none());
+    }
+    // 丢弃分配器时内存还给了全局堆
+    allocator::assert_balanced();
+}
+
+static OOM_CALLS: AtomicUsize = AtomicUsize::new(0);
+static OOM_REMAINING: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+fn count_oom(bump: &BumpAllocator, layout: Layout) {
+    assert_eq!(layout.size(), 150);
+    OOM_CALLS.fetch_add(1, Ordering::Relaxed);
+    OOM_REMAINING.store(bump.remaining(), Ordering::Relaxed);
+}
+
+#[test_case]
+fn oom_hook_runs_once_per_failed_allocation() {
+    let allocator = Locked::new(BumpAllocator::new());
+    unsafe { allocator.lock().init(arena_start(), 1000) };
+    allocator.set_oom_hook(count_oom);
+    let layout = Layout::from_size_align(100, 4).unwrap();
+    for _ in 0..9 {
+        assert!(!unsafe { allocator.alloc(layout) }.is_null());
+    }
+    assert_eq!(OOM_CALLS.load(Ordering::Relaxed), 0);
+
+    // 剩下的 100 字节放不下 150 字节
+    let large = Layout::from_size_align(150, 4).unwrap();
+    for _ in 0..3 {
+        assert!(unsafe { allocator.alloc(large) }.is_null());
+    }
+    assert_eq!(OOM_CALLS.load(Ordering::Relaxed), 3);
+    assert_eq!(OOM_REMAINING.load(Ordering::Relaxed), 100);
+    // 成功的分配不调用钩子
+    assert!(!unsafe { allocator.alloc(layout) }.is_null());
+    assert_eq!(OOM_CALLS.load(Ordering::Relaxed), 3);
+}
+
+#[test_case]
+fn freeing_everything_wipes_the_range_used_since_the_last_reset() {
+    let allocator = new_allocator();
+    allocator.set_zero_on_reset(true);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let mut ptrs = [ptr::null_mut(); 8];
+    for ptr in ptrs.iter_mut() {
+        *ptr = unsafe { allocator.alloc(layout) };
+        unsafe { ptr.write_bytes(0xab, 256) };
+    }
+    // 释放最近的分配让 next 退回去，那里的旧数据也要清零
+    for &ptr in ptrs.iter().rev() {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    assert_eq!(allocator.used(), 0);
+    let start = arena_start() as *const u8;
+    for offset in 0..8 * 256 {
+        assert_eq!(
+            unsafe { start.add(offset).read_volatile() },
+            0,
+            "byte {}",
+            offset
+        );
+    }
+}
+
+#[test_case]
+fn both_ends_meet_in_the_middle_without_overlap() {
+    let mut bump = BumpAllocator::with_memory(Box::new_uninit_slice(4096));
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut failed = [false; 2];
+    let mut i = 0;
+    // 两端交替分配，对齐在 1 到 256 之间变化，直到两端都分配失败
+    while !(failed[0] && failed[1]) {
+        let end = if i % 2 == 0 { End::Bottom } else { End::Top };
+        let layout = Layout::from_size_align(24 + i % 40, 1 << (i % 9)).unwrap();
+        match bump.alloc_at(layout, end) {
+            Some(ptr) => {
+                let start = ptr.as_ptr() as usize;
+                assert_eq!(start % layout.align(), 0);
+                ranges.push((start, start + layout.size()));
+            }
+            None => failed[i % 2] = true,
+        }
+        i += 1;
+    }
+    for (n, &(start, end)) in ranges.iter().enumerate() {
+        for &(other_start, other_end) in &ranges[n + 1..] {
+            assert!(
+                end <= other_start || other_end <= start,
+                "allocations overlap"
+            );
+        }
+    }
+    assert_eq!(bump.allocation_count(), ranges.len());
+    assert!(bump.remaining() < 24 + 40 + 256);
+    assert_eq!(bump.used() + bump.remaining(), 4096);
+}
+
+#[test_case]
+fn each_end_rewinds_independently() {
+    let allocator = new_allocator();
+    let mut bump = allocator.lock();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let metadata = bump.alloc_at(layout, End::Bottom).unwrap();
+    let top = bump.checkpoint_at(End::Top);
+    for _ in 0..3 {
+        let scratch = bump.alloc_at(layout, End::Top).unwrap();
+        assert!(scratch.as_ptr() as usize >= arena_start() + ARENA_SIZE - 3 * 64);
+        // 底端在两次回滚之间继续分配，不受回滚影响
+        bump.alloc_at(layout, End::Bottom).unwrap();
+        unsafe { bump.rewind(top) };
+    }
+    assert_eq!(bump.allocation_count(), 4);
+    assert_eq!(bump.used(), 4 * 64);
+    // 顶端每次都从堆尾重新开始
+    let scratch = bump.alloc_at(layout, End::Top).unwrap();
+    assert_eq!(scratch.as_ptr() as usize, arena_start() + ARENA_SIZE - 64);
+    assert_eq!(metadata.as_ptr() as usize, arena_start());
+}
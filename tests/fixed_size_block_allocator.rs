@@ -0,0 +1,1335 @@
+// 在 tests/fixed_size_block_allocator.rs 中
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+#![feature(allocator_api)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+#[cfg(not(feature = "heap-slab"))]
+use blog_os::allocator::fixed_size_block::ClassStats;
+#[cfg(not(feature = "min-align-16"))]
+use blog_os::allocator::fixed_size_block::ClassWaste;
+use blog_os::{
+    allocator::{
+        fixed_size_block::{
+            ClassLockedBlockAllocator, FixedSizeBlockAllocator, PageAllocator,
+            PerCpuBlockAllocator, SelfTestError, SelfTestReason, CLASS_COUNT, DEFAULT_CACHE_CAP,
+            MIN_ADAPTIVE_CAP, MIN_ALIGN, MIN_RETURNABLE_BLOCK, PAGE_SIZE,
+        },
+        Locked,
+    },
+    serial_println,
+};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    alloc::{Allocator, GlobalAlloc, Layout},
+    arch::x86_64::_rdtsc,
+    fmt,
+    panic::PanicInfo,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    test_main();
+    loop {}
+}
+
+/// 测试用的独立堆，每个测试都在它上面新建一个分配器。
+const ARENA_SIZE: usize = 1024 * 1024;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+fn arena_start() -> usize {
+    unsafe { ptr::addr_of_mut!(ARENA.0) as usize }
+}
+
+fn new_allocator() -> Locked<FixedSizeBlockAllocator> {
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { allocator.lock().init(arena_start(), ARENA_SIZE) };
+    without_quarantine(&allocator);
+    allocator
+}
+
+/// 其余测试假设释放的块马上回到空闲列表，启用 heap-quarantine 时关闭隔离区。
+fn without_quarantine<const N: usize>(allocator: &Locked<FixedSizeBlockAllocator<N>>) {
+    #[cfg(feature = "heap-quarantine")]
+    allocator.lock().set_quarantine_depth(0);
+    #[cfg(not(feature = "heap-quarantine"))]
+    let _ = allocator;
+}
+
+/// 块大小为 `block_size` 的类的统计。
+#[cfg(not(feature = "heap-slab"))]
+fn class_stats(allocator: &Locked<FixedSizeBlockAllocator>, block_size: usize) -> ClassStats {
+    let stats = allocator.lock().stats();
+    *stats
+        .classes
+        .iter()
+        .find(|class| class.block_size == block_size)
+        .unwrap()
+}
+
+/// 先把整个 ARENA 填成 `fill`，再用 `init_zeroed` 初始化分配器。
+fn zeroed_allocator(fill: u8) -> Locked<FixedSizeBlockAllocator> {
+    unsafe { ptr::write_bytes(arena_start() as *mut u8, fill, ARENA_SIZE) };
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { allocator.lock().init_zeroed(arena_start(), ARENA_SIZE) };
+    without_quarantine(&allocator);
+    allocator
+}
+
+fn bytes<'a>(ptr: *mut u8, size: usize) -> &'a [u8] {
+    assert!(!ptr.is_null());
+    unsafe { core::slice::from_raw_parts(ptr, size) }
+}
+
+#[test_case]
+fn zero_size_allocations_use_no_heap() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(0, 16).unwrap();
+    for _ in 0..1_000_000 {
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 16, 0);
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    // 零大小的分配没有占用后备堆，第一个真正的块仍然从堆的起点切出
+    let real = Layout::from_size_align(8, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(real) };
+    #[cfg(not(feature = "heap-slab"))]
+    assert_eq!(ptr as usize, arena_start());
+    // 启用 heap-slab 时块排在从堆的起点切出的 slab 的头部之后
+    #[cfg(feature = "heap-slab")]
+    assert_eq!(ptr as usize & !4095, arena_start());
+    unsafe { allocator.dealloc(ptr, real) };
+}
+
+#[test_case]
+fn usable_size_reports_block_size() {
+    let allocator = new_allocator();
+    for (size, usable) in [
+        (0, 0),
+        (1, MIN_ALIGN),
+        (100, 128),
+        (2048, 2048),
+        (3000, 4096),
+        (9000, 9000),
+    ] {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(allocator.usable_size(ptr, layout), usable);
+        // 多出来的部分可以放心写入
+        unsafe { ptr.write_bytes(0x55, usable) };
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+}
+
+#[test_case]
+fn surplus_blocks_return_to_fallback() {
+    let allocator = new_allocator();
+    assert_eq!(allocator.lock().cache_cap(2048), Some(DEFAULT_CACHE_CAP));
+    assert_eq!(allocator.lock().cache_cap(3000), None);
+    assert_eq!(allocator.lock().cache_cap(8192), Some(DEFAULT_CACHE_CAP));
+    allocator.lock().set_cache_cap(2048, 2);
+    let before = allocator.lock().fallback_free();
+
+    let layout = Layout::from_size_align(2048, 8).unwrap();
+    let mut blocks = [ptr::null_mut(); 6];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+        assert!(!block.is_null());
+    }
+    assert_eq!(allocator.lock().fallback_free(), before - 6 * 2048);
+    for &block in blocks.iter() {
+        unsafe { allocator.dealloc(block, layout) };
+    }
+
+    // 只有缓存的两个块还占着后备堆，其余的都还了回去
+    assert_eq!(allocator.lock().cached_blocks(2048), Some(2));
+    assert_eq!(allocator.lock().fallback_free(), before - 2 * 2048);
+
+    // 缓存的块仍然优先使用
+    let a = unsafe { allocator.alloc(layout) };
+    let b = unsafe { allocator.alloc(layout) };
+    assert_eq!(allocator.lock().cached_blocks(2048), Some(0));
+    assert_eq!(allocator.lock().fallback_free(), before - 2 * 2048);
+    unsafe {
+        allocator.dealloc(a, layout);
+        allocator.dealloc(b, layout);
+    }
+}
+
+// 启用 heap-slab 时小块的类不使用批量切出的空闲列表，启用 min-align-16 时不使用 8 字节的类
+#[cfg(not(any(feature = "heap-slab", feature = "min-align-16")))]
+#[test_case]
+fn stats_count_every_branch() {
+    let allocator = new_allocator();
+    let tiny = Layout::from_size_align(8, 8).unwrap();
+    let medium = Layout::from_size_align(100, 4).unwrap();
+    let large = Layout::from_size_align(9000, 8).unwrap();
+    // 每次只切一个块，计数才和分配一一对应
+    allocator.lock().set_batch_size(8, 1);
+    allocator.lock().set_batch_size(128, 1);
+
+    // 三次切块，释放两个，再分配三次：两次命中空闲列表，一次切块
+    let mut blocks = [ptr::null_mut(); 6];
+    for block in &mut blocks[..3] {
+        *block = unsafe { allocator.alloc(tiny) };
+    }
+    unsafe {
+        allocator.dealloc(blocks[0], tiny);
+        allocator.dealloc(blocks[1], tiny);
+    }
+    for block in &mut blocks[3..] {
+        *block = unsafe { allocator.alloc(tiny) };
+    }
+    let m = unsafe { allocator.alloc(medium) };
+    unsafe { allocator.dealloc(m, medium) };
+    let l = unsafe { allocator.alloc(large) };
+
+    let stats = allocator.lock().stats();
+    assert_eq!(
+        stats.classes[0],
+        ClassStats {
+            block_size: 8,
+            hits: 2,
+            carves: 4,
+            returned: 0,
+            cached: 0,
+            live: 4,
+        }
+    );
+    assert_eq!(
+        stats.classes[4],
+        ClassStats {
+            block_size: 128,
+            hits: 0,
+            carves: 1,
+            returned: 0,
+            cached: 1,
+            live: 0,
+        }
+    );
+    let others = stats
+        .classes
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 0 && i != 4);
+    for (_, class) in others {
+        assert_eq!(
+            class,
+            &ClassStats {
+                block_size: class.block_size,
+                ..ClassStats::default()
+            }
+        );
+    }
+    assert_eq!(stats.large_allocs, 1);
+    // 八次分配中两次命中，五次切块，一次绕过缓存
+    assert_eq!((stats.hits(), stats.misses(), stats.bypasses()), (2, 5, 1));
+    assert_eq!(stats.hit_permille(), Some(250));
+    assert_eq!(stats.classes[0].hit_permille(), Some(333));
+    assert_eq!(stats.classes[1].hit_permille(), None);
+    unsafe { allocator.dealloc(l, large) };
+}
+
+#[test_case]
+fn page_sized_buffers_reuse_cached_blocks() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+    for _ in 0..1000 {
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 4096, 0);
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    // 只有第一次需要从后备分配器切块
+    let stats = allocator.lock().stats();
+    let class = stats
+        .classes
+        .iter()
+        .find(|class| class.block_size == 4096)
+        .unwrap();
+    assert_eq!((class.carves, class.hits), (1, 999));
+    assert_eq!(stats.large_allocs, 0);
+}
+
+// 启用 heap-slab 时小块的类不使用批量切出的空闲列表
+#[cfg(not(feature = "heap-slab"))]
+#[test_case]
+fn prefilled_blocks_serve_allocations_without_fallback() {
+    const PREFILL: usize = 32;
+    let allocator = new_allocator();
+    assert_eq!(
+        allocator.lock().prefill(&[PREFILL; CLASS_COUNT]),
+        [PREFILL; CLASS_COUNT]
+    );
+    let fallback_free = allocator.lock().fallback_free();
+
+    let block_sizes = allocator
+        .lock()
+        .stats()
+        .classes
+        .map(|class| class.block_size);
+    // 启用 min-align-16 时 8 字节的类不再使用，预先切出的块一直留着
+    for &block_size in block_sizes.iter().filter(|&&size| size >= MIN_ALIGN) {
+        let layout = Layout::from_size_align(block_size, 1).unwrap();
+        for _ in 0..PREFILL {
+            assert!(!unsafe { allocator.alloc(layout) }.is_null());
+        }
+    }
+    let stats = allocator.lock().stats();
+    for class in stats.classes.iter() {
+        let expected = if class.block_size >= MIN_ALIGN {
+            (PREFILL, 0, 0)
+        } else {
+            (0, 0, PREFILL)
+        };
+        assert_eq!((class.hits, class.carves, class.cached), expected);
+    }
+    assert_eq!(allocator.lock().fallback_free(), fallback_free);
+
+    // 预先切出的块用完以后才回到后备分配器
+    let tiny = Layout::from_size_align(MIN_ALIGN, MIN_ALIGN).unwrap();
+    assert!(!unsafe { allocator.alloc(tiny) }.is_null());
+    assert_eq!(class_stats(&allocator, MIN_ALIGN).carves, 1);
+}
+
+#[test_case]
+fn prefill_stops_when_fallback_runs_short() {
+    let allocator = new_allocator();
+    let mut counts = [0; CLASS_COUNT];
+    counts[CLASS_COUNT - 1] = 1000;
+    let filled = allocator.lock().prefill(&counts);
+    assert!(filled[CLASS_COUNT - 1] > 0 && filled[CLASS_COUNT - 1] < 1000);
+    assert_eq!(filled[..CLASS_COUNT - 1], [0; CLASS_COUNT - 1]);
+    assert_eq!(
+        allocator.lock().cached_blocks(8192),
+        Some(filled[CLASS_COUNT - 1])
+    );
+}
+
+// 启用 heap-slab 时小块的类不使用批量切出的空闲列表
+#[cfg(not(feature = "heap-slab"))]
+#[test_case]
+fn misses_carve_a_batch_of_blocks() {
+    const BURST: usize = 160;
+    let tiny = Layout::from_size_align(MIN_ALIGN, MIN_ALIGN).unwrap();
+    let carves_for_burst = |batch: Option<usize>| {
+        let allocator = new_allocator();
+        if let Some(batch) = batch {
+            allocator.lock().set_batch_size(MIN_ALIGN, batch);
+        }
+        let mut blocks = [ptr::null_mut(); BURST];
+        for block in blocks.iter_mut() {
+            *block = unsafe { allocator.alloc(tiny) };
+            assert!(!block.is_null());
+        }
+        // 同一批的块互不重叠
+        blocks.sort_unstable();
+        assert!(blocks
+            .windows(2)
+            .all(|pair| pair[1] as usize - pair[0] as usize >= MIN_ALIGN));
+        for &block in blocks.iter() {
+            unsafe { allocator.dealloc(block, tiny) };
+        }
+        class_stats(&allocator, MIN_ALIGN).carves
+    };
+
+    let defaults = new_allocator();
+    assert_eq!(defaults.lock().batch_size(MIN_ALIGN), Some(16));
+    assert_eq!(defaults.lock().batch_size(4096), Some(1));
+    assert_eq!(carves_for_burst(None), BURST / 16);
+    assert_eq!(carves_for_burst(Some(1)), BURST);
+}
+
+#[test_case]
+fn zeroed_allocations_are_zero_in_both_paths() {
+    let allocator = zeroed_allocator(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let batch = allocator.lock().batch_size(64).unwrap();
+    let mut blocks = [ptr::null_mut(); 16];
+    for block in blocks[..batch].iter_mut() {
+        *block = unsafe { allocator.alloc_zeroed(layout) };
+        assert!(bytes(*block, 64).iter().all(|&byte| byte == 0));
+        unsafe { block.write_bytes(0xab, 64) };
+    }
+    let large = Layout::from_size_align(9000, 8).unwrap();
+    let ptr = unsafe { allocator.alloc_zeroed(large) };
+    assert!(bytes(ptr, 9000).iter().all(|&byte| byte == 0));
+    unsafe { allocator.dealloc(ptr, large) };
+
+    // 回收的块写过非零数据，必须重新清零
+    for &block in blocks[..batch].iter() {
+        unsafe { allocator.dealloc(block, layout) };
+    }
+    for _ in 0..batch {
+        let ptr = unsafe { allocator.alloc_zeroed(layout) };
+        assert!(bytes(ptr, 64).iter().all(|&byte| byte == 0));
+    }
+    let ptr = unsafe { allocator.alloc_zeroed(large) };
+    assert!(bytes(ptr, 9000).iter().all(|&byte| byte == 0));
+}
+
+// 启用 `heap-poison` 时放进空闲列表的块都被填充，只有每批的第一个块跳过清零；
+// 启用 heap-slab 时小块不是成批切出的；启用 heap-verify 时空闲块中还有 magic；
+// 启用 zero-on-free 时放进空闲列表的块都被清零
+#[cfg(not(any(
+    feature = "heap-poison",
+    feature = "heap-slab",
+    feature = "heap-verify",
+    feature = "zero-on-free"
+)))]
+#[test_case]
+fn fresh_blocks_skip_the_memset() {
+    // 故意违反 `init_zeroed` 的约定，留下的填充说明哪些字节没有被清零
+    let allocator = zeroed_allocator(0xcd);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let first = unsafe { allocator.alloc_zeroed(layout) };
+    let second = unsafe { allocator.alloc_zeroed(layout) };
+    assert_eq!(second as usize, first as usize + 64);
+    // 只清零了后备分配器的空闲记录和空闲列表的节点
+    let record = bytes(first, 64)
+        .iter()
+        .take_while(|&&byte| byte == 0)
+        .count();
+    assert!(record <= 32);
+    assert!(bytes(first, 64)[record..].iter().all(|&byte| byte == 0xcd));
+    assert!(bytes(second, 8).iter().all(|&byte| byte == 0));
+    assert!(bytes(second, 64)[8..].iter().all(|&byte| byte == 0xcd));
+
+    // 回收的块不再全零，总是清零
+    unsafe { allocator.dealloc(first, layout) };
+    let recycled = unsafe { allocator.alloc_zeroed(layout) };
+    assert_eq!(recycled, first);
+    assert!(bytes(recycled, 64).iter().all(|&byte| byte == 0));
+
+    // 没有用 init_zeroed 初始化的堆不做任何假设
+    unsafe { ptr::write_bytes(arena_start() as *mut u8, 0xcd, ARENA_SIZE) };
+    let allocator = new_allocator();
+    for _ in 0..2 {
+        let ptr = unsafe { allocator.alloc_zeroed(layout) };
+        assert!(bytes(ptr, 64).iter().all(|&byte| byte == 0));
+    }
+}
+
+#[test_case]
+fn realloc_within_a_class_keeps_the_block() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(40, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    unsafe { ptr.write_bytes(0x5a, 40) };
+    let before = allocator.lock().stats();
+
+    // 40 和 60 字节都在 64 字节的类里，缩回 33 字节也一样
+    let grown = unsafe { allocator.realloc(ptr, layout, 60) };
+    assert_eq!(grown, ptr);
+    let grown_layout = Layout::from_size_align(60, 8).unwrap();
+    let shrunk = unsafe { allocator.realloc(grown, grown_layout, 33) };
+    assert_eq!(shrunk, ptr);
+    assert_eq!(allocator.lock().stats(), before);
+
+    // 离开这个类时才分配新块并复制
+    let shrunk_layout = Layout::from_size_align(33, 8).unwrap();
+    let moved = unsafe { allocator.realloc(shrunk, shrunk_layout, 100) };
+    assert_ne!(moved, ptr);
+    assert!(bytes(moved, 33).iter().all(|&byte| byte == 0x5a));
+    let stats = allocator.lock().stats();
+    assert_eq!(stats.classes[3].live, 0);
+    assert_eq!(stats.classes[4].live, 1);
+}
+
+#[cfg(feature = "heap-poison")]
+#[test_case]
+fn freed_blocks_are_poisoned() {
+    use blog_os::allocator::fixed_size_block::POISON;
+
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    // 启用 heap-slab 时再占住同一个 slab 中的一个块，免得空出来的 slab 被还给后备分配器
+    #[cfg(feature = "heap-slab")]
+    let _pinned = unsafe { allocator.alloc(layout) };
+    unsafe { ptr.write_bytes(0x42, 256) };
+    unsafe { allocator.dealloc(ptr, layout) };
+
+    // 前 8 字节是 ListNode 的指针，启用 heap-verify 时之后还有 8 字节的 magic，再之后都是填充
+    let header = if cfg!(feature = "heap-verify") { 16 } else { 8 };
+    assert!(bytes(ptr, 256)[header..].iter().all(|&byte| byte == POISON));
+
+    // 填充完好时照常分配，alloc_zeroed 也会清掉填充
+    let again = unsafe { allocator.alloc_zeroed(layout) };
+    assert_eq!(again, ptr);
+    assert!(bytes(again, 256).iter().all(|&byte| byte == 0));
+}
+
+#[cfg(all(feature = "zero-on-free", not(feature = "heap-poison")))]
+#[test_case]
+fn freed_memory_is_scrubbed() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    unsafe { ptr.write_bytes(0x42, 256) };
+    unsafe { allocator.dealloc(ptr, layout) };
+    // 开头是空闲列表的记录，之后都清零了
+    let header = if cfg!(feature = "heap-verify") { 16 } else { 8 };
+    assert!(bytes(ptr, 256)[header..].iter().all(|&byte| byte == 0));
+    assert!(!bytes(ptr, 256).contains(&0x42));
+
+    // 交给后备分配器的大块也清零，只留下后备分配器的空闲记录
+    let large = Layout::from_size_align(9000, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(large) };
+    unsafe { ptr.write_bytes(0x42, 9000) };
+    unsafe { allocator.dealloc(ptr, large) };
+    let dirty = bytes(ptr, 9000).iter().filter(|&&byte| byte != 0).count();
+    assert!(dirty <= MIN_RETURNABLE_BLOCK);
+    assert!(!bytes(ptr, 9000).contains(&0x42));
+
+    // 回收的块已经是零，alloc_zeroed 得到的仍然全零
+    let again = unsafe { allocator.alloc_zeroed(layout) };
+    assert!(bytes(again, 256).iter().all(|&byte| byte == 0));
+}
+
+#[test_case]
+fn every_size_maps_to_the_smallest_fitting_class() {
+    let allocator = new_allocator();
+    // 每次调用都会在 debug 构建中与逐个比较的结果对照
+    for align in [1, 8, 64, 4096] {
+        for size in 1..=8192 {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let expected = size.max(align).max(MIN_ALIGN).next_power_of_two();
+            assert_eq!(allocator.usable_size(ptr::null_mut(), layout), expected);
+        }
+    }
+    let large = Layout::from_size_align(8193, 8).unwrap();
+    assert_eq!(allocator.usable_size(ptr::null_mut(), large), 8193);
+}
+
+#[test_case]
+fn small_block_round_trip_cycles() {
+    const ROUNDS: u64 = 100_000;
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    unsafe { allocator.dealloc(allocator.alloc(layout), layout) };
+    let start = unsafe { _rdtsc() };
+    for _ in 0..ROUNDS {
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    let cycles = unsafe { _rdtsc() } - start;
+    serial_println!("{} cycles per 16-byte alloc/dealloc", cycles / ROUNDS);
+}
+
+#[test_case]
+fn drain_returns_cached_blocks_to_fallback() {
+    let allocator = new_allocator();
+    let initial = allocator.lock().fallback_free();
+    let mut blocks = [ptr::null_mut(); CLASS_COUNT];
+    for (i, block) in blocks.iter_mut().enumerate() {
+        let layout = Layout::from_size_align(8 << i, 8).unwrap();
+        *block = unsafe { allocator.alloc(layout) };
+        assert!(!block.is_null());
+    }
+    for (i, &block) in blocks.iter().enumerate() {
+        let layout = Layout::from_size_align(8 << i, 8).unwrap();
+        unsafe { allocator.dealloc(block, layout) };
+    }
+    assert!(allocator.lock().fallback_free() < initial);
+
+    // 太小的块不能单独归还，整批留在空闲列表中；启用 heap-slab 时它们的 slab 已经归还，
+    // 启用 min-align-16 时 8 字节的类没有用到
+    let kept = |block_size: usize| {
+        if cfg!(feature = "heap-slab") || !(MIN_ALIGN..MIN_RETURNABLE_BLOCK).contains(&block_size) {
+            0
+        } else {
+            allocator.lock().batch_size(block_size).unwrap()
+        }
+    };
+    let before = allocator.lock().fallback_free();
+    let released = allocator.drain();
+    assert_eq!(allocator.lock().fallback_free(), before + released);
+    let stats = allocator.lock().stats();
+    let kept_bytes: usize = stats
+        .classes
+        .iter()
+        .map(|class| class.block_size * kept(class.block_size))
+        .sum();
+    assert_eq!(allocator.lock().fallback_free(), initial - kept_bytes);
+    for class in stats.classes.iter() {
+        assert_eq!(class.cached, kept(class.block_size));
+    }
+    assert_eq!(allocator.drain(), 0);
+}
+
+#[cfg(feature = "heap-slab")]
+#[test_case]
+fn emptied_slabs_return_to_fallback() {
+    const BURST: usize = 1000;
+    let allocator = new_allocator();
+    let initial = allocator.lock().fallback_free();
+    let mut blocks = [ptr::null_mut(); BURST];
+    for round in 0..3 {
+        for (i, block) in blocks.iter_mut().enumerate() {
+            let layout = Layout::from_size_align(8 << (i % 7), 8).unwrap();
+            *block = unsafe { allocator.alloc(layout) };
+            assert!(!block.is_null());
+            unsafe { block.write_bytes(round as u8, 8 << (i % 7)) };
+        }
+        assert!(allocator.lock().fallback_free() < initial);
+        // 按与分配不同的顺序释放，slab 交替地变满和变空
+        for i in (0..BURST).step_by(2).chain((1..BURST).step_by(2)) {
+            let layout = Layout::from_size_align(8 << (i % 7), 8).unwrap();
+            unsafe { allocator.dealloc(blocks[i], layout) };
+        }
+        assert_eq!(allocator.lock().fallback_free(), initial);
+    }
+
+    let stats = allocator.lock().stats();
+    for class in stats.classes[..7].iter() {
+        assert_eq!(class.cached, 0);
+        assert_eq!(class.live, 0);
+        // 启用 min-align-16 时 8 字节的请求落在 16 字节的类里
+        if class.block_size < MIN_ALIGN {
+            continue;
+        }
+        assert!(class.carves > 0);
+        // 每个切出的 slab 都整个归还了
+        assert_eq!(class.returned % class.carves, 0);
+    }
+}
+
+#[test_case]
+fn every_alignment_up_to_two_mebibytes() {
+    let allocator = new_allocator();
+    let initial = allocator.lock().fallback_free();
+    for size in [1, 8, 16, 100, 4096, 8192, 9000] {
+        for shift in 0..=21 {
+            let align = 1 << shift;
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let ptr = unsafe { allocator.alloc(layout) };
+            if ptr.is_null() {
+                // 只有对齐大到 ARENA 放不下时才会失败
+                assert!(align > ARENA_SIZE / 4, "{:?}", layout);
+                continue;
+            }
+            assert_eq!(ptr as usize % align, 0, "{:?}", layout);
+            unsafe { ptr.write_bytes(0x77, size) };
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+    }
+
+    // 每个指针都回到了分配它的路径：没有尚未释放的块，清空缓存后后备堆完全恢复
+    let stats = allocator.lock().stats();
+    assert!(stats.classes.iter().all(|class| class.live == 0));
+    allocator.drain();
+    let block_sizes = *allocator.lock().block_sizes();
+    let kept: usize = block_sizes
+        .iter()
+        .filter(|&&block_size| block_size < MIN_RETURNABLE_BLOCK)
+        .map(|&block_size| allocator.lock().cached_blocks(block_size).unwrap() * block_size)
+        .sum();
+    assert_eq!(allocator.lock().fallback_free(), initial - kept);
+}
+
+/// 注册了回收函数的分配器，使用自己的 ARENA，不会被其他测试重新初始化。
+const SHRINK_ARENA_SIZE: usize = 64 * 1024;
+#[repr(align(4096))]
+struct ShrinkArena([u8; SHRINK_ARENA_SIZE]);
+static mut SHRINK_ARENA: ShrinkArena = ShrinkArena([0; SHRINK_ARENA_SIZE]);
+static SHRINKABLE: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+
+#[test_case]
+fn shrinker_frees_cached_blocks_when_the_heap_runs_out() {
+    let start = unsafe { ptr::addr_of_mut!(SHRINK_ARENA.0) as usize };
+    unsafe { SHRINKABLE.lock().init(start, SHRINK_ARENA_SIZE) };
+    without_quarantine(&SHRINKABLE);
+    SHRINKABLE.lock().set_cache_cap(1024, usize::MAX);
+
+    // 用 1024 字节的块占满后备堆，全部释放后都留在缓存中
+    let small = Layout::from_size_align(1024, 8).unwrap();
+    let mut blocks = [ptr::null_mut(); SHRINK_ARENA_SIZE / 1024];
+    let mut count = 0;
+    for block in blocks.iter_mut() {
+        *block = unsafe { SHRINKABLE.alloc(small) };
+        if block.is_null() {
+            break;
+        }
+        count += 1;
+    }
+    for &block in blocks[..count].iter() {
+        unsafe { SHRINKABLE.dealloc(block, small) };
+    }
+    let large = Layout::from_size_align(16 * 1024, 8).unwrap();
+    assert!(unsafe { SHRINKABLE.alloc(large) }.is_null());
+
+    // 注册之后，分配失败时回收函数归还缓存的块，重试就能成功
+    assert!(blog_os::allocator::register_shrinker(|target| {
+        SHRINKABLE.trim(target)
+    }));
+    let ptr = unsafe { SHRINKABLE.alloc(large) };
+    assert!(!ptr.is_null());
+    assert!(SHRINKABLE.lock().cached_blocks(1024).unwrap() < count);
+    unsafe { SHRINKABLE.dealloc(ptr, large) };
+}
+
+/// `trim` 测试用的堆，要缓存得下超过 1 MiB 的块。
+const TRIM_ARENA_SIZE: usize = 2 * 1024 * 1024;
+#[repr(align(4096))]
+struct TrimArena([u8; TRIM_ARENA_SIZE]);
+static mut TRIM_ARENA: TrimArena = TrimArena([0; TRIM_ARENA_SIZE]);
+
+#[test_case]
+fn trim_releases_cached_blocks_up_to_the_target() {
+    const MIB: usize = 1024 * 1024;
+    const BLOCKS: usize = 160;
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    let start = unsafe { ptr::addr_of_mut!(TRIM_ARENA.0) as usize };
+    unsafe { allocator.lock().init(start, TRIM_ARENA_SIZE) };
+    without_quarantine(&allocator);
+    allocator.lock().set_cache_cap(8192, usize::MAX);
+
+    // 160 个 8 KiB 的块全部释放后留在缓存中，一共 1.25 MiB
+    let layout = Layout::from_size_align(8192, 8).unwrap();
+    let blocks = [(); BLOCKS].map(|_| unsafe { allocator.alloc(layout) });
+    for &block in blocks.iter() {
+        assert!(!block.is_null());
+        unsafe { allocator.dealloc(block, layout) };
+    }
+    let free_before = allocator.fallback_free();
+
+    // 从最大的类开始归还，正好达到目标就停下
+    assert_eq!(allocator.trim(MIB), MIB);
+    assert!(allocator.fallback_free() >= free_before + MIB);
+    assert_eq!(
+        allocator.lock().cached_blocks(8192),
+        Some(BLOCKS - MIB / 8192)
+    );
+}
+
+#[test_case]
+fn self_test_reports_a_corrupted_free_list_pointer() {
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { allocator.lock().init(arena_start(), ARENA_SIZE) };
+    without_quarantine(&allocator);
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let block = unsafe { allocator.alloc(layout) };
+    assert!(!block.is_null());
+    unsafe { allocator.dealloc(block, layout) };
+    assert_eq!(allocator.self_test(), Ok(()));
+
+    // 自检的分配也把块放回了列表头部，把它的 `next` 改成指向块的中间
+    let wild = block as usize + 8;
+    unsafe { (block as *mut usize).write(wild) };
+    assert_eq!(
+        allocator.self_test(),
+        Err(SelfTestError {
+            block_size: 1024,
+            addr: wild,
+            reason: SelfTestReason::Misaligned,
+        })
+    );
+}
+
+#[test_case]
+fn tick_shrinks_the_cache_of_a_class_that_went_quiet() {
+    const HOT: usize = 300;
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { allocator.lock().init(arena_start(), ARENA_SIZE) };
+    without_quarantine(&allocator);
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+
+    // 繁忙的一段时间之后，上限跟着这段时间的分配次数变大，释放的块全部留在缓存中
+    let blocks = [(); HOT].map(|_| unsafe { allocator.alloc(layout) });
+    assert!(blocks.iter().all(|block| !block.is_null()));
+    allocator.tick();
+    assert_eq!(allocator.lock().cache_cap(1024), Some(HOT));
+    for &block in blocks.iter() {
+        unsafe { allocator.dealloc(block, layout) };
+    }
+    let hot_cached = allocator.lock().cached_blocks(1024).unwrap();
+    assert!(hot_cached >= HOT);
+    let free_before = allocator.fallback_free();
+
+    // 之后不再分配，上限每次减半，直到下限
+    for _ in 0..8 {
+        allocator.tick();
+    }
+    assert_eq!(allocator.lock().cache_cap(1024), Some(MIN_ADAPTIVE_CAP));
+    assert_eq!(allocator.lock().cached_blocks(1024), Some(MIN_ADAPTIVE_CAP));
+    let returned = (hot_cached - MIN_ADAPTIVE_CAP) * 1024;
+    assert!(allocator.fallback_free() >= free_before + returned);
+    allocator.assert_balanced();
+}
+
+#[test_case]
+fn fallback_usage_accounts_for_the_whole_heap() {
+    const SLOTS: usize = 200;
+    let allocator = new_allocator();
+    let mut blocks = [(ptr::null_mut(), Layout::new::<u8>()); SLOTS];
+    for (i, block) in blocks.iter_mut().enumerate() {
+        // 小块为主，每隔几个夹杂一个比所有类都大的块
+        let size = if i % 10 == 0 {
+            9000
+        } else {
+            1 + (i * 37) % 2000
+        };
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        *block = (unsafe { allocator.alloc(layout) }, layout);
+        assert!(!block.0.is_null());
+    }
+    for &(ptr, layout) in blocks.iter().step_by(3) {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    let stats = allocator.stats();
+    // 启用 heap-debug 时堆的末尾是记录每个块所属的类的表，不属于后备分配器
+    #[cfg(not(feature = "heap-debug"))]
+    assert_eq!(stats.fallback_size, ARENA_SIZE);
+    assert_eq!(
+        stats.fallback_used + stats.fallback_free,
+        stats.fallback_size
+    );
+    let large: usize = blocks
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i % 10 == 0 && i % 3 != 0)
+        .map(|(_, &(_, layout))| layout.size())
+        .sum();
+    // 启用 heap-slab 时 slab 的头部和结尾放不下一个块的部分不属于任何块
+    let accounted = stats.fallback_free + stats.cached_bytes() + stats.live_bytes() + large;
+    assert!(accounted <= stats.fallback_size);
+    assert!(stats.fallback_size - accounted < ARENA_SIZE / 50);
+    assert_eq!(allocator.fallback_free(), stats.fallback_free);
+}
+
+/// 模拟的当前 CPU 编号。
+static CPU: AtomicUsize = AtomicUsize::new(0);
+
+fn current_cpu() -> usize {
+    CPU.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn per_cpu_caches_handle_cross_cpu_frees() {
+    const ROUNDS: usize = 100;
+    const BLOCKS: usize = 16;
+    let allocator = PerCpuBlockAllocator::<2>::new(current_cpu);
+    unsafe { allocator.shared().lock().init(arena_start(), ARENA_SIZE) };
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    // 每一轮在一个 CPU 上分配，在另一个 CPU 上释放，两个方向交替
+    let mut blocks = [ptr::null_mut::<u8>(); BLOCKS];
+    for round in 0..2 * ROUNDS {
+        CPU.store(round % 2, Ordering::Relaxed);
+        for (i, block) in blocks.iter_mut().enumerate() {
+            *block = unsafe { allocator.alloc(layout) };
+            assert!(!block.is_null());
+            unsafe { ptr::write_bytes(*block, i as u8, layout.size()) };
+        }
+        CPU.store(1 - round % 2, Ordering::Relaxed);
+        for (i, &block) in blocks.iter().enumerate() {
+            // 没有两个块重叠
+            assert!(bytes(block, layout.size()).iter().all(|&b| b == i as u8));
+            unsafe { allocator.dealloc(block, layout) };
+        }
+    }
+
+    // 每批块只锁一次共享分配器
+    let operations = 2 * ROUNDS * 2 * BLOCKS;
+    assert!(allocator.shared_lock_count() * 4 < operations);
+    allocator.flush();
+    allocator.shared().assert_balanced();
+    CPU.store(0, Ordering::Relaxed);
+}
+
+#[test_case]
+fn per_cpu_null_frees_reach_the_shared_allocator() {
+    let allocator = PerCpuBlockAllocator::<2>::new(current_cpu);
+    unsafe { allocator.shared().lock().init(arena_start(), ARENA_SIZE) };
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe { allocator.dealloc(ptr::null_mut(), layout) };
+    assert_eq!(allocator.shared().lock().null_frees(), 1);
+
+    // 空指针没有进入当前 CPU 的列表，之后的分配都拿到真正的块
+    let blocks = [(); 4].map(|_| unsafe { allocator.alloc(layout) });
+    assert!(blocks.iter().all(|block| !block.is_null()));
+    for &block in blocks.iter() {
+        unsafe { allocator.dealloc(block, layout) };
+    }
+    allocator.flush();
+    allocator.shared().assert_balanced();
+}
+
+#[test_case]
+fn class_locks_serve_interleaved_sizes_from_their_own_lists() {
+    const ROUNDS: usize = 100;
+    const BLOCKS: usize = 16;
+    let allocator = ClassLockedBlockAllocator::new();
+    unsafe { allocator.shared().lock().init(arena_start(), ARENA_SIZE) };
+    let layouts = [16, 2048, 64, 512].map(|size| Layout::from_size_align(size, 8).unwrap());
+
+    // 每一轮交替分配四个类的块，再倒着释放
+    let mut blocks = [(ptr::null_mut::<u8>(), layouts[0]); BLOCKS];
+    for _ in 0..ROUNDS {
+        for (i, block) in blocks.iter_mut().enumerate() {
+            let layout = layouts[i % layouts.len()];
+            let ptr = unsafe { allocator.alloc(layout) };
+            assert!(!ptr.is_null());
+            unsafe { ptr::write_bytes(ptr, i as u8, layout.size()) };
+            *block = (ptr, layout);
+        }
+        for (i, &(ptr, layout)) in blocks.iter().enumerate().rev() {
+            // 没有两个块重叠
+            assert!(bytes(ptr, layout.size()).iter().all(|&b| b == i as u8));
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+    }
+
+    // 命中时只锁上块所属的类，共享分配器只在取出一批块时锁一次
+    let operations = ROUNDS * 2 * BLOCKS;
+    assert!(allocator.shared_lock_count() * 4 < operations);
+    allocator.flush();
+    allocator.shared().assert_balanced();
+}
+
+#[test_case]
+fn tiny_allocations_meet_the_minimum_alignment() {
+    const SLOTS: usize = 512;
+    let allocator = new_allocator();
+    let align = allocator.lock().min_alignment();
+    #[cfg(feature = "min-align-16")]
+    assert_eq!(align, 16);
+    #[cfg(not(feature = "min-align-16"))]
+    assert_eq!(align, 8);
+
+    // 1 到 8 字节、对齐 1 到 8 的请求轮流分配，一共 4096 次
+    let mut blocks = [(ptr::null_mut(), Layout::new::<u8>()); SLOTS];
+    for round in 0..8 {
+        for (i, block) in blocks.iter_mut().enumerate() {
+            let size = 1 + (i + round) % 8;
+            let layout = Layout::from_size_align(size, 1 << (i % 4)).unwrap();
+            let ptr = unsafe { allocator.alloc(layout) };
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % align, 0, "{:?}", layout);
+            *block = (ptr, layout);
+        }
+        for &(ptr, layout) in blocks.iter() {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+    }
+
+    // 交给后备分配器的大块也满足
+    let large = Layout::from_size_align(9000, 1).unwrap();
+    let ptr = unsafe { allocator.alloc(large) };
+    assert_eq!(ptr as usize % align, 0);
+    unsafe { allocator.dealloc(ptr, large) };
+    allocator.assert_balanced();
+}
+
+#[test_case]
+fn three_class_table_sends_larger_sizes_to_fallback() {
+    let allocator = Locked::new(FixedSizeBlockAllocator::with_block_sizes([16, 64, 256]));
+    unsafe { allocator.lock().init(arena_start(), ARENA_SIZE) };
+    let cases = [
+        (1, 16),
+        (16, 16),
+        (17, 64),
+        (200, 256),
+        (256, 256),
+        (257, 257),
+        (4096, 4096),
+    ];
+    for &(size, usable) in cases.iter() {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(allocator.usable_size(ptr, layout), usable);
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    let stats = allocator.stats();
+    assert_eq!(stats.classes.map(|class| class.block_size), [16, 64, 256]);
+    assert_eq!(stats.large_allocs, 2);
+    allocator.assert_balanced();
+}
+
+/// 一直到 16 KiB 的 12 个类。
+const DESKTOP_SIZES: [usize; 12] = [
+    1 << 3,
+    1 << 4,
+    1 << 5,
+    1 << 6,
+    1 << 7,
+    1 << 8,
+    1 << 9,
+    1 << 10,
+    1 << 11,
+    1 << 12,
+    1 << 13,
+    1 << 14,
+];
+
+#[test_case]
+fn twelve_class_table_serves_sixteen_kib_blocks() {
+    let allocator = Locked::new(FixedSizeBlockAllocator::with_block_sizes(DESKTOP_SIZES));
+    unsafe { allocator.lock().init(arena_start(), ARENA_SIZE) };
+    without_quarantine(&allocator);
+    for size in (1..=20_000).step_by(97) {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let required = size.max(MIN_ALIGN);
+        let expected = DESKTOP_SIZES
+            .iter()
+            .copied()
+            .find(|&block| block >= required);
+        let usable = allocator.usable_size(ptr::null_mut(), layout);
+        assert_eq!(usable, expected.unwrap_or(size));
+    }
+
+    // 16 KiB 的类按块大小对齐，释放后的块留在缓存中，下次直接复用
+    let layout = Layout::from_size_align(10_000, 8).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    assert!(!first.is_null());
+    assert_eq!(first as usize % (16 * 1024), 0);
+    unsafe { allocator.dealloc(first, layout) };
+    let second = unsafe { allocator.alloc(layout) };
+    assert_eq!(second, first);
+    unsafe { allocator.dealloc(second, layout) };
+
+    let stats = allocator.stats();
+    assert_eq!(stats.classes[11].hits, 1);
+    assert_eq!(stats.large_allocs, 0);
+    allocator.assert_balanced();
+}
+
+#[test_case]
+fn allocator_api_returns_whole_blocks() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(20, 4).unwrap();
+    let block = allocator.allocate(layout).unwrap();
+    assert_eq!(block.len(), 32);
+    unsafe { allocator.deallocate(block.cast(), layout) };
+
+    // 零大小的分配按对齐悬空，长度为零
+    let empty = Layout::from_size_align(0, 64).unwrap();
+    let block = allocator.allocate(empty).unwrap();
+    assert_eq!(block.len(), 0);
+    assert_eq!(block.cast::<u8>().as_ptr() as usize % 64, 0);
+    unsafe { allocator.deallocate(block.cast(), empty) };
+
+    // 在同一个类里调整大小不移动块，越过类的边界时移动并保留内容
+    let small = Layout::from_size_align(9, 8).unwrap();
+    let block = allocator.allocate_zeroed(small).unwrap();
+    assert!(unsafe { block.as_ref() }.iter().all(|&byte| byte == 0));
+    let ptr = block.cast::<u8>();
+    unsafe { ptr.as_ptr().write_bytes(0xAB, small.size()) };
+    let same = Layout::from_size_align(16, 16).unwrap();
+    let grown = unsafe { allocator.grow(ptr, small, same) }.unwrap();
+    assert_eq!(grown.cast::<u8>(), ptr);
+    let larger = Layout::from_size_align(100, 8).unwrap();
+    let moved = unsafe { allocator.grow_zeroed(ptr, same, larger) }.unwrap();
+    assert_eq!(moved.len(), 128);
+    let bytes = unsafe { moved.as_ref() };
+    assert!(bytes[..small.size()].iter().all(|&byte| byte == 0xAB));
+    assert!(bytes[same.size()..].iter().all(|&byte| byte == 0));
+    // `Locked` 自己的 `shrink` 是归还缓存的块，这里要用 `Allocator` 的
+    let shrunk = unsafe { Allocator::shrink(&allocator, moved.cast(), larger, small) }.unwrap();
+    assert_eq!(shrunk.len(), 16);
+    unsafe { allocator.deallocate(shrunk.cast(), small) };
+    allocator.assert_balanced();
+}
+
+#[test_case]
+fn vec_in_block_allocator_grows_across_classes() {
+    let allocator = new_allocator();
+    let mut vec: Vec<u8, _> = Vec::new_in(&allocator);
+    // 容量从 8 字节翻倍到 8192 字节，经过每一个类
+    for i in 0..5000 {
+        vec.push(i as u8);
+    }
+    assert!(vec.iter().enumerate().all(|(i, &byte)| byte == i as u8));
+    vec.truncate(100);
+    vec.shrink_to_fit();
+    assert!(vec.iter().enumerate().all(|(i, &byte)| byte == i as u8));
+
+    drop(vec);
+    allocator.assert_balanced();
+}
+
+/// 写入固定大小缓冲区的 `fmt::Write`，用来检查不分配内存的输出。
+struct BufWriter {
+    buf: [u8; 1024],
+    len: usize,
+}
+
+impl BufWriter {
+    fn new() -> Self {
+        BufWriter {
+            buf: [0; 1024],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap()
+    }
+}
+
+impl fmt::Write for BufWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buf.len() {
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[test_case]
+fn fallback_histogram_buckets_requested_sizes() {
+    let allocator = new_allocator();
+    let sizes = [24, 3000, 9000, 12_000, 16_384, 100_000];
+    for &size in sizes.iter() {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    let all = allocator.size_histogram();
+    assert_eq!(all.total(), sizes.len());
+    assert_eq!((all.counts[4], all.counts[11]), (1, 1));
+    // 只有比 8 KiB 大的请求交给后备分配器
+    let fallback = allocator.fallback_histogram();
+    assert_eq!(fallback.total(), 4);
+    assert_eq!(fallback.counts[13], 2);
+    assert_eq!(fallback.counts[14], 1);
+    assert_eq!(fallback.counts[16], 1);
+
+    let mut out = BufWriter::new();
+    fallback.dump(&mut out).unwrap();
+    let text = out.as_str();
+    serial_println!("{}", text);
+    assert_eq!(text.lines().count(), 3);
+    assert_eq!(
+        text.lines().next().map(str::trim),
+        Some("8192..=16383    bytes: 2")
+    );
+}
+
+#[cfg(feature = "heap-quarantine")]
+#[test_case]
+fn freed_blocks_wait_in_quarantine() {
+    let allocator = new_allocator();
+    allocator.lock().set_quarantine_depth(2);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let blocks = [(); 3].map(|_| unsafe { allocator.alloc(layout) });
+    unsafe {
+        allocator.dealloc(blocks[0], layout);
+        allocator.dealloc(blocks[1], layout);
+    }
+    assert_eq!(allocator.lock().quarantined_bytes(), 128);
+
+    // 隔离区没满，刚释放的块不会被重新分配
+    let other = unsafe { allocator.alloc(layout) };
+    assert!(!blocks[..2].contains(&other));
+
+    // 再释放一个块，最早释放的块离开隔离区，下一次分配就用到它
+    unsafe { allocator.dealloc(blocks[2], layout) };
+    assert_eq!(allocator.lock().quarantined_bytes(), 128);
+    assert_eq!(unsafe { allocator.alloc(layout) }, blocks[0]);
+
+    // drain 先清空隔离区
+    allocator.drain();
+    assert_eq!(allocator.lock().quarantined_bytes(), 0);
+}
+
+/// 页分配函数交出的页，测试中同时只有一次页分配。
+const PAGE_ARENA_SIZE: usize = 64 * 1024;
+#[repr(align(4096))]
+struct PageArena([u8; PAGE_ARENA_SIZE]);
+static mut PAGE_ARENA: PageArena = PageArena([0; PAGE_ARENA_SIZE]);
+static PAGES_TAKEN: AtomicUsize = AtomicUsize::new(0);
+static PAGES_FREED: AtomicUsize = AtomicUsize::new(0);
+
+fn page_arena_start() -> usize {
+    unsafe { ptr::addr_of_mut!(PAGE_ARENA.0) as usize }
+}
+
+fn take_pages(count: usize) -> Option<usize> {
+    if count * PAGE_SIZE > PAGE_ARENA_SIZE {
+        return None;
+    }
+    PAGES_TAKEN.fetch_add(count, Ordering::Relaxed);
+    Some(page_arena_start())
+}
+
+fn return_pages(addr: usize, count: usize) {
+    assert_eq!(addr, page_arena_start());
+    PAGES_FREED.fetch_add(count, Ordering::Relaxed);
+}
+
+#[test_case]
+fn large_buffers_come_from_the_page_hook() {
+    let allocator = new_allocator();
+    let pages = PageAllocator {
+        alloc_pages: take_pages,
+        free_pages: return_pages,
+    };
+    allocator.lock().set_page_allocator(pages, 32 * 1024);
+    let used = allocator.fallback_used();
+
+    let buffer: Vec<u8, _> = Vec::with_capacity_in(64 * 1024, &allocator);
+    assert_eq!(buffer.as_ptr() as usize, page_arena_start());
+    assert_eq!(PAGES_TAKEN.load(Ordering::Relaxed), 16);
+    assert_eq!(allocator.lock().pages_in_use(), 16);
+    assert_eq!(allocator.fallback_used(), used);
+    drop(buffer);
+    assert_eq!(PAGES_FREED.load(Ordering::Relaxed), 16);
+    assert_eq!(allocator.lock().pages_in_use(), 0);
+
+    // 低于阈值的大块仍然来自后备堆
+    let layout = Layout::from_size_align(16 * 1024, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(allocator.fallback_used() > used);
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(PAGES_TAKEN.load(Ordering::Relaxed), 16);
+    allocator.assert_balanced();
+}
+
+// 启用 min-align-16 时 8 字节的请求落在 16 字节的类里
+#[cfg(not(feature = "min-align-16"))]
+#[test_case]
+fn waste_counts_bytes_beyond_each_request() {
+    let allocator = new_allocator();
+    let small = Layout::from_size_align(100, 8).unwrap();
+    let medium = Layout::from_size_align(200, 8).unwrap();
+    let exact = Layout::from_size_align(8, 8).unwrap();
+    let smalls = [(); 3].map(|_| unsafe { allocator.alloc(small) });
+    let m = unsafe { allocator.alloc(medium) };
+    let e = unsafe { allocator.alloc(exact) };
+    unsafe { allocator.dealloc(smalls[0], small) };
+
+    // 三个 100 字节的请求各浪费 28 字节，释放了一个；200 字节的请求浪费 56 字节
+    let waste = allocator.lock().waste();
+    assert_eq!(
+        waste[4],
+        ClassWaste {
+            block_size: 128,
+            allocations: 3,
+            wasted: 84,
+            live: 2,
+            live_wasted: 56,
+        }
+    );
+    assert_eq!(waste[4].wasted_permille(), Some(218));
+    assert_eq!(waste[4].live_wasted_permille(), Some(218));
+    assert_eq!((waste[5].wasted, waste[5].live_wasted), (56, 56));
+    assert_eq!((waste[0].allocations, waste[0].wasted), (1, 0));
+    assert_eq!(waste[1].wasted_permille(), None);
+    assert_eq!(allocator.internal_fragmentation_bytes(), 112);
+
+    // 在同一个类里调整大小不移动块，只重新计算浪费的字节
+    let grown = unsafe { allocator.realloc(m, medium, 250) };
+    assert_eq!(grown, m);
+    assert_eq!(allocator.internal_fragmentation_bytes(), 62);
+
+    let mut out = BufWriter::new();
+    allocator.lock().dump_waste(&mut out).unwrap();
+    let text = out.as_str();
+    serial_println!("{}", text);
+    assert_eq!(text.lines().count(), 3);
+    assert_eq!(
+        text.lines().nth(1).map(str::trim),
+        Some("128-byte class: 3 allocations wasted 84 bytes (21.8%), 2 live blocks waste 56 bytes (21.8%)")
+    );
+
+    unsafe {
+        allocator.dealloc(smalls[1], small);
+        allocator.dealloc(smalls[2], small);
+        allocator.dealloc(grown, Layout::from_size_align(250, 8).unwrap());
+        allocator.dealloc(e, exact);
+    }
+    assert_eq!(allocator.internal_fragmentation_bytes(), 0);
+    assert_eq!(allocator.lock().waste()[4].wasted, 84);
+}
+
+#[test_case]
+fn null_frees_are_ignored_and_counted() {
+    let allocator = new_allocator();
+    let small = Layout::from_size_align(64, 8).unwrap();
+    let large = Layout::from_size_align(9000, 8).unwrap();
+    let before = allocator.stats();
+    unsafe {
+        allocator.dealloc(ptr::null_mut(), small);
+        allocator.dealloc(ptr::null_mut(), large);
+    }
+    assert_eq!(allocator.lock().null_frees(), 2);
+    assert_eq!(allocator.stats(), before);
+
+    // 堆内的大块照常释放
+    let ptr = unsafe { allocator.alloc(large) };
+    unsafe { allocator.dealloc(ptr, large) };
+    assert_eq!(allocator.fallback_used(), before.fallback_used);
+    allocator.assert_balanced();
+}
+
+// 启用 external-fallback 时只能在后备堆的结尾扩展，而启用 heap-debug 时那里是类的记录表
+#[cfg(not(all(feature = "external-fallback", feature = "heap-debug")))]
+#[test_case]
+fn extend_adds_memory_to_the_fallback_heap() {
+    let half = ARENA_SIZE / 2;
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { allocator.lock().init(arena_start(), half) };
+    without_quarantine(&allocator);
+    let layout = Layout::from_size_align(half / 4 * 3, 8).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    assert!(!first.is_null());
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+
+    let size = allocator.fallback_size();
+    unsafe { allocator.lock().extend(arena_start() + half, half) };
+    assert_eq!(allocator.fallback_size(), size + half);
+    let second = unsafe { allocator.alloc(layout) };
+    assert!(!second.is_null());
+    unsafe { second.write_bytes(0x5a, layout.size()) };
+    unsafe { allocator.dealloc(second, layout) };
+    unsafe { allocator.dealloc(first, layout) };
+    assert_eq!(allocator.fallback_used(), 0);
+}
+
+#[cfg(not(all(feature = "external-fallback", feature = "heap-debug")))]
+#[test_case]
+fn extend_by_a_mib_makes_a_large_allocation_fit() {
+    const MIB: usize = 1024 * 1024;
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    let start = unsafe { ptr::addr_of_mut!(TRIM_ARENA.0) as usize };
+    unsafe { allocator.lock().init(start, MIB / 2) };
+    without_quarantine(&allocator);
+    let layout = Layout::from_size_align(MIB / 4 * 3, 8).unwrap();
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+
+    unsafe { allocator.extend(start + MIB / 2, MIB) };
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(!ptr.is_null());
+    unsafe { ptr.write_bytes(0x5a, layout.size()) };
+    unsafe { allocator.dealloc(ptr, layout) };
+    allocator.assert_balanced();
+}
@@ -0,0 +1,78 @@
+// in tests/fixed_size_gap_free.rs
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+    allocator::{fixed_size_block::FixedSizeBlockAllocator, Locked},
+    exit_qemu, serial_print, serial_println, QemuExitCode,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt::{self, Write},
+    panic::PanicInfo,
+    ptr,
+};
+
+/// 堆和 `extend` 加入的内存各占四页，中间隔着四页不属于堆的内存。
+const PART_SIZE: usize = 4 * 4096;
+const ARENA_SIZE: usize = 3 * PART_SIZE;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    gap_free();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn gap_free() {
+    serial_print!("fixed_size_gap_free::gap_free...\t");
+
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    let arena = unsafe { ptr::addr_of_mut!(ARENA.0) as usize };
+    unsafe {
+        allocator.lock().init(arena, PART_SIZE);
+        allocator.extend(arena + 2 * PART_SIZE, PART_SIZE);
+    }
+    let layout = Layout::from_size_align(9000, 8).unwrap();
+    let _guard = unsafe { allocator.alloc(layout) };
+    // 两段堆之间的指针也不应当交给后备分配器
+    unsafe { allocator.dealloc((arena + PART_SIZE) as *mut u8, layout) };
+}
+
+/// 保存 panic 消息的开头，用来检查是不是范围检查发现了它。
+struct Message {
+    buf: [u8; 128],
+    len: usize,
+}
+
+impl fmt::Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = Message {
+        buf: [0; 128],
+        len: 0,
+    };
+    let _ = write!(message, "{}", info.message());
+    if message.buf[..message.len].starts_with(b"dealloc of") {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
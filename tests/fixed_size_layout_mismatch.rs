@@ -0,0 +1,46 @@
+// in tests/fixed_size_layout_mismatch.rs
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc};
+use blog_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use core::{alloc::Layout, panic::PanicInfo};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use blog_os::allocator;
+    use blog_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    mismatched_free();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn mismatched_free() {
+    serial_print!("fixed_size_layout_mismatch::mismatched_free...\t");
+
+    // 200 字节落在 256 字节的类中，用 100 字节的布局释放会把它放进 128 字节的类
+    let ptr = unsafe { alloc(Layout::from_size_align(200, 8).unwrap()) };
+    assert!(!ptr.is_null());
+    unsafe { dealloc(ptr, Layout::from_size_align(100, 8).unwrap()) };
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
@@ -0,0 +1,59 @@
+// in tests/fixed_size_magic.rs
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+    allocator::{fixed_size_block::FixedSizeBlockAllocator, Locked},
+    exit_qemu, serial_print, serial_println, QemuExitCode,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    panic::PanicInfo,
+    ptr,
+};
+
+/// 启用 heap-slab 时要放得下一个 slab，启用 heap-debug 时堆的末尾还有记录每个块所属的类的表。
+const ARENA_SIZE: usize = 2 * 4096;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    scribbled_block();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn scribbled_block() {
+    serial_print!("fixed_size_magic::scribbled_block...\t");
+
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+    // 启用 heap-quarantine 时释放的块不会马上被重新分配
+    #[cfg(feature = "heap-quarantine")]
+    allocator.lock().set_quarantine_depth(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let dangling = unsafe { allocator.alloc(layout) };
+    let _guard = unsafe { allocator.alloc(layout) };
+    unsafe {
+        allocator.dealloc(dangling, layout);
+        // 改写缓存着的块开头，连同 ListNode 的指针和 magic 一起写坏；
+        // 再次分配时应当在这个块上停下，而不是把被改写的 next 当作表头
+        dangling.write_bytes(0x42, 16);
+        allocator.alloc(layout);
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
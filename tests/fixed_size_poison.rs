@@ -0,0 +1,56 @@
+// in tests/fixed_size_poison.rs
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+    allocator::{fixed_size_block::FixedSizeBlockAllocator, Locked},
+    exit_qemu, serial_print, serial_println, QemuExitCode,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    panic::PanicInfo,
+    ptr,
+};
+
+/// 启用 heap-slab 时要放得下一个 slab，启用 heap-debug 时堆的末尾还有记录每个块所属的类的表。
+const ARENA_SIZE: usize = 2 * 4096;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    use_after_free();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn use_after_free() {
+    serial_print!("fixed_size_poison::use_after_free...\t");
+
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+    // 启用 heap-quarantine 时释放的块不会马上被重新分配
+    #[cfg(feature = "heap-quarantine")]
+    allocator.lock().set_quarantine_depth(0);
+    let layout = Layout::new::<[u64; 8]>();
+    let dangling = unsafe { &mut *(allocator.alloc(layout) as *mut [u64; 8]) };
+    let _guard = unsafe { allocator.alloc(layout) };
+    unsafe { allocator.dealloc(dangling.as_mut_ptr() as *mut u8, layout) };
+    // 通过悬垂的引用写到 ListNode 之后，下一次分配这个类时应当发现
+    dangling[3] = 42;
+    unsafe { allocator.alloc(layout) };
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
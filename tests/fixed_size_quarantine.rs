@@ -0,0 +1,61 @@
+// in tests/fixed_size_quarantine.rs
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+    allocator::{
+        fixed_size_block::{FixedSizeBlockAllocator, DEFAULT_QUARANTINE_DEPTH},
+        Locked,
+    },
+    exit_qemu, serial_print, serial_println, QemuExitCode,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    panic::PanicInfo,
+    ptr,
+};
+
+/// 启用 heap-slab 时要放得下一个 slab，启用 heap-debug 时堆的末尾还有记录每个块所属的类的表。
+const ARENA_SIZE: usize = 2 * 4096;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    stale_write_ages_out();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn stale_write_ages_out() {
+    serial_print!("fixed_size_quarantine::stale_write_ages_out...\t");
+
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+    let layout = Layout::new::<[u64; 8]>();
+    let dangling = unsafe { &mut *(allocator.alloc(layout) as *mut [u64; 8]) };
+    let others = [(); DEFAULT_QUARANTINE_DEPTH].map(|_| unsafe { allocator.alloc(layout) });
+    unsafe { allocator.dealloc(dangling.as_mut_ptr() as *mut u8, layout) };
+    // 块还在隔离区中，同一个类的分配不会用到它，悬垂的写入不会落到别人的数据上
+    let reused = unsafe { allocator.alloc(layout) };
+    assert_ne!(reused, dangling.as_mut_ptr() as *mut u8);
+    dangling[3] = 42;
+    // 再释放 `DEFAULT_QUARANTINE_DEPTH` 个块把它挤出隔离区，这时应当发现填充被改写
+    for ptr in others {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
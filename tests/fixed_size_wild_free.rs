@@ -0,0 +1,50 @@
+// in tests/fixed_size_wild_free.rs
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+    allocator::{fixed_size_block::FixedSizeBlockAllocator, Locked},
+    exit_qemu, serial_print, serial_println, QemuExitCode,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    panic::PanicInfo,
+    ptr,
+};
+
+/// 放得下一个比所有类都大的块。
+const ARENA_SIZE: usize = 4 * 4096;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    wild_free();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn wild_free() {
+    serial_print!("fixed_size_wild_free::wild_free...\t");
+
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+    let layout = Layout::from_size_align(9000, 8).unwrap();
+    let _guard = unsafe { allocator.alloc(layout) };
+    // 远在堆外的指针不应当交给后备分配器
+    unsafe { allocator.dealloc(0xdead_0000 as *mut u8, layout) };
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
@@ -8,11 +8,17 @@
 
 extern crate alloc;
 
-use alloc::{boxed::Box, vec::Vec};
-use blog_os::allocator::HEAP_SIZE;
+use blog_os::allocator::{self, AllocatorKind};
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 
+// 同样的测试在 heap_allocation_bump.rs 和 heap_allocation_linked_list.rs 中用另外两个分配器运行
+#[path = "heap_allocation/cases.rs"]
+mod cases;
+
+/// 这个测试内核的主堆使用的分配器。
+const KIND: AllocatorKind = AllocatorKind::FixedSizeBlock;
+
 entry_point!(main);
 
 #[panic_handler]
@@ -20,7 +26,6 @@ fn panic(info: &PanicInfo) -> ! {
     blog_os::test_panic_handler(info)
 }
 fn main(boot_info: &'static BootInfo) -> ! {
-    use blog_os::allocator;
     use blog_os::memory::{self, BootInfoFrameAllocator};
     use x86_64::VirtAddr;
 
@@ -28,31 +33,9 @@ fn main(boot_info: &'static BootInfo) -> ! {
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    allocator::init_heap_with(&mut mapper, &mut frame_allocator, KIND)
+        .expect("heap initialization failed");
 
     test_main();
     loop {}
 }
-#[test_case]
-fn simple_allocation() {
-    let heap_value_1 = Box::new(41);
-    let heap_value_2 = Box::new(13);
-    assert_eq!(*heap_value_1, 41);
-    assert_eq!(*heap_value_2, 13);
-}
-#[test_case]
-fn large_vec() {
-    let n = 1000;
-    let mut vec = Vec::new();
-    for i in 0..n {
-        vec.push(i);
-    }
-    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
-}
-#[test_case]
-fn many_boxes() {
-    for i in 0..HEAP_SIZE {
-        let x = Box::new(i);
-        assert_eq!(*x, i);
-    }
-}
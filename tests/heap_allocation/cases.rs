@@ -0,0 +1,87 @@
+//! 三个 heap_allocation 测试内核共用的测试，`super::KIND` 是主堆使用的分配器。
+
+use super::KIND;
+use alloc::{boxed::Box, vec::Vec};
+use blog_os::allocator::{self, AllocatorKind, KernelStats, HEAP_SIZE};
+
+#[test_case]
+fn simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+    drop((heap_value_1, heap_value_2));
+    allocator::assert_balanced();
+}
+#[test_case]
+fn large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+    drop(vec);
+    allocator::assert_balanced();
+}
+#[test_case]
+fn many_boxes() {
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    allocator::assert_balanced();
+}
+#[test_case]
+fn selected_allocator_serves_the_heap() {
+    assert_eq!(allocator::heap_allocator_kind(), KIND);
+    let value = Box::new([7u8; 64]);
+    match allocator::heap_stats() {
+        KernelStats::Bump { allocations, .. } if KIND == AllocatorKind::Bump => {
+            assert_eq!(allocations, 1)
+        }
+        KernelStats::LinkedList(stats) if KIND == AllocatorKind::LinkedList => {
+            assert_eq!(stats.allocations, 1)
+        }
+        KernelStats::FixedSizeBlock(stats) if KIND == AllocatorKind::FixedSizeBlock => {
+            assert_eq!(
+                stats.classes.iter().map(|class| class.live).sum::<usize>(),
+                1
+            )
+        }
+        stats => panic!("{:?} heap reported {:?}", KIND, stats),
+    }
+    drop(value);
+    allocator::assert_balanced();
+}
+#[test_case]
+fn realloc_keeps_contents() {
+    let mut vec: Vec<usize> = (0..100).collect();
+    vec.reserve(2000);
+    assert!(vec.iter().copied().eq(0..100));
+    vec.truncate(10);
+    vec.shrink_to_fit();
+    assert!(vec.iter().copied().eq(0..10));
+    drop(vec);
+    allocator::assert_balanced();
+}
+#[test_case]
+fn heap_can_be_dumped() {
+    allocator::dump_heap();
+}
+#[test_case]
+fn alloc_zeroed_clears_reused_memory() {
+    use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    for _ in 0..4 {
+        let ptr = unsafe { alloc_zeroed(layout) };
+        assert!(!ptr.is_null());
+        let bytes = unsafe { core::slice::from_raw_parts_mut(ptr, layout.size()) };
+        assert!(bytes.iter().all(|&byte| byte == 0));
+        // 弄脏以后释放，下一轮很可能分到同一块内存
+        bytes.fill(0xAA);
+        unsafe { dealloc(ptr, layout) };
+    }
+    allocator::assert_balanced();
+}
@@ -0,0 +1,41 @@
+// 在 tests/heap_allocation_bump.rs 中
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use blog_os::allocator::{self, AllocatorKind};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+// 和 heap_allocation.rs 相同的测试
+#[path = "heap_allocation/cases.rs"]
+mod cases;
+
+/// 这个测试内核的主堆使用的分配器。
+const KIND: AllocatorKind = AllocatorKind::Bump;
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+fn main(boot_info: &'static BootInfo) -> ! {
+    use blog_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap_with(&mut mapper, &mut frame_allocator, KIND)
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
@@ -0,0 +1,52 @@
+// in tests/heap_double_free.rs
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+    allocator::{linked_list::LinkedListAllocator, Locked},
+    exit_qemu, serial_print, serial_println, QemuExitCode,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    panic::PanicInfo,
+    ptr,
+};
+
+const ARENA_SIZE: usize = 4096;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    double_free();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn double_free() {
+    serial_print!("heap_double_free::double_free...\t");
+
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+    let layout = Layout::new::<u64>();
+    let first = unsafe { allocator.alloc(layout) };
+    let _second = unsafe { allocator.alloc(layout) };
+    unsafe {
+        allocator.dealloc(first, layout);
+        allocator.dealloc(first, layout);
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
@@ -0,0 +1,154 @@
+// 在 tests/heap_interrupt.rs 中
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use blog_os::{
+    allocator::{self, fixed_size_block::ClassLockedBlockAllocator, Locked, LockedGuard},
+    interrupts,
+};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    panic::PanicInfo,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+fn main(boot_info: &'static BootInfo) -> ! {
+    use blog_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+/// 在时钟中断中完成的分配次数。
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+fn allocate_in_interrupt() {
+    let boxed = Box::new([TICKS.load(Ordering::Relaxed); 4]);
+    drop(boxed);
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn timer_interrupt_allocates_while_the_main_loop_allocates() {
+    interrupts::set_timer_hook(allocate_in_interrupt);
+    // 时钟中断大约每 55 毫秒一次，主循环一直在分配和释放，中断多半落在持有堆的锁的时候
+    let mut rounds = 0;
+    while TICKS.load(Ordering::Relaxed) < 20 {
+        let values: Vec<usize> = (0..64).collect();
+        assert_eq!(values.iter().sum::<usize>(), 63 * 64 / 2);
+        rounds += 1;
+    }
+    assert!(rounds > 0);
+}
+
+/// 假装的当前 CPU 的编号。
+static CPU: AtomicUsize = AtomicUsize::new(0);
+
+fn current_cpu() -> usize {
+    CPU.load(Ordering::Relaxed)
+}
+
+/// 由“另一个 CPU”持有的锁，时钟中断过几次之后放开它。
+static OTHER: Locked<usize> = Locked::new(0);
+static mut OTHER_GUARD: Option<LockedGuard<'static, usize>> = None;
+static RELEASE_TICKS: AtomicUsize = AtomicUsize::new(0);
+static RELEASED: AtomicBool = AtomicBool::new(false);
+
+fn release_other_after_two_ticks() {
+    if RELEASE_TICKS.fetch_add(1, Ordering::Relaxed) == 2 {
+        drop(unsafe { (*ptr::addr_of_mut!(OTHER_GUARD)).take() });
+        RELEASED.store(true, Ordering::Relaxed);
+    }
+}
+
+fn no_hook() {}
+
+#[test_case]
+fn lock_held_by_another_cpu_is_waited_for() {
+    interrupts::set_timer_hook(no_hook);
+    allocator::set_current_cpu(current_cpu);
+    CPU.store(1, Ordering::Relaxed);
+    let guard = OTHER.lock();
+    unsafe { ptr::addr_of_mut!(OTHER_GUARD).write(Some(guard)) };
+    CPU.store(0, Ordering::Relaxed);
+    // 两次时钟中断远远超过 LOCK_SPIN_LIMIT 次自旋，锁不是这个 CPU 持有的，应当一直等下去
+    interrupts::set_timer_hook(release_other_after_two_ticks);
+    *OTHER.lock() += 1;
+    interrupts::set_timer_hook(no_hook);
+    assert!(RELEASED.load(Ordering::Relaxed));
+    assert_eq!(*OTHER.lock(), 1);
+}
+
+const CLASS_ARENA_SIZE: usize = 16 * 4096;
+#[repr(align(4096))]
+struct ClassArena([u8; CLASS_ARENA_SIZE]);
+static mut CLASS_ARENA: ClassArena = ClassArena([0; CLASS_ARENA_SIZE]);
+
+/// 每个类各有一把锁的前端，时钟中断和主循环都从它分配。
+static CLASS_HEAP: ClassLockedBlockAllocator = ClassLockedBlockAllocator::new();
+/// 在时钟中断中从 `CLASS_HEAP` 完成的分配次数。
+static CLASS_TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// 一个小块、一个较大的块和一个比所有类都大的块。
+fn class_layouts() -> [Layout; 3] {
+    [
+        Layout::from_size_align(24, 8).unwrap(),
+        Layout::from_size_align(400, 8).unwrap(),
+        Layout::from_size_align(5000, 8).unwrap(),
+    ]
+}
+
+fn allocate_from_class_heap_in_interrupt() {
+    for layout in class_layouts() {
+        let ptr = unsafe { CLASS_HEAP.alloc(layout) };
+        assert!(!ptr.is_null());
+        unsafe { CLASS_HEAP.dealloc(ptr, layout) };
+    }
+    CLASS_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn timer_interrupt_allocates_from_the_class_locked_front_end() {
+    unsafe {
+        CLASS_HEAP
+            .shared()
+            .lock()
+            .init(ptr::addr_of_mut!(CLASS_ARENA.0) as usize, CLASS_ARENA_SIZE)
+    };
+    interrupts::set_timer_hook(allocate_from_class_heap_in_interrupt);
+    // 主循环一直在分配和释放，锁不关中断的话，中断多半落在持有某个类或者共享分配器的锁的时候
+    let mut rounds = 0;
+    while CLASS_TICKS.load(Ordering::Relaxed) < 20 {
+        for layout in class_layouts() {
+            let ptr = unsafe { CLASS_HEAP.alloc(layout) };
+            assert!(!ptr.is_null());
+            unsafe { CLASS_HEAP.dealloc(ptr, layout) };
+        }
+        rounds += 1;
+    }
+    interrupts::set_timer_hook(no_hook);
+    assert!(rounds > 0);
+    CLASS_HEAP.flush();
+}
@@ -0,0 +1,55 @@
+// in tests/heap_magic.rs
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+    allocator::{linked_list::LinkedListAllocator, Locked},
+    exit_qemu, serial_print, serial_println, QemuExitCode,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    panic::PanicInfo,
+    ptr,
+};
+
+const ARENA_SIZE: usize = 4096;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    scribbled_node();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn scribbled_node() {
+    serial_print!("heap_magic::scribbled_node...\t");
+
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let dangling = unsafe { allocator.alloc(layout) };
+    let _guard = unsafe { allocator.alloc(layout) };
+    unsafe {
+        allocator.dealloc(dangling, layout);
+        // 改写释放后的块开头，连同 ListNode 的指针和 magic 一起写坏；
+        // 再次分配时应当在这个节点上停下，而不是跟随被改写的 next
+        dangling.write_bytes(0x42, 32);
+        allocator.alloc(layout);
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
@@ -0,0 +1,54 @@
+// in tests/heap_poison.rs
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+    allocator::{linked_list::LinkedListAllocator, Locked},
+    exit_qemu, serial_print, serial_println, QemuExitCode,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    panic::PanicInfo,
+    ptr,
+};
+
+const ARENA_SIZE: usize = 4096;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    use_after_free();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn use_after_free() {
+    serial_print!("heap_poison::use_after_free...\t");
+
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let dangling = unsafe { allocator.alloc(layout) };
+    let _guard = unsafe { allocator.alloc(layout) };
+    unsafe {
+        allocator.dealloc(dangling, layout);
+        // 写到 ListNode 之后的字节，再次分配同一块时应当发现
+        dangling.add(32).write(0x42);
+        allocator.alloc(layout);
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
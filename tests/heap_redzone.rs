@@ -0,0 +1,64 @@
+// in tests/heap_redzone.rs
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+    allocator::{linked_list::LinkedListAllocator, Locked},
+    exit_qemu, serial_print, serial_println, QemuExitCode,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    panic::PanicInfo,
+    ptr,
+};
+
+const ARENA_SIZE: usize = 4096;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+
+    clean_allocation(&allocator);
+    off_by_one(&allocator);
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn clean_allocation(allocator: &Locked<LinkedListAllocator>) {
+    serial_print!("heap_redzone::clean_allocation...\t");
+    let layout = Layout::new::<[u8; 10]>();
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        ptr.write_bytes(0xff, layout.size());
+        allocator.dealloc(ptr, layout);
+    }
+    serial_println!("[ok]");
+}
+
+/// 和 `Box<[u8; 10]>` 一样的布局，写到数组之后的第一个字节。
+fn off_by_one(allocator: &Locked<LinkedListAllocator>) {
+    serial_print!("heap_redzone::off_by_one...\t");
+    let layout = Layout::new::<[u8; 10]>();
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        ptr.add(layout.size()).write(0);
+        allocator.dealloc(ptr, layout);
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
@@ -0,0 +1,85 @@
+// 在 tests/heap_reentrant.rs 中
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+    allocator::{linked_list::LinkedListAllocator, Locked},
+    exit_qemu, interrupts, serial_print, serial_println, QemuExitCode,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt::{self, Write},
+    panic::PanicInfo,
+    ptr,
+};
+
+const ARENA_SIZE: usize = 4096;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+/// 故意不关中断的堆，时钟中断在主循环持有它的锁时分配。
+static HEAP: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
+
+fn allocate_in_interrupt() {
+    let layout = Layout::new::<u64>();
+    unsafe { HEAP.dealloc(HEAP.alloc(layout), layout) };
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    blog_os::init();
+    reentrant_allocation();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+fn reentrant_allocation() {
+    serial_print!("heap_reentrant::reentrant_allocation...\t");
+
+    unsafe {
+        HEAP.lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+    interrupts::set_timer_hook(allocate_in_interrupt);
+    // 持有锁等待下一次时钟中断，中断处理程序中的分配应当 panic 而不是一直自旋
+    let _guard = HEAP.lock();
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// 保存 panic 消息的开头，用来检查 panic 的原因。
+struct Message {
+    buf: [u8; 128],
+    len: usize,
+}
+
+impl fmt::Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = Message {
+        buf: [0; 128],
+        len: 0,
+    };
+    let _ = write!(message, "{}", info.message());
+    if message.buf[..message.len].starts_with(b"reentrant heap allocation from interrupt context") {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
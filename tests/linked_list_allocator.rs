@@ -0,0 +1,1684 @@
+// 在 tests/linked_list_allocator.rs 中
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+#![feature(allocator_api)]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec, vec::Vec};
+#[cfg(not(feature = "heap-verify"))]
+use blog_os::allocator::linked_list::AccountingMismatch;
+use blog_os::{
+    allocator::{
+        linked_list::{
+            CorruptionReason, ExtendError, HeapCheck, HeapCorruption, HeapStats,
+            LinkedListAllocator, Policy, FOOTER_SIZE, HEADER_SIZE, PAGE_SIZE,
+        },
+        Locked,
+    },
+    serial_println,
+};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    alloc::{Allocator, GlobalAlloc, Layout},
+    fmt::{self, Write},
+    mem::{self, MaybeUninit},
+    panic::PanicInfo,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+fn main(boot_info: &'static BootInfo) -> ! {
+    use blog_os::allocator;
+    use blog_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+/// 测试用的独立堆，每个测试都在它上面新建一个分配器。
+const ARENA_SIZE: usize = 128 * 1024;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+/// 每个块的头部标记加脚标。
+const TAGS: usize = HEADER_SIZE + FOOTER_SIZE;
+
+fn arena_start() -> usize {
+    unsafe { ptr::addr_of_mut!(ARENA.0) as usize }
+}
+
+fn new_allocator() -> Locked<LinkedListAllocator> {
+    allocator_with_size(ARENA_SIZE)
+}
+
+fn allocator_with_size(size: usize) -> Locked<LinkedListAllocator> {
+    assert!(size <= ARENA_SIZE);
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(arena_start(), size) };
+    allocator
+}
+
+/// 先清零整个 ARENA，再用 `init_zeroed` 初始化分配器。
+fn zeroed_allocator() -> Locked<LinkedListAllocator> {
+    unsafe { ptr::write_bytes(arena_start() as *mut u8, 0, ARENA_SIZE) };
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init_zeroed(arena_start(), ARENA_SIZE) };
+    allocator
+}
+
+fn assert_zeroed(ptr: *mut u8, size: usize) {
+    assert!(!ptr.is_null());
+    let bytes = unsafe { core::slice::from_raw_parts(ptr, size) };
+    assert!(bytes.iter().all(|&byte| byte == 0));
+}
+
+/// 构造空闲区域依次为 64、4096、128 字节的堆，区域之间用已分配的 32 字节块隔开。
+///
+/// 按地址从高到低释放，新释放的区域在表头：64 和 128 字节的区域在同一个分箱里，
+/// 64 在前；4096 字节的区域在更大的分箱。
+/// 返回三个空闲区域的起始地址。
+fn allocator_with_regions(policy: Policy) -> (Locked<LinkedListAllocator>, [usize; 3]) {
+    let sizes = [64, 48, 4096, 48, 128, 48];
+    let allocator = allocator_with_size(sizes.iter().sum());
+    allocator.lock().set_policy(policy);
+    let mut blocks = [0; 6];
+    for (block, &size) in blocks.iter_mut().zip(sizes.iter()) {
+        let layout = Layout::from_size_align(size - TAGS, 8).unwrap();
+        *block = unsafe { allocator.alloc(layout) } as usize;
+        assert_ne!(*block, 0);
+    }
+    for i in [4, 2, 0] {
+        let layout = Layout::from_size_align(sizes[i] - TAGS, 8).unwrap();
+        unsafe { allocator.dealloc(blocks[i] as *mut u8, layout) };
+    }
+    let region = |i: usize| blocks[i] - HEADER_SIZE;
+    (allocator, [region(0), region(2), region(4)])
+}
+
+#[test_case]
+fn coalesce_after_fragmentation() {
+    let allocator = new_allocator();
+    // 加上边界标记正好占 64 字节
+    let layout = Layout::from_size_align(64 - TAGS, 8).unwrap();
+    let mut blocks = [ptr::null_mut(); ARENA_SIZE / 64];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+        assert!(!block.is_null());
+    }
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+
+    // 先释放偶数块再释放奇数块，最大程度打乱释放顺序
+    for block in blocks
+        .iter()
+        .step_by(2)
+        .chain(blocks.iter().skip(1).step_by(2))
+    {
+        unsafe { allocator.dealloc(*block, layout) };
+    }
+
+    let full = Layout::from_size_align(ARENA_SIZE - TAGS, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(full) };
+    assert_eq!(ptr as usize, arena_start() + HEADER_SIZE);
+}
+
+#[test_case]
+fn repeated_page_sized_churn() {
+    let allocator = new_allocator();
+    let layout = Layout::new::<[u8; 4096]>();
+    for _ in 0..1_000_000 {
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    let full = Layout::from_size_align(ARENA_SIZE - TAGS, 8).unwrap();
+    assert!(!unsafe { allocator.alloc(full) }.is_null());
+}
+
+// 树按大小和地址排序，不保留释放顺序
+#[cfg(not(feature = "heap-tree"))]
+#[test_case]
+fn freed_regions_reused_most_recent_first() {
+    const N: usize = 16;
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut blocks = [ptr::null_mut(); 2 * N];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+    }
+
+    // 以打乱的顺序释放偶数块，奇数块保持占用，使这些空闲区域互不相邻
+    for i in 0..N {
+        let index = 2 * (i * 7 % N);
+        unsafe { allocator.dealloc(blocks[index], layout) };
+    }
+
+    // 新释放的区域插在表头，first-fit 按相反的释放顺序取回每个区域
+    for i in (0..N).rev() {
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert_eq!(ptr, blocks[2 * (i * 7 % N)]);
+    }
+}
+
+#[test_case]
+fn free_list_sorted_after_shuffled_frees() {
+    const N: usize = 16;
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut blocks = [ptr::null_mut(); 2 * N];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+    }
+    for i in 0..N {
+        let index = 2 * (i * 7 % N);
+        unsafe { allocator.dealloc(blocks[index], layout) };
+    }
+
+    // 释放时不再按地址插入，整理之后同一个分箱里的区域才按地址排列
+    assert_eq!(allocator.lock().defragment(), 0);
+    let allocator = allocator.lock();
+    let mut starts = allocator.free_regions().map(|(start, _)| start).take(N);
+    let mut previous = starts.next().unwrap();
+    for start in starts {
+        assert!(start > previous);
+        previous = start;
+    }
+}
+
+/// 构造同一个分箱里依次为 192、128 字节的两个空闲区域，返回它们的起始地址。
+fn allocator_with_same_bin_regions(policy: Policy) -> (Locked<LinkedListAllocator>, [usize; 2]) {
+    let sizes = [128, 48, 192, 48];
+    let allocator = allocator_with_size(sizes.iter().sum());
+    allocator.lock().set_policy(policy);
+    let mut blocks = [0; 4];
+    for (block, &size) in blocks.iter_mut().zip(sizes.iter()) {
+        let layout = Layout::from_size_align(size - TAGS, 8).unwrap();
+        *block = unsafe { allocator.alloc(layout) } as usize;
+        assert_ne!(*block, 0);
+    }
+    for i in [0, 2] {
+        let layout = Layout::from_size_align(sizes[i] - TAGS, 8).unwrap();
+        unsafe { allocator.dealloc(blocks[i] as *mut u8, layout) };
+    }
+    let region = |i: usize| blocks[i] - HEADER_SIZE;
+    (allocator, [region(2), region(0)])
+}
+
+#[test_case]
+fn best_fit_picks_smallest_region() {
+    // 正好占满 128 字节的区域
+    let layout = Layout::from_size_align(128 - TAGS, 8).unwrap();
+
+    let (allocator, [_, small]) = allocator_with_same_bin_regions(Policy::BestFit);
+    assert_eq!(
+        unsafe { allocator.alloc(layout) } as usize,
+        small + HEADER_SIZE
+    );
+
+    // 两个区域都要拆分时 first-fit 使用列表中的第一个区域
+    #[cfg(not(feature = "heap-tree"))]
+    {
+        let split = Layout::from_size_align(64 - TAGS, 8).unwrap();
+        let (allocator, [large, _]) = allocator_with_same_bin_regions(Policy::FirstFit);
+        assert_eq!(
+            unsafe { allocator.alloc(split) } as usize,
+            large + HEADER_SIZE
+        );
+    }
+}
+
+#[test_case]
+fn first_fit_prefers_exact_region() {
+    let layout = Layout::from_size_align(128 - TAGS, 8).unwrap();
+    let (allocator, [_, small]) = allocator_with_same_bin_regions(Policy::FirstFit);
+    assert_eq!(
+        unsafe { allocator.alloc(layout) } as usize,
+        small + HEADER_SIZE
+    );
+    // 正好用掉的区域不会拆分出新的碎片
+    assert_eq!(allocator.region_count(), 1);
+}
+
+#[test_case]
+fn equal_size_pairs_keep_region_count() {
+    const SLOTS: usize = 64;
+    let allocator = new_allocator();
+    let mut rng = XorShift(0x1234_5678_9abc_def1);
+    // 先用不同大小的分配把堆打散
+    let mut live: [Option<(*mut u8, Layout)>; SLOTS] = [None; SLOTS];
+    for slot in live.iter_mut() {
+        let layout = Layout::from_size_align(8 + rng.next() % 500, 8).unwrap();
+        *slot = Some((unsafe { allocator.alloc(layout) }, layout));
+    }
+    for slot in live.iter_mut().step_by(2) {
+        let (ptr, layout) = slot.take().unwrap();
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    // 先分配下一个再释放上一个
+    let layout = Layout::from_size_align(200, 8).unwrap();
+    let mut current = unsafe { allocator.alloc(layout) };
+    let regions = allocator.region_count();
+    for _ in 0..200 {
+        let next = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(current, layout) };
+        current = next;
+        // 区域数可能在相邻的两个值之间交替，但不会持续增长
+        assert!(allocator.region_count() <= regions + 1);
+    }
+
+    unsafe { allocator.dealloc(current, layout) };
+    for (ptr, layout) in live.iter().flatten() {
+        unsafe { allocator.dealloc(*ptr, *layout) };
+    }
+    assert_eq!(allocator.region_count(), 1);
+}
+
+#[test_case]
+fn search_starts_in_request_bin() {
+    // 64 和 128 字节的区域在更小的分箱里，first-fit 直接从 4096 字节的区域找起
+    let layout = Layout::from_size_align(2048, 8).unwrap();
+    let (allocator, [_, large, _]) = allocator_with_regions(Policy::FirstFit);
+    let scanned = allocator.lock().regions_scanned();
+    assert_eq!(
+        unsafe { allocator.alloc(layout) } as usize,
+        large + HEADER_SIZE
+    );
+    assert_eq!(allocator.lock().regions_scanned() - scanned, 1);
+}
+
+/// 在请求所在的分箱里留下 100 个放不下请求的小空洞，然后以 FIFO 方式反复分配/释放 128、136、
+/// 144 字节的块，返回平均每次分配检查的空闲区域数。
+///
+/// 大小相近但不相同：刚释放的区域放在表头，但多半放不下下一个请求。
+#[cfg(not(feature = "heap-tree"))]
+fn average_scan_length(policy: Policy) -> usize {
+    const ALLOCATIONS: usize = 10_000;
+    let allocator = new_allocator();
+    allocator.lock().set_policy(policy);
+
+    let small = Layout::from_size_align(64 - TAGS, 8).unwrap();
+    let mut holes = [ptr::null_mut(); 200];
+    for hole in holes.iter_mut() {
+        *hole = unsafe { allocator.alloc(small) };
+    }
+    for hole in holes.iter().step_by(2) {
+        unsafe { allocator.dealloc(*hole, small) };
+    }
+
+    let mut live: [(*mut u8, Layout); 32] = [(ptr::null_mut(), small); 32];
+    let scanned_before = allocator.lock().regions_scanned();
+    for i in 0..ALLOCATIONS {
+        let (slot, slot_layout) = &mut live[i % 32];
+        if !slot.is_null() {
+            unsafe { allocator.dealloc(*slot, *slot_layout) };
+        }
+        *slot_layout = Layout::from_size_align(128 + i % 3 * 8, 8).unwrap();
+        *slot = unsafe { allocator.alloc(*slot_layout) };
+        assert!(!slot.is_null());
+    }
+    let scanned = allocator.lock().regions_scanned() - scanned_before;
+    scanned / ALLOCATIONS
+}
+
+#[cfg(not(feature = "heap-tree"))]
+#[test_case]
+fn next_fit_scans_fewer_regions() {
+    let first_fit = average_scan_length(Policy::FirstFit);
+    let next_fit = average_scan_length(Policy::NextFit);
+    serial_println!(
+        "average regions scanned per allocation: first-fit {}, next-fit {}",
+        first_fit,
+        next_fit
+    );
+    assert!(next_fit < first_fit);
+}
+
+#[test_case]
+fn realloc_grows_in_place() {
+    let allocator = new_allocator();
+    let mut layout = Layout::from_size_align(1024, 8).unwrap();
+    let mut ptr = unsafe { allocator.alloc(layout) };
+    unsafe { ptr.write_bytes(0xab, layout.size()) };
+
+    // 后面紧跟着的就是空闲的堆尾，每次扩展都应该原地完成
+    let mut copies = 0;
+    while layout.size() < 64 * 1024 {
+        let new_size = layout.size() + 1024;
+        let new_ptr = unsafe { allocator.realloc(ptr, layout, new_size) };
+        assert!(!new_ptr.is_null());
+        if new_ptr != ptr {
+            copies += 1;
+        }
+        ptr = new_ptr;
+        layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+    }
+    assert_eq!(copies, 0);
+    assert!((0..1024).all(|i| unsafe { *ptr.add(i) } == 0xab));
+}
+
+#[test_case]
+fn realloc_copies_when_neighbour_is_used() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    let _second = unsafe { allocator.alloc(layout) };
+    unsafe { first.write_bytes(0x5a, layout.size()) };
+
+    let moved = unsafe { allocator.realloc(first, layout, 256) };
+    assert!(!moved.is_null());
+    assert_ne!(moved, first);
+    assert!((0..64).all(|i| unsafe { *moved.add(i) } == 0x5a));
+
+    // 旧分配已被释放，同样大小的请求会重新用到它
+    assert_eq!(unsafe { allocator.alloc(layout) }, first);
+}
+
+#[test_case]
+fn realloc_shrinks_in_place() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    let _next = unsafe { allocator.alloc(layout) };
+
+    // 100 字节调整后占用 104 字节，尾部 920 字节回到空闲列表
+    assert_eq!(unsafe { allocator.realloc(ptr, layout, 100) }, ptr);
+    let tail = Layout::from_size_align(920 - TAGS, 8).unwrap();
+    assert_eq!(
+        unsafe { allocator.alloc(tail) } as usize,
+        ptr as usize + 104 + TAGS
+    );
+}
+
+#[test_case]
+fn realloc_shrink_keeps_tiny_tail() {
+    let allocator = new_allocator();
+    let initial = allocator.stats();
+
+    // 尾部只有 8 字节，后面又是已分配的块：原样返回指针，尾部留在块里
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let min = Layout::from_size_align(24, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    let next = unsafe { allocator.alloc(min) };
+    assert_eq!(unsafe { allocator.realloc(ptr, layout, 24) }, ptr);
+    assert_eq!(
+        allocator.free_bytes(),
+        initial.free_bytes - (32 + TAGS) - (24 + TAGS)
+    );
+
+    // 释放时尾部随块一起归还，不会漏掉
+    unsafe {
+        allocator.dealloc(ptr, Layout::from_size_align(24, 8).unwrap());
+        allocator.dealloc(next, min);
+    }
+    assert_eq!(allocator.stats(), initial);
+}
+
+#[test_case]
+fn grow_in_place_needs_large_enough_neighbour() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    let neighbour = unsafe { allocator.alloc(layout) };
+    let _fence = unsafe { allocator.alloc(layout) };
+
+    // 后面的块还在使用中
+    assert_eq!(unsafe { allocator.grow_in_place(ptr, layout, 65) }, Err(()));
+
+    // 空出来的邻居正好能放下 64 + TAGS 字节，多 1 字节就不行
+    unsafe { allocator.dealloc(neighbour, layout) };
+    let stats = allocator.stats();
+    let usage = allocator.current_usage();
+    let fits = 2 * 64 + TAGS;
+    assert_eq!(
+        unsafe { allocator.grow_in_place(ptr, layout, fits + 1) },
+        Err(())
+    );
+    assert_eq!(allocator.stats(), stats);
+    assert_eq!(allocator.current_usage(), usage);
+
+    assert_eq!(
+        unsafe { allocator.grow_in_place(ptr, layout, fits) },
+        Ok(())
+    );
+    assert_eq!(allocator.current_usage(), usage + 64 + TAGS);
+    assert_eq!(allocator.free_bytes(), stats.free_bytes - (64 + TAGS));
+    assert!(allocator.check_consistency().is_ok());
+}
+
+#[test_case]
+fn shrink_in_place_splits_large_tails() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    let _fence = unsafe { allocator.alloc(layout) };
+    let free = allocator.free_bytes();
+
+    // 8 字节的尾部放不下空闲块，后面又是已分配的块：留在块里
+    assert_eq!(
+        unsafe { allocator.shrink_in_place(ptr, layout, 248) },
+        Ok(())
+    );
+    assert_eq!(allocator.free_bytes(), free);
+
+    // 剩下的尾部足够大，拆分成新的空闲区域
+    let shrunk = Layout::from_size_align(248, 8).unwrap();
+    let regions = allocator.region_count();
+    assert_eq!(
+        unsafe { allocator.shrink_in_place(ptr, shrunk, 64) },
+        Ok(())
+    );
+    assert_eq!(allocator.free_bytes(), free + 256 - 64);
+    assert_eq!(allocator.region_count(), regions + 1);
+
+    // 不能用 shrink 扩大
+    let small = Layout::from_size_align(64, 8).unwrap();
+    assert_eq!(
+        unsafe { allocator.shrink_in_place(ptr, small, 128) },
+        Err(())
+    );
+    assert!(allocator.check_consistency().is_ok());
+}
+
+#[test_case]
+fn free_regions_follow_list_order() {
+    let (allocator, [small, large, medium]) = allocator_with_regions(Policy::FirstFit);
+    let mut regions = [(0, 0); 4];
+    let guard = allocator.lock();
+    let mut count = 0;
+    for (slot, region) in regions.iter_mut().zip(guard.free_regions()) {
+        *slot = region;
+        count += 1;
+    }
+    assert_eq!(count, guard.region_count());
+    assert_eq!(regions, [(small, 64), (medium, 128), (large, 4096), (0, 0)]);
+}
+
+#[test_case]
+fn stats_match_known_allocations() {
+    let allocator = new_allocator();
+    assert_eq!(
+        allocator.stats(),
+        HeapStats {
+            free_bytes: ARENA_SIZE,
+            region_count: 1,
+            largest_free_region: ARENA_SIZE,
+            allocations: 0,
+            tag_bytes: 0,
+            user_bytes: 0,
+            reserved_bytes: 0,
+            overhead: 0,
+            dropped_bytes: 0,
+        }
+    );
+
+    // 20 字节调整为 24 字节，每个块再加上边界标记；释放中间的一块留下一个 256 字节加边界标记的空洞
+    let small = Layout::from_size_align(20, 1).unwrap();
+    let large = Layout::from_size_align(256, 8).unwrap();
+    let _a = unsafe { allocator.alloc(small) };
+    let b = unsafe { allocator.alloc(large) };
+    let _c = unsafe { allocator.alloc(small) };
+    unsafe { allocator.dealloc(b, large) };
+
+    let small_block = 24 + TAGS;
+    let large_block = 256 + TAGS;
+    let tail = ARENA_SIZE - 2 * small_block - large_block;
+    assert_eq!(
+        allocator.stats(),
+        HeapStats {
+            free_bytes: large_block + tail,
+            region_count: 2,
+            largest_free_region: tail,
+            allocations: 2,
+            tag_bytes: 2 * TAGS,
+            user_bytes: 2 * 20,
+            reserved_bytes: 2 * small_block,
+            overhead: 2 * (small_block - 20),
+            dropped_bytes: 0,
+        }
+    );
+}
+
+#[test_case]
+fn overhead_of_small_objects() {
+    const OBJECTS: usize = 1000;
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(10, 1).unwrap();
+    let mut ptrs = [ptr::null_mut(); OBJECTS];
+    for ptr in ptrs.iter_mut() {
+        *ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+    }
+
+    // 每个对象至少占一个最小块：放得下 ListNode 和脚标的 40 字节
+    let block = (16 + TAGS).max(40);
+    let stats = allocator.stats();
+    serial_println!(
+        "{} objects of 10 bytes: {} bytes reserved, {} bytes overhead",
+        OBJECTS,
+        stats.reserved_bytes,
+        stats.overhead
+    );
+    assert_eq!(stats.user_bytes, OBJECTS * 10);
+    assert_eq!(stats.reserved_bytes, OBJECTS * block);
+    assert_eq!(stats.overhead, OBJECTS * (block - 10));
+
+    // 原地调整大小和搬到新块都只改变请求的字节数
+    ptrs[0] = unsafe { allocator.realloc(ptrs[0], layout, 4) };
+    ptrs[1] = unsafe { allocator.realloc(ptrs[1], layout, 1000) };
+    let stats = allocator.stats();
+    assert_eq!(stats.user_bytes, OBJECTS * 10 - 6 + 990);
+    assert_eq!(stats.overhead, stats.reserved_bytes - stats.user_bytes);
+
+    unsafe {
+        allocator.dealloc(ptrs[0], Layout::from_size_align(4, 1).unwrap());
+        allocator.dealloc(ptrs[1], Layout::from_size_align(1000, 1).unwrap());
+    }
+    for &ptr in &ptrs[2..] {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    let stats = allocator.stats();
+    assert_eq!(
+        (stats.user_bytes, stats.reserved_bytes, stats.overhead),
+        (0, 0, 0)
+    );
+}
+
+/// 写入固定大小缓冲区的 `fmt::Write`，用来检查不分配内存的输出。
+struct BufWriter {
+    buf: [u8; 1024],
+    len: usize,
+}
+
+impl BufWriter {
+    fn new() -> Self {
+        BufWriter {
+            buf: [0; 1024],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap()
+    }
+}
+
+impl fmt::Write for BufWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buf.len() {
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[test_case]
+fn dump_lists_every_region() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = unsafe { allocator.alloc(layout) };
+    let _b = unsafe { allocator.alloc(layout) };
+    unsafe { allocator.dealloc(a, layout) };
+
+    let mut out = BufWriter::new();
+    allocator.lock().dump(&mut out).unwrap();
+    let text = out.as_str();
+    serial_println!("{}", text);
+
+    // 标题行、两个区域、两行汇总
+    assert_eq!(text.lines().count(), 5);
+    let summary = text.lines().nth(3).unwrap();
+    assert!(summary.starts_with("total free: "));
+    assert!(summary.ends_with(" bytes in 2 regions"));
+    let mut expected = BufWriter::new();
+    write!(
+        expected,
+        "total allocated: {} bytes reserved for 64 requested bytes in 1 allocations, {} bytes overhead",
+        64 + TAGS,
+        TAGS
+    )
+    .unwrap();
+    assert_eq!(text.lines().last(), Some(expected.as_str()));
+    // 64 字节加上边界标记
+    let mut expected = BufWriter::new();
+    write!(expected, " {} bytes", 64 + TAGS).unwrap();
+    assert!(text.contains(expected.as_str()));
+    allocator.dump_to_serial();
+}
+
+#[test_case]
+fn dealloc_outside_heap_is_ignored() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let _used = unsafe { allocator.alloc(layout) };
+    let before = allocator.stats();
+
+    let mut on_stack = [0u64; 8];
+    unsafe { allocator.dealloc(on_stack.as_mut_ptr() as *mut u8, layout) };
+    // 指针在堆内，但它前面的头部在堆外
+    let heap_start = arena_start() as *mut u8;
+    unsafe { allocator.dealloc(heap_start, layout) };
+
+    assert_eq!(allocator.stats(), before);
+    assert_eq!(on_stack, [0; 8]);
+}
+
+#[test_case]
+fn alloc_zeroed_from_pristine_regions() {
+    let allocator = zeroed_allocator();
+    let big = Layout::from_size_align(8192, 8).unwrap();
+    let first = unsafe { allocator.alloc_zeroed(big) };
+    assert_zeroed(first, big.size());
+    unsafe { first.write_bytes(0xff, big.size()) };
+
+    // 从剩余的原始区域中切出，区域开头正是被移走的 ListNode
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let second = unsafe { allocator.alloc_zeroed(small) };
+    assert_eq!(second as usize, first as usize + big.size() + TAGS);
+    assert_zeroed(second, small.size());
+
+    let aligned = Layout::from_size_align(100, 64).unwrap();
+    let third = unsafe { allocator.alloc_zeroed(aligned) };
+    assert_eq!(third as usize % 64, 0);
+    assert_zeroed(third, aligned.size());
+}
+
+#[test_case]
+fn alloc_zeroed_clears_recycled_regions() {
+    let allocator = zeroed_allocator();
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    unsafe {
+        first.write_bytes(0xab, layout.size());
+        allocator.dealloc(first, layout);
+    }
+
+    let second = unsafe { allocator.alloc_zeroed(layout) };
+    assert_eq!(second, first);
+    assert_zeroed(second, layout.size());
+
+    // 没有用 init_zeroed 初始化的堆不做任何假设
+    unsafe { ptr::write_bytes(arena_start() as *mut u8, 0xcd, ARENA_SIZE) };
+    let allocator = new_allocator();
+    let third = unsafe { allocator.alloc_zeroed(layout) };
+    assert_zeroed(third, layout.size());
+}
+
+#[test_case]
+fn page_aligned_allocations_keep_front_padding() {
+    let allocator = new_allocator();
+    let initial = allocator.stats();
+    let layout = Layout::from_size_align(64, 4096).unwrap();
+    for i in 1..=16 {
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 4096, 0);
+        let stats = allocator.stats();
+        assert_eq!(
+            stats.free_bytes,
+            initial.free_bytes - i * layout.size() - stats.tag_bytes
+        );
+    }
+
+    // 前部空隙放不下空闲块时起点后移，空隙仍然作为空闲块放回列表
+    let allocator = new_allocator();
+    let first_layout = Layout::from_size_align(64 - TAGS, 8).unwrap();
+    let first = unsafe { allocator.alloc(first_layout) };
+    let aligned_layout = Layout::from_size_align(32, 32).unwrap();
+    let aligned = unsafe { allocator.alloc(aligned_layout) };
+    assert_eq!(aligned as usize, arena_start() + 128);
+    unsafe {
+        allocator.dealloc(aligned, aligned_layout);
+        allocator.dealloc(first, first_layout);
+    }
+    assert_eq!(allocator.stats(), initial);
+}
+
+// 红区检查依赖释放时传入的布局
+#[cfg(not(feature = "heap-redzone"))]
+#[test_case]
+fn dealloc_trusts_header_over_layout() {
+    let allocator = new_allocator();
+    let initial = allocator.stats();
+    let big = Layout::from_size_align(256, 8).unwrap();
+    let small = Layout::from_size_align(8, 8).unwrap();
+
+    let first = unsafe { allocator.alloc(big) };
+    let second = unsafe { allocator.alloc(big) };
+    // 用更小的布局释放：整个块仍然回到空闲列表，并和后面的区域合并
+    unsafe {
+        allocator.dealloc(second, small);
+        allocator.dealloc(first, small);
+    }
+    assert_eq!(allocator.stats(), initial);
+
+    let full = Layout::from_size_align(ARENA_SIZE - TAGS, 8).unwrap();
+    assert_eq!(unsafe { allocator.alloc(full) }, first);
+}
+
+/// 简单的 xorshift 伪随机数生成器，测试不依赖外部 crate。
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+#[test_case]
+fn churn_returns_to_single_region() {
+    const SLOTS: usize = 64;
+    let allocator = new_allocator();
+    let initial = allocator.stats();
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut live: [Option<(*mut u8, Layout)>; SLOTS] = [None; SLOTS];
+
+    for _ in 0..20_000 {
+        let slot = &mut live[rng.next() % SLOTS];
+        let size = 1 + rng.next() % 2048;
+        match *slot {
+            Some((ptr, layout)) if rng.next().is_multiple_of(4) => {
+                let new_ptr = unsafe { allocator.realloc(ptr, layout, size) };
+                assert!(!new_ptr.is_null());
+                *slot = Some((
+                    new_ptr,
+                    Layout::from_size_align(size, layout.align()).unwrap(),
+                ));
+            }
+            Some((ptr, layout)) => {
+                unsafe { allocator.dealloc(ptr, layout) };
+                *slot = None;
+            }
+            None => {
+                let layout = Layout::from_size_align(size, 1 << (rng.next() % 7)).unwrap();
+                let ptr = unsafe { allocator.alloc(layout) };
+                assert!(!ptr.is_null());
+                *slot = Some((ptr, layout));
+            }
+        }
+    }
+    for (ptr, layout) in live.iter().flatten() {
+        unsafe { allocator.dealloc(*ptr, *layout) };
+    }
+
+    assert_eq!(allocator.stats(), initial);
+}
+
+/// 在 `allocator` 上随机分配和释放小块，结束时保留一半的分配，返回小于 192 字节的空闲区域个数。
+fn small_regions_after_churn(allocator: &Locked<LinkedListAllocator>) -> usize {
+    const SLOTS: usize = 256;
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+    let mut live: [Option<(*mut u8, Layout)>; SLOTS] = [None; SLOTS];
+
+    for _ in 0..20_000 {
+        let slot = &mut live[rng.next() % SLOTS];
+        match *slot {
+            Some((ptr, layout)) => {
+                unsafe { allocator.dealloc(ptr, layout) };
+                *slot = None;
+            }
+            None => {
+                let layout = Layout::from_size_align(1 + rng.next() % 200, 8).unwrap();
+                let ptr = unsafe { allocator.alloc(layout) };
+                assert!(!ptr.is_null());
+                *slot = Some((ptr, layout));
+            }
+        }
+    }
+    let small = allocator.lock().regions_smaller_than(192);
+    for (ptr, layout) in live.iter().flatten() {
+        unsafe { allocator.dealloc(*ptr, *layout) };
+    }
+    small
+}
+
+#[test_case]
+fn min_split_leaves_fewer_small_regions() {
+    let allocator = new_allocator();
+    let initial = allocator.stats();
+    let default_small = small_regions_after_churn(&allocator);
+    assert_eq!(allocator.stats(), initial);
+
+    let allocator = new_allocator();
+    allocator.lock().set_min_split(192);
+    let split_small = small_regions_after_churn(&allocator);
+    // 剩余部分并入了分配，释放后一样完整归还
+    assert_eq!(allocator.stats(), initial);
+
+    serial_println!(
+        "free regions below 192 bytes: default {}, min split 192 {}",
+        default_small,
+        split_small
+    );
+    assert!(split_small < default_small);
+}
+
+#[test_case]
+fn extend_with_disjoint_region() {
+    let allocator = allocator_with_size(ARENA_SIZE / 4);
+    let before = allocator.free_bytes();
+    let layout = Layout::from_size_align(ARENA_SIZE / 2, 8).unwrap();
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+
+    let extra = arena_start() + ARENA_SIZE / 2;
+    unsafe { allocator.extend(extra, ARENA_SIZE / 2) }.unwrap();
+    assert_eq!(allocator.free_bytes(), before + ARENA_SIZE / 2);
+    assert_eq!(allocator.region_count(), 2);
+
+    let layout = Layout::from_size_align(ARENA_SIZE / 2 - TAGS, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert_eq!(ptr as usize, extra + HEADER_SIZE);
+    unsafe { allocator.dealloc(ptr, layout) };
+    // 两段之间有空隙，不会合并
+    assert_eq!(allocator.region_count(), 2);
+    assert_eq!(allocator.free_bytes(), before + ARENA_SIZE / 2);
+}
+
+#[test_case]
+fn allocations_never_straddle_hole() {
+    // 两段之间隔着一页不属于堆的空洞
+    let low = arena_start();
+    let high = arena_start() + ARENA_SIZE / 2 + PAGE_SIZE;
+    let allocator = allocator_with_size(ARENA_SIZE / 2);
+    unsafe { allocator.extend(high, ARENA_SIZE / 2 - PAGE_SIZE) }.unwrap();
+    {
+        let guard = allocator.lock();
+        let mut ranges = [(0, 0); 2];
+        ranges.copy_from_slice(guard.heap_ranges());
+        ranges.sort_unstable();
+        assert_eq!(
+            ranges,
+            [
+                (low, low + ARENA_SIZE / 2),
+                (high, arena_start() + ARENA_SIZE)
+            ]
+        );
+    }
+    let initial = allocator.stats();
+
+    // 分配大小不一的块直到堆满，每个块都只能落在其中一段里
+    let mut blocks = [(ptr::null_mut(), Layout::new::<u8>()); 64];
+    let mut count = 0;
+    for (i, block) in blocks.iter_mut().enumerate() {
+        let layout = Layout::from_size_align(1000 + i * 37, 8).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        if ptr.is_null() {
+            break;
+        }
+        let (start, end) = (ptr as usize, ptr as usize + layout.size());
+        let guard = allocator.lock();
+        assert!(guard
+            .heap_ranges()
+            .iter()
+            .any(|&(range_start, range_end)| range_start <= start && end <= range_end));
+        *block = (ptr, layout);
+        count += 1;
+    }
+    assert!(count > 0 && count < blocks.len());
+
+    for &(ptr, layout) in &blocks[..count] {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    // 空洞两边各剩一个完整的空闲区域
+    assert_eq!(allocator.region_count(), 2);
+    assert_eq!(allocator.stats(), initial);
+    assert!(allocator.check_consistency().is_ok());
+}
+
+#[test_case]
+fn extend_adjacent_region_spans_boundary() {
+    let allocator = allocator_with_size(ARENA_SIZE / 2);
+    unsafe { allocator.extend(arena_start() + ARENA_SIZE / 2, ARENA_SIZE / 2) }.unwrap();
+    assert_eq!(allocator.region_count(), 1);
+
+    // 分配跨过原来的堆尾
+    let layout = Layout::from_size_align(ARENA_SIZE - TAGS, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert_eq!(ptr as usize, arena_start() + HEADER_SIZE);
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(allocator.free_bytes(), ARENA_SIZE);
+}
+
+#[test_case]
+fn extend_rejects_invalid_regions() {
+    let allocator = allocator_with_size(ARENA_SIZE / 2);
+    let before = allocator.stats();
+    let start = arena_start();
+    assert_eq!(
+        unsafe { allocator.extend(start + ARENA_SIZE / 4, ARENA_SIZE / 2) },
+        Err(ExtendError::Overlapping)
+    );
+    assert_eq!(
+        unsafe { allocator.extend(start + ARENA_SIZE / 2 + 4, 4096) },
+        Err(ExtendError::Misaligned)
+    );
+    assert_eq!(
+        unsafe { allocator.extend(start + ARENA_SIZE / 2, 8) },
+        Err(ExtendError::TooSmall)
+    );
+    assert_eq!(allocator.stats(), before);
+}
+
+// 启用 strict-free-regions 时这些区域会触发断言
+#[cfg(not(feature = "strict-free-regions"))]
+#[test_case]
+fn init_trims_unaligned_heap() {
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(arena_start() + 1, ARENA_SIZE - 1) };
+    let stats = allocator.stats();
+    assert_eq!(stats.free_bytes, ARENA_SIZE - 8);
+    assert_eq!(stats.dropped_bytes, 7);
+
+    let layout = Layout::from_size_align(ARENA_SIZE - 8 - TAGS, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert_eq!(ptr as usize, arena_start() + 8 + HEADER_SIZE);
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(allocator.stats(), stats);
+}
+
+#[cfg(not(feature = "strict-free-regions"))]
+#[test_case]
+fn tiny_heaps_are_dropped_without_panicking() {
+    for offset in 0..8 {
+        for size in 1..=40 {
+            let allocator = Locked::new(LinkedListAllocator::new());
+            unsafe { allocator.lock().init(arena_start() + offset, size) };
+            let stats = allocator.stats();
+            assert_eq!(stats.free_bytes + stats.dropped_bytes, size);
+            assert!(stats.region_count <= 1);
+            assert!(unsafe { allocator.alloc(Layout::new::<u64>()) }.is_null() || size >= 32);
+        }
+    }
+}
+
+#[test_case]
+fn consistency_check_passes_on_healthy_heap() {
+    let (allocator, _) = allocator_with_regions(Policy::FirstFit);
+    assert_eq!(
+        allocator.check_consistency(),
+        Ok(HeapCheck {
+            regions: 3,
+            free_bytes: 64 + 4096 + 128,
+            blocks: 6,
+        })
+    );
+}
+
+#[test_case]
+fn consistency_check_reports_corruption() {
+    let (allocator, [small, large, medium]) = allocator_with_regions(Policy::FirstFit);
+    let write = |addr: usize, value: usize| unsafe { (addr as *mut usize).write(value) };
+    let read = |addr: usize| unsafe { (addr as *const usize).read() };
+
+    // 前一个分配越界写坏了空闲块的头部标记
+    let tag = read(large);
+    write(large, 0);
+    assert_eq!(
+        allocator.check_consistency(),
+        Err(HeapCorruption {
+            addr: large,
+            reason: CorruptionReason::NotFree,
+        })
+    );
+    write(large, tag);
+
+    // 写坏已分配块的脚标
+    let used = small + 64;
+    let footer_addr = used + 48 - mem::size_of::<usize>();
+    let footer = read(footer_addr);
+    write(footer_addr, 0xdead);
+    assert_eq!(
+        allocator.check_consistency(),
+        Err(HeapCorruption {
+            addr: used,
+            reason: CorruptionReason::BadFooter,
+        })
+    );
+    write(footer_addr, footer);
+
+    // 表尾指回表头形成环
+    #[cfg(not(feature = "heap-tree"))]
+    {
+        write(medium + 8, small);
+        assert_eq!(
+            allocator.check_consistency(),
+            Err(HeapCorruption {
+                addr: small,
+                reason: CorruptionReason::BrokenLink,
+            })
+        );
+        write(medium + 8, 0);
+    }
+    // 最小区域的左子节点指向更大的区域，破坏了树的顺序
+    #[cfg(feature = "heap-tree")]
+    {
+        let _ = medium;
+        write(small + 8, large);
+        assert_eq!(
+            allocator.check_consistency(),
+            Err(HeapCorruption {
+                addr: large,
+                reason: CorruptionReason::BrokenLink,
+            })
+        );
+        write(small + 8, 0);
+    }
+    assert!(allocator.check_consistency().is_ok());
+}
+
+#[cfg(feature = "heap-poison")]
+#[test_case]
+fn freed_memory_is_poisoned() {
+    use blog_os::allocator::linked_list::POISON;
+
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    let _guard = unsafe { allocator.alloc(layout) };
+    unsafe { ptr.write_bytes(0x42, 256) };
+    unsafe { allocator.dealloc(ptr, layout) };
+
+    // 用户数据的前 24 字节被 ListNode 的指针和 magic 占用，之后直到脚标都是填充
+    let bytes = unsafe { core::slice::from_raw_parts(ptr.add(24), 256 - 24) };
+    assert!(bytes.iter().all(|&byte| byte == POISON));
+
+    // 填充完好时照常分配，释放后合并回去也不会误报
+    let again = unsafe { allocator.alloc(layout) };
+    assert_eq!(again, ptr);
+    unsafe { allocator.dealloc(again, layout) };
+}
+
+#[test_case]
+fn fragmentation_rises_and_recovers() {
+    const N: usize = 64;
+    let allocator = new_allocator();
+    assert_eq!(allocator.fragmentation(), 0);
+
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let mut blocks = [ptr::null_mut(); N];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+    }
+    let before = allocator.fragmentation();
+    for block in blocks.iter().step_by(2) {
+        unsafe { allocator.dealloc(*block, layout) };
+    }
+    let fragmented = allocator.fragmentation();
+    serial_println!("fragmentation: {} -> {} per mille", before, fragmented);
+    assert!(fragmented > before);
+
+    for block in blocks.iter().skip(1).step_by(2) {
+        unsafe { allocator.dealloc(*block, layout) };
+    }
+    assert_eq!(allocator.fragmentation(), 0);
+
+    // 全部分配出去、没有空闲内存时也不会除以零
+    let full = Layout::from_size_align(ARENA_SIZE - TAGS, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(full) };
+    assert_eq!(allocator.free_bytes(), 0);
+    assert_eq!(allocator.fragmentation(), 0);
+    unsafe { allocator.dealloc(ptr, full) };
+}
+
+#[test_case]
+fn adjacent_small_regions_collapse_to_one() {
+    const N: usize = 1000;
+    let allocator = new_allocator();
+    let initial = allocator.stats();
+    let layout = Layout::from_size_align(64 - TAGS, 8).unwrap();
+    let mut blocks = [ptr::null_mut(); N];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+    }
+    // 以打乱的顺序释放 1000 个相邻的小块，它们在释放时就已经合并
+    let mut rng = XorShift(0x1234_5678_9abc_def1);
+    for i in (1..N).rev() {
+        blocks.swap(i, rng.next() % (i + 1));
+    }
+    for block in blocks.iter() {
+        unsafe { allocator.dealloc(*block, layout) };
+    }
+    assert_eq!(allocator.defragment(), 0);
+    assert_eq!(allocator.stats(), initial);
+}
+
+#[test_case]
+fn defragment_sorts_free_list_by_address() {
+    const N: usize = 100;
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(64 - TAGS, 8).unwrap();
+    let mut blocks = [ptr::null_mut(); 2 * N];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+    }
+    // 释放偶数块，新释放的在表头，列表和地址顺序相反
+    for block in blocks.iter().step_by(2) {
+        unsafe { allocator.dealloc(*block, layout) };
+    }
+    let before = allocator.stats();
+
+    assert_eq!(allocator.defragment(), 0);
+    assert_eq!(allocator.stats(), before);
+    let guard = allocator.lock();
+    let mut previous = 0;
+    for (start, _) in guard.free_regions() {
+        assert!(start > previous);
+        previous = start;
+    }
+    drop(guard);
+    assert!(allocator.check_consistency().is_ok());
+
+    // 排序后 first-fit 先用最低地址的空洞
+    assert_eq!(unsafe { allocator.alloc(layout) }, blocks[0]);
+}
+
+#[test_case]
+fn bins_shorten_mixed_size_search() {
+    const SLOTS: usize = 256;
+    let allocator = new_allocator();
+    let mut rng = XorShift(0x0bad_cafe_dead_beef);
+    let mut live: [Option<(*mut u8, Layout)>; SLOTS] = [None; SLOTS];
+    let mut allocations = 0;
+    let mut regions_seen = 0;
+    let scanned_before = allocator.lock().regions_scanned();
+
+    for _ in 0..20_000 {
+        let slot = &mut live[rng.next() % SLOTS];
+        match *slot {
+            Some((ptr, layout)) => {
+                unsafe { allocator.dealloc(ptr, layout) };
+                *slot = None;
+            }
+            None => {
+                // 大多是小分配，偶尔夹杂大块
+                let size = match rng.next() % 8 {
+                    0 => 512 + rng.next() % 1024,
+                    1 | 2 => 64 + rng.next() % 192,
+                    _ => 1 + rng.next() % 48,
+                };
+                let layout = Layout::from_size_align(size, 8).unwrap();
+                regions_seen += allocator.region_count();
+                let ptr = unsafe { allocator.alloc(layout) };
+                assert!(!ptr.is_null());
+                *slot = Some((ptr, layout));
+                allocations += 1;
+            }
+        }
+    }
+    let scanned = allocator.lock().regions_scanned() - scanned_before;
+    serial_println!(
+        "mixed workload: {} regions scanned and {} free regions per allocation",
+        scanned / allocations,
+        regions_seen / allocations
+    );
+    assert!(scanned * 2 < regions_seen);
+    for (ptr, layout) in live.iter().flatten() {
+        unsafe { allocator.dealloc(*ptr, *layout) };
+    }
+    assert!(allocator.check_consistency().is_ok());
+}
+
+#[test_case]
+fn peak_usage_survives_frees() {
+    let allocator = new_allocator();
+    assert_eq!((allocator.current_usage(), allocator.peak_usage()), (0, 0));
+
+    let big = Layout::from_size_align(100 * 1024, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(big) };
+    unsafe { allocator.dealloc(ptr, big) };
+    let small = Layout::from_size_align(10 * 1024, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(small) };
+
+    assert_eq!(allocator.peak_usage(), 100 * 1024 + TAGS);
+    assert_eq!(allocator.current_usage(), 10 * 1024 + TAGS);
+
+    // 原地扩大和缩小也计入使用量
+    let grown = unsafe { allocator.realloc(ptr, small, 20 * 1024) };
+    assert_eq!(grown, ptr);
+    assert_eq!(allocator.current_usage(), 20 * 1024 + TAGS);
+    let grown_layout = Layout::from_size_align(20 * 1024, 8).unwrap();
+    let shrunk = unsafe { allocator.realloc(grown, grown_layout, 1024) };
+    assert_eq!(allocator.current_usage(), 1024 + TAGS);
+    let shrunk_layout = Layout::from_size_align(1024, 8).unwrap();
+    unsafe { allocator.dealloc(shrunk, shrunk_layout) };
+    assert_eq!(allocator.current_usage(), 0);
+    assert_eq!(allocator.peak_usage(), 100 * 1024 + TAGS);
+}
+
+/// OOM 钩子最近一次看到的布局大小、对齐和空闲字节数。
+static OOM_SIZE: AtomicUsize = AtomicUsize::new(0);
+static OOM_ALIGN: AtomicUsize = AtomicUsize::new(0);
+static OOM_FREE: AtomicUsize = AtomicUsize::new(0);
+
+fn record_oom(allocator: &LinkedListAllocator, layout: Layout) {
+    OOM_SIZE.store(layout.size(), Ordering::Relaxed);
+    OOM_ALIGN.store(layout.align(), Ordering::Relaxed);
+    OOM_FREE.store(allocator.free_bytes(), Ordering::Relaxed);
+}
+
+#[test_case]
+fn oom_hook_sees_failed_layout() {
+    let allocator = allocator_with_size(1024);
+    allocator.lock().set_oom_hook(record_oom);
+    OOM_SIZE.store(0, Ordering::Relaxed);
+
+    let fits = Layout::from_size_align(64, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(fits) };
+    assert!(!ptr.is_null());
+    assert_eq!(OOM_SIZE.load(Ordering::Relaxed), 0);
+
+    let too_big = Layout::from_size_align(2048, 16).unwrap();
+    assert!(unsafe { allocator.alloc(too_big) }.is_null());
+    assert_eq!(OOM_SIZE.load(Ordering::Relaxed), 2048);
+    assert_eq!(OOM_ALIGN.load(Ordering::Relaxed), 16);
+    assert_eq!(OOM_FREE.load(Ordering::Relaxed), allocator.free_bytes());
+}
+
+#[test_case]
+fn zero_size_allocations_use_no_heap() {
+    let allocator = new_allocator();
+    let free = allocator.free_bytes();
+    let layout = Layout::from_size_align(0, 16).unwrap();
+    for _ in 0..1_000_000 {
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 16, 0);
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    assert_eq!(allocator.free_bytes(), free);
+    assert_eq!(allocator.stats().allocations, 0);
+
+    // 只有从零大小扩大时才真正分配
+    let ptr = unsafe { allocator.alloc(layout) };
+    let grown = unsafe { allocator.realloc(ptr, layout, 64) };
+    assert_eq!(allocator.stats().allocations, 1);
+    unsafe { allocator.dealloc(grown, Layout::from_size_align(64, 16).unwrap()) };
+    assert_eq!(allocator.free_bytes(), free);
+}
+
+#[test_case]
+fn pathological_layouts_return_null() {
+    let allocator = new_allocator();
+    allocator.lock().set_oom_hook(|_, _| {});
+    let initial = allocator.stats();
+
+    // `Layout` 不接受超过 isize::MAX 的大小，这里是能构造出的最极端的布局：
+    // 第一个按 ListNode 对齐时就会溢出
+    let huge = [
+        Layout::from_size_align(isize::MAX as usize, 1).unwrap(),
+        Layout::from_size_align(isize::MAX as usize - 7, 8).unwrap(),
+    ];
+    for layout in huge {
+        assert!(unsafe { allocator.alloc(layout) }.is_null());
+        assert!(unsafe { allocator.alloc_zeroed(layout) }.is_null());
+    }
+
+    // 扩大到放不下的大小失败时原来的分配保持不变
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    unsafe { ptr.write_bytes(0x5a, 64) };
+    assert!(unsafe { allocator.realloc(ptr, layout, isize::MAX as usize - 7) }.is_null());
+    assert!(unsafe { core::slice::from_raw_parts(ptr, 64) }
+        .iter()
+        .all(|&byte| byte == 0x5a));
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(allocator.stats(), initial);
+}
+
+#[test_case]
+fn page_aligned_allocations_fill_heap_without_leaks() {
+    const SLOTS: usize = 64;
+    let allocator = new_allocator();
+    let initial = allocator.stats();
+    // 忽略堆耗尽时的提示
+    allocator.lock().set_oom_hook(|_, _| {});
+
+    for size in [1, 4096, 12288] {
+        let layout = Layout::from_size_align(size, 4096).unwrap();
+        let mut live = [ptr::null_mut(); SLOTS];
+        let mut count = 0;
+        while count < SLOTS {
+            let ptr = unsafe { allocator.alloc(layout) };
+            if ptr.is_null() {
+                break;
+            }
+            assert_eq!(ptr as usize % 4096, 0);
+            live[count] = ptr;
+            count += 1;
+        }
+        // 每个分配最多浪费一页，所以应该能放满整个堆
+        let per_block = align_up_4k(size + TAGS) + 4096;
+        assert!(count * per_block >= ARENA_SIZE - per_block);
+        for &ptr in &live[..count] {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+        assert_eq!(allocator.stats(), initial);
+    }
+
+    // 对齐比整个堆还大时多半找不到位置，但无论如何都不能破坏堆
+    let huge = Layout::from_size_align(64, 2 * 1024 * 1024).unwrap();
+    let ptr = unsafe { allocator.alloc(huge) };
+    if !ptr.is_null() {
+        assert_eq!(ptr as usize % huge.align(), 0);
+        unsafe { allocator.dealloc(ptr, huge) };
+    }
+    assert_eq!(allocator.stats(), initial);
+}
+
+fn align_up_4k(size: usize) -> usize {
+    (size + 4095) & !4095
+}
+
+#[test_case]
+fn vec_in_private_heap() {
+    let allocator = new_allocator();
+    let initial = allocator.stats();
+
+    let mut vec: Vec<u32, _> = Vec::new_in(&allocator);
+    for i in 0..10_000 {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u32>(), (0..10_000).sum());
+    assert_eq!(allocator.stats().allocations, 1);
+    vec.truncate(10);
+    vec.shrink_to_fit();
+    assert_eq!(&vec[..3], &[0, 1, 2]);
+    assert!(allocator.free_bytes() > initial.free_bytes - 1024);
+
+    drop(vec);
+    assert_eq!(allocator.stats(), initial);
+}
+
+#[test_case]
+fn counters_balance_after_drops() {
+    let allocator = new_allocator();
+    let mut boxes = Vec::new_in(&allocator);
+    for i in 0..20u64 {
+        boxes.push(Box::new_in(i, &allocator));
+    }
+    assert_eq!(allocator.live_allocs(), 21);
+
+    // 向量增长时只调整大小，不算作新的分配或释放
+    assert!(allocator.total_reallocs() > 0);
+    assert_eq!(allocator.total_allocs(), 21);
+    drop(boxes);
+    assert_eq!(allocator.live_allocs(), 0);
+    assert_eq!(allocator.total_deallocs(), allocator.total_allocs());
+
+    // 零大小的分配不计入
+    let empty = unsafe { allocator.alloc(Layout::new::<()>()) };
+    unsafe { allocator.dealloc(empty, Layout::new::<()>()) };
+    assert_eq!(allocator.total_allocs(), 21);
+}
+
+#[test_case]
+fn allocator_api_reports_usable_size() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(13, 8).unwrap();
+    let block = allocator.allocate(layout).unwrap();
+    // 至少是请求的大小，取整的部分也可以使用
+    assert!(block.len() >= layout.size());
+    unsafe { (block.as_ptr() as *mut u8).write_bytes(0xAB, block.len()) };
+    unsafe { allocator.deallocate(block.cast(), layout) };
+    assert_eq!(allocator.stats().allocations, 0);
+
+    let zeroed = allocator.allocate_zeroed(layout).unwrap();
+    let bytes = unsafe { zeroed.as_ref() };
+    assert!(bytes.iter().all(|&byte| byte == 0));
+    unsafe { allocator.deallocate(zeroed.cast(), layout) };
+}
+
+#[test_case]
+fn sub_heap_on_main_heap() {
+    let mut backing: Box<[MaybeUninit<u8>]> = vec![MaybeUninit::uninit(); 4096].into_boxed_slice();
+    let backing_start = backing.as_ptr() as usize;
+    {
+        let sub_heap = Locked::new(unsafe { LinkedListAllocator::from_slice(&mut backing) });
+        let initial = sub_heap.stats();
+        let mut vec: Vec<u64, _> = Vec::new_in(&sub_heap);
+        vec.extend(0..256);
+        let vec_start = vec.as_ptr() as usize;
+        assert!(vec_start >= backing_start && vec_start < backing_start + 4096);
+        assert_eq!(vec.iter().sum::<u64>(), 255 * 256 / 2);
+        drop(vec);
+        assert_eq!(sub_heap.stats(), initial);
+    }
+    drop(backing);
+
+    // 子堆用完以后，主堆可以照常回收和重新使用那块内存
+    let mut again: Box<[u8]> = vec![0xAA; 4096].into_boxed_slice();
+    again[4095] = 1;
+    assert_eq!(again.iter().filter(|&&byte| byte == 0xAA).count(), 4095);
+}
+
+#[test_case]
+fn usable_size_covers_rounding() {
+    let allocator = new_allocator();
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    let usable = unsafe { allocator.usable_size(ptr, layout) };
+    #[cfg(not(feature = "heap-redzone"))]
+    assert!(usable >= 104);
+    #[cfg(feature = "heap-redzone")]
+    assert_eq!(usable, 100);
+
+    unsafe { ptr.write_bytes(0x55, usable) };
+    assert!(allocator.check_consistency().is_ok());
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(allocator.region_count(), 1);
+}
+
+/// `release_tail` 的测试需要 1 MiB 的堆。
+const TAIL_ARENA_SIZE: usize = 1024 * 1024;
+#[repr(align(4096))]
+struct TailArena([u8; TAIL_ARENA_SIZE]);
+static mut TAIL_ARENA: TailArena = TailArena([0; TAIL_ARENA_SIZE]);
+
+#[test_case]
+fn release_tail_returns_free_pages() {
+    let start = unsafe { ptr::addr_of_mut!(TAIL_ARENA.0) as usize };
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(start, TAIL_ARENA_SIZE) };
+
+    // 第一个分配一直保留，其余的占满整个堆后全部释放
+    let keep = Layout::from_size_align(100, 8).unwrap();
+    let kept = unsafe { allocator.alloc(keep) };
+    let chunk = Layout::from_size_align(16 * 1024, 8).unwrap();
+    let mut chunks = [ptr::null_mut(); TAIL_ARENA_SIZE / (16 * 1024)];
+    let mut count = 0;
+    allocator.lock().set_oom_hook(|_, _| {});
+    while let Some(ptr) = ptr::NonNull::new(unsafe { allocator.alloc(chunk) }) {
+        chunks[count] = ptr.as_ptr();
+        count += 1;
+    }
+    for &ptr in &chunks[..count] {
+        unsafe { allocator.dealloc(ptr, chunk) };
+    }
+    let free = allocator.free_bytes();
+
+    let mut reported = (0, 0);
+    let released = allocator.release_tail(|start, size| reported = (start, size));
+    // 第一页里分配剩下的部分不是整页，要留在空闲列表中
+    assert_eq!(reported, (start + PAGE_SIZE, TAIL_ARENA_SIZE - PAGE_SIZE));
+    assert_eq!(released, reported.1);
+    assert_eq!(allocator.free_bytes() + released, free);
+    assert_eq!(allocator.region_count(), 1);
+    assert!(allocator.check_consistency().is_ok());
+
+    // 没有更多可以归还的页，堆仍然可以正常使用
+    assert_eq!(
+        allocator.release_tail(|_, _| panic!("nothing to release")),
+        0
+    );
+    unsafe { allocator.dealloc(kept, keep) };
+    assert_eq!(allocator.free_bytes(), PAGE_SIZE);
+    unsafe { allocator.extend(reported.0, reported.1) }.unwrap();
+    assert_eq!(allocator.free_bytes(), TAIL_ARENA_SIZE);
+}
+
+#[cfg(feature = "heap-registry")]
+#[test_case]
+fn registry_lists_leaked_boxes() {
+    let allocator = new_allocator();
+    drop(Box::new_in(0u64, &allocator));
+    mem::forget(Box::new_in(1u8, &allocator));
+    mem::forget(Box::new_in([2u64; 4], &allocator));
+    let mut grown: Vec<u32, _> = Vec::with_capacity_in(10, &allocator);
+    grown.reserve_exact(100);
+    mem::forget(grown);
+
+    assert_eq!(allocator.live_allocation_count(), 3);
+    let mut sizes = [0; 3];
+    let mut i = 0;
+    allocator.live_allocations(|_, layout| {
+        sizes[i] = layout.size();
+        i += 1;
+    });
+    sizes.sort_unstable();
+    assert_eq!(sizes, [1, 32, 400]);
+}
+
+#[cfg(not(feature = "strict-free-regions"))]
+#[test_case]
+fn accounting_balances_across_heap_changes() {
+    // 未对齐的开头和结尾被丢弃，但仍然记在账上
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(arena_start() + 3, 64 * 1024 - 8) };
+    assert_eq!(allocator.lock().heap_size(), 64 * 1024 - 8);
+    assert_eq!(allocator.check_accounting(), Ok(()));
+
+    let layout = Layout::from_size_align(1000, 64).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    let grown = unsafe { allocator.realloc(ptr, layout, 3000) };
+    assert_eq!(allocator.check_accounting(), Ok(()));
+
+    unsafe { allocator.extend(arena_start() + 64 * 1024, 64 * 1024) }.unwrap();
+    assert_eq!(allocator.check_accounting(), Ok(()));
+    unsafe { allocator.dealloc(grown, Layout::from_size_align(3000, 64).unwrap()) };
+    let released = allocator.release_tail(|_, _| {});
+    assert!(released > 0);
+    assert_eq!(allocator.lock().heap_size(), 128 * 1024 - 8 - released);
+    assert_eq!(allocator.check_accounting(), Ok(()));
+}
+
+// 启用 `heap-verify` 时改小的标记与节点的 magic 不符，遍历空闲区域时就会 panic
+#[cfg(not(feature = "heap-verify"))]
+#[test_case]
+fn accounting_reports_lost_bytes() {
+    let allocator = new_allocator();
+    // 把唯一的空闲区域的头部标记改小，少掉的字节不再属于任何一项
+    let tag = unsafe { (arena_start() as *const usize).read() };
+    unsafe { (arena_start() as *mut usize).write(tag - 64) };
+    assert_eq!(
+        allocator.check_accounting(),
+        Err(AccountingMismatch {
+            used_bytes: 0,
+            free_bytes: ARENA_SIZE - 64,
+            dropped_bytes: 0,
+            heap_size: ARENA_SIZE,
+        })
+    );
+    unsafe { (arena_start() as *mut usize).write(tag) };
+    assert_eq!(allocator.check_accounting(), Ok(()));
+}
+
+#[cfg(feature = "heap-latency")]
+#[test_case]
+fn latency_stats_use_cycle_counter() {
+    use core::sync::atomic::AtomicU64;
+
+    // 每读一次前进 100 个周期，所以每次调用正好耗时 100 个周期
+    static CLOCK: AtomicU64 = AtomicU64::new(0);
+    let allocator = new_allocator();
+    allocator
+        .lock()
+        .set_cycle_counter(|| CLOCK.fetch_add(100, Ordering::Relaxed));
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    for _ in 0..10 {
+        let ptr = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    let report = allocator.latency_stats();
+    assert_eq!(report.alloc.count, 10);
+    assert_eq!(report.dealloc.count, 10);
+    assert_eq!(report.alloc.min_cycles, 100);
+    assert_eq!(report.alloc.max_cycles, 100);
+    assert_eq!(report.alloc.average_cycles(), 100);
+    assert_eq!(report.alloc.histogram[6], 10);
+
+    // 无法原地扩大时 realloc 内部的分配和释放不会重复计入
+    let ptr = unsafe { allocator.alloc(layout) };
+    let guard = unsafe { allocator.alloc(layout) };
+    let moved = unsafe { allocator.realloc(ptr, layout, 4096) };
+    assert_ne!(moved, ptr);
+    let report = allocator.latency_stats();
+    assert_eq!(
+        (
+            report.alloc.count,
+            report.dealloc.count,
+            report.realloc.count
+        ),
+        (12, 10, 1)
+    );
+    unsafe { allocator.dealloc(moved, Layout::from_size_align(4096, 8).unwrap()) };
+    unsafe { allocator.dealloc(guard, layout) };
+
+    allocator.reset_latency_stats();
+    let report = allocator.latency_stats();
+    assert_eq!(report.dealloc.count, 0);
+    assert_eq!(report.dealloc.min_cycles, u64::MAX);
+}
+
+#[test_case]
+fn raw_ranges_can_be_freed_piece_by_piece() {
+    let allocator = new_allocator();
+    let mut heap = allocator.lock();
+    let free = heap.free_bytes();
+    // 一次切出 4 个 64 字节的块，前后都没有边界标记
+    let layout = Layout::from_size_align(4 * 64, 64).unwrap();
+    let chunk = heap.allocate(layout).unwrap();
+    assert_eq!(chunk.as_ptr() as usize % 64, 0);
+    assert_eq!(heap.free_bytes(), free - 4 * 64);
+    assert_eq!(heap.current_usage(), 4 * 64);
+
+    // 逐个释放，顺序打乱，最后合并回原来的样子
+    let block = Layout::from_size_align(64, 64).unwrap();
+    for i in [2, 0, 3, 1] {
+        let ptr = unsafe { ptr::NonNull::new_unchecked(chunk.as_ptr().add(i * 64)) };
+        unsafe { heap.deallocate(ptr, block) };
+    }
+    assert_eq!(heap.free_bytes(), free);
+    assert_eq!(heap.current_usage(), 0);
+    assert_eq!(heap.region_count(), 1);
+    assert_eq!(heap.total_allocs(), 0);
+
+    // 放不下的请求失败，不修改堆
+    let huge = Layout::from_size_align(ARENA_SIZE * 2, 8).unwrap();
+    assert!(heap.allocate(huge).is_err());
+    assert_eq!(heap.free_bytes(), free);
+}
@@ -0,0 +1,178 @@
+// 在 tests/linked_list_bench.rs 中
+//
+// 比较两种空闲区域索引的分配速度：分别不带和带上 `--features heap-tree` 运行，
+// 对比打印出的每次分配的周期数。启用 `heap-latency` 时还会打印各个策略下的耗时分布。
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+#[cfg(feature = "heap-latency")]
+use blog_os::allocator::linked_list::Policy;
+use blog_os::{
+    allocator::{
+        linked_list::{LinkedListAllocator, FOOTER_SIZE, HEADER_SIZE},
+        Locked,
+    },
+    serial_println,
+};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    arch::x86_64::_rdtsc,
+    panic::PanicInfo,
+    ptr,
+};
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    test_main();
+    loop {}
+}
+
+/// 测试用的独立堆，要放得下所有空洞和它们之间的隔板。
+const ARENA_SIZE: usize = 4 * 1024 * 1024;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+/// 每个块的头部标记加脚标。
+const TAGS: usize = HEADER_SIZE + FOOTER_SIZE;
+
+/// 空洞的个数，以及计时的分配次数。
+const HOLES: usize = 10_240;
+const ALLOCATIONS: usize = 1000;
+
+/// 每次分配消耗的 TSC 周期数。
+fn cycles_per_allocation() -> u64 {
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+
+    // 256 字节的空洞之间用已分配的块隔开，它们和请求的 600 字节块在同一个分箱里却都放不下
+    let hole = Layout::from_size_align(256 - TAGS, 8).unwrap();
+    let separator = Layout::from_size_align(48 - TAGS, 8).unwrap();
+    // 新堆从低地址依次分配，第 i 个空洞位于 first + i * 304
+    let first = unsafe { allocator.alloc(hole) };
+    assert!(!unsafe { allocator.alloc(separator) }.is_null());
+    for i in 1..HOLES {
+        let ptr = unsafe { allocator.alloc(hole) };
+        assert_eq!(ptr as usize, first as usize + i * (256 + 48));
+        assert!(!unsafe { allocator.alloc(separator) }.is_null());
+    }
+    for i in 0..HOLES {
+        unsafe { allocator.dealloc(first.add(i * (256 + 48)), hole) };
+    }
+    assert!(allocator.region_count() > HOLES);
+    let before = allocator.stats();
+
+    let layout = Layout::from_size_align(600, 8).unwrap();
+    let mut blocks = [ptr::null_mut(); ALLOCATIONS];
+    let start = unsafe { _rdtsc() };
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+    }
+    let cycles = unsafe { _rdtsc() } - start;
+
+    for &block in blocks.iter() {
+        assert!(!block.is_null());
+        unsafe { allocator.dealloc(block, layout) };
+    }
+    assert_eq!(allocator.stats(), before);
+    assert!(allocator.check_consistency().is_ok());
+    cycles / ALLOCATIONS as u64
+}
+
+#[test_case]
+fn allocation_with_many_free_regions() {
+    let index = if cfg!(feature = "heap-tree") {
+        "size-ordered tree"
+    } else {
+        "binned lists"
+    };
+    serial_println!(
+        "{}: {} cycles per allocation with {} free regions",
+        index,
+        cycles_per_allocation(),
+        HOLES
+    );
+}
+
+/// 简单的 xorshift 伪随机数生成器，测试不依赖外部 crate。
+#[cfg(feature = "heap-latency")]
+struct XorShift(u64);
+
+#[cfg(feature = "heap-latency")]
+impl XorShift {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+/// 在 `policy` 下随机分配和释放大小悬殊的块，把堆打碎，打印每次调用耗时的分布。
+#[cfg(feature = "heap-latency")]
+fn print_latency_histogram(policy: Policy) {
+    const SLOTS: usize = 256;
+    const OPERATIONS: usize = 20_000;
+
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+    allocator.lock().set_policy(policy);
+    allocator.lock().set_oom_hook(|_, _| {});
+
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut live: [Option<(*mut u8, Layout)>; SLOTS] = [None; SLOTS];
+    for _ in 0..OPERATIONS {
+        let slot = &mut live[rng.next() % SLOTS];
+        match slot.take() {
+            Some((ptr, layout)) => unsafe { allocator.dealloc(ptr, layout) },
+            None => {
+                // 大多数是小块，偶尔夹杂一个大块
+                let size = if rng.next().is_multiple_of(8) {
+                    4096 + rng.next() % (60 * 1024)
+                } else {
+                    16 + rng.next() % 256
+                };
+                let layout = Layout::from_size_align(size, 8).unwrap();
+                let ptr = unsafe { allocator.alloc(layout) };
+                if !ptr.is_null() {
+                    *slot = Some((ptr, layout));
+                }
+            }
+        }
+    }
+    for &(ptr, layout) in live.iter().flatten() {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    let report = allocator.latency_stats();
+    serial_println!("{:?} alloc: {}", policy, report.alloc);
+    serial_println!("{:?} dealloc: {}", policy, report.dealloc);
+}
+
+#[cfg(feature = "heap-latency")]
+#[test_case]
+fn latency_histogram_per_policy() {
+    serial_println!();
+    for policy in [Policy::FirstFit, Policy::BestFit, Policy::NextFit] {
+        print_latency_histogram(policy);
+    }
+}
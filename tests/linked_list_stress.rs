@@ -0,0 +1,173 @@
+// 在 tests/linked_list_stress.rs 中
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::allocator::{
+    linked_list::{LinkedListAllocator, Policy},
+    Locked,
+};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    panic::PanicInfo,
+    ptr,
+};
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    test_main();
+    loop {}
+}
+
+/// 测试用的独立堆。
+const ARENA_SIZE: usize = 1024 * 1024;
+#[repr(align(4096))]
+struct Arena([u8; ARENA_SIZE]);
+static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+/// 同时存活的分配数上限，记录放在栈上的定长数组里，不经过被测的堆。
+const SLOTS: usize = 256;
+const OPERATIONS: usize = 100_000;
+
+/// 简单的 xorshift 伪随机数生成器，测试不依赖外部 crate。
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+/// 一个存活的分配；`pattern` 不为零时整个分配都填充了这个字节。
+#[derive(Clone, Copy)]
+struct Live {
+    ptr: *mut u8,
+    layout: Layout,
+    pattern: u8,
+}
+
+fn random_layout(rng: &mut XorShift) -> Layout {
+    let size = 1 + rng.next() % (8 * 1024);
+    // 偶尔请求按页对齐，其余的对齐不超过 512 字节
+    let align = if rng.next().is_multiple_of(64) {
+        4096
+    } else {
+        1 << (rng.next() % 10)
+    };
+    Layout::from_size_align(size, align).unwrap()
+}
+
+/// 检查 `live` 的填充是否完好，被改写说明有两个分配重叠了。
+fn verify(live: &Live, seed: u64) {
+    if live.pattern == 0 {
+        return;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(live.ptr, live.layout.size()) };
+    if let Some(offset) = bytes.iter().position(|&byte| byte != live.pattern) {
+        panic!(
+            "seed {:#x}: allocation {:p} ({:?}) overwritten at offset {}",
+            seed, live.ptr, live.layout, offset
+        );
+    }
+}
+
+/// 用 `seed` 在 `policy` 下随机分配和释放 `OPERATIONS` 次，结束时释放所有分配。
+fn stress(policy: Policy, seed: u64) {
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(ptr::addr_of_mut!(ARENA.0) as usize, ARENA_SIZE)
+    };
+    allocator.lock().set_policy(policy);
+    // 堆满是正常情况，不需要打印
+    allocator.lock().set_oom_hook(|_, _| {});
+    let initial = allocator.stats();
+
+    let mut rng = XorShift(seed);
+    let mut live: [Option<Live>; SLOTS] = [None; SLOTS];
+    for op in 0..OPERATIONS {
+        let slot = &mut live[rng.next() % SLOTS];
+        match *slot {
+            Some(old) => {
+                verify(&old, seed);
+                unsafe { allocator.dealloc(old.ptr, old.layout) };
+                *slot = None;
+            }
+            None => {
+                let layout = random_layout(&mut rng);
+                let ptr = unsafe { allocator.alloc(layout) };
+                if ptr.is_null() {
+                    continue;
+                }
+                assert_eq!(
+                    ptr as usize % layout.align(),
+                    0,
+                    "seed {:#x}: {:p} is misaligned for {:?}",
+                    seed,
+                    ptr,
+                    layout
+                );
+                let pattern = if rng.next().is_multiple_of(4) {
+                    (op % 255 + 1) as u8
+                } else {
+                    0
+                };
+                if pattern != 0 {
+                    unsafe { ptr.write_bytes(pattern, layout.size()) };
+                }
+                *slot = Some(Live {
+                    ptr,
+                    layout,
+                    pattern,
+                });
+            }
+        }
+        if op.is_multiple_of(1000) {
+            if let Err(corruption) = allocator.check_consistency() {
+                panic!("seed {:#x}: after {} operations: {}", seed, op, corruption);
+            }
+            if let Err(mismatch) = allocator.check_accounting() {
+                panic!("seed {:#x}: after {} operations: {}", seed, op, mismatch);
+            }
+        }
+    }
+
+    for old in live.iter().flatten() {
+        verify(old, seed);
+        unsafe { allocator.dealloc(old.ptr, old.layout) };
+    }
+    assert!(
+        allocator.stats() == initial,
+        "seed {:#x}: heap did not return to its initial state",
+        seed
+    );
+}
+
+#[test_case]
+fn first_fit_survives_random_churn() {
+    stress(Policy::FirstFit, 0x2545_f491_4f6c_dd1d);
+}
+
+#[test_case]
+fn best_fit_survives_random_churn() {
+    stress(Policy::BestFit, 0x9e37_79b9_7f4a_7c15);
+}
+
+#[test_case]
+fn next_fit_survives_random_churn() {
+    stress(Policy::NextFit, 0xd1b5_4a32_d192_ed03);
+}